@@ -1,22 +1,30 @@
 use crate::{
-    device::Device,
+    device::{Device, QueueType},
+    error::Result,
     pipeline::PipelineStages,
+    render_pass::RenderingDesc,
     sync::{Fence, Semaphore},
 };
-use anyhow::Result;
 use ash::vk;
 
 pub struct Context {
     pub command_buffer: vk::CommandBuffer,
+    queue_type: QueueType,
+    device: Device,
 }
 
 impl Context {
     pub fn new(device: &Device) -> Result<Self> {
+        Self::with_queue(device, QueueType::Graphics)
+    }
+
+    // Commands recorded in the context are submitted to `queue_type`'s queue
+    pub fn with_queue(device: &Device, queue_type: QueueType) -> Result<Self> {
         // TODO: Allocate buffers in bulk, let Device manage it
 
         let command_buffer_allocate_info = vk::CommandBufferAllocateInfo::builder()
             .command_buffer_count(1)
-            .command_pool(device.command_pool)
+            .command_pool(device.queue(queue_type).command_pool)
             .level(vk::CommandBufferLevel::PRIMARY);
 
         let command_buffers = unsafe {
@@ -27,9 +35,36 @@ impl Context {
 
         Ok(Self {
             command_buffer: command_buffers[0],
+            queue_type,
+            device: device.clone(),
         })
     }
 
+    pub fn queue_type(&self) -> QueueType {
+        self.queue_type
+    }
+
+    // Begins dynamic rendering into `desc`'s attachments, moving them to attachment layouts first.
+    // Pipelines drawing inside have to be created with `GraphicsPipeline::with_rendering`.
+    pub fn begin_rendering(&self, device: &Device, desc: &RenderingDesc) -> Result<()> {
+        desc.begin(device, self)
+    }
+
+    // `desc` has to be the one rendering was begun with, swapchain images in it are moved to
+    // the present layout
+    pub fn end_rendering(&self, device: &Device, desc: &RenderingDesc) {
+        desc.end(device, self);
+    }
+
+    // Moves to the next subpass of the render pass begun on this context
+    pub fn next_subpass(&self, device: &Device) {
+        unsafe {
+            device
+                .device
+                .cmd_next_subpass(self.command_buffer, vk::SubpassContents::INLINE)
+        };
+    }
+
     pub fn begin(&self, device: &Device, fence: &Fence) -> Result<()> {
         fence.wait(device);
         fence.reset(device);
@@ -56,8 +91,8 @@ impl Context {
     pub fn end(
         &self,
         device: &Device,
-        wait_semaphores: &[Semaphore],
-        signal_semaphores: &[Semaphore],
+        wait_semaphores: &[&Semaphore],
+        signal_semaphores: &[&Semaphore],
         fence: &Fence,
         wait_mask: &[PipelineStages],
     ) -> Result<()> {
@@ -74,8 +109,8 @@ impl Context {
     pub fn record<F>(
         &self,
         device: &Device,
-        wait_semaphores: &[Semaphore],
-        signal_semaphores: &[Semaphore],
+        wait_semaphores: &[&Semaphore],
+        signal_semaphores: &[&Semaphore],
         fence: &Fence,
         wait_mask: &[PipelineStages],
         f: F,
@@ -95,3 +130,14 @@ impl Context {
         Ok(())
     }
 }
+
+impl Drop for Context {
+    fn drop(&mut self) {
+        unsafe {
+            self.device.device.free_command_buffers(
+                self.device.queue(self.queue_type).command_pool,
+                &[self.command_buffer],
+            )
+        };
+    }
+}
@@ -1,30 +1,87 @@
 use crate::{
     device::Device,
+    error::Result,
     resources::{ash_image::Image, buffer::Buffer, sampler::Sampler},
+    shader::reflection::{ReflectionError, ShaderReflection},
 };
-use anyhow::Result;
 use ash::vk;
+use thiserror::Error;
 
 const MAX_BINDLESS_RESOURCES: u32 = 16536;
+const MAX_INPUT_ATTACHMENTS: u32 = 64;
+const MAX_UNIFORM_BUFFERS: u32 = 1024;
+const MAX_TEXEL_BUFFERS: u32 = 1024;
+
+#[derive(Debug, Error)]
+pub enum DescriptorError {
+    #[error("descriptor set has {expected} bindings, but {found} descriptors were bound")]
+    BindingCountMismatch { expected: usize, found: usize },
+    #[error("binding {binding} of set {set} is {ty:?}, which descriptor pools don't provide")]
+    UnsupportedDescriptorType {
+        set: u32,
+        binding: u32,
+        ty: vk::DescriptorType,
+    },
+    #[error("binding {binding} of set {set} is a runtime array, which needs descriptor indexing")]
+    RuntimeArrayWithoutDescriptorIndexing { set: u32, binding: u32 },
+}
+
+// Every type a `DescriptorPool` has room for
+const POOL_DESCRIPTOR_SIZES: [vk::DescriptorPoolSize; 9] = [
+    vk::DescriptorPoolSize {
+        ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+        descriptor_count: MAX_BINDLESS_RESOURCES,
+    },
+    vk::DescriptorPoolSize {
+        ty: vk::DescriptorType::SAMPLED_IMAGE,
+        descriptor_count: MAX_BINDLESS_RESOURCES,
+    },
+    vk::DescriptorPoolSize {
+        ty: vk::DescriptorType::STORAGE_IMAGE,
+        descriptor_count: MAX_BINDLESS_RESOURCES,
+    },
+    vk::DescriptorPoolSize {
+        ty: vk::DescriptorType::SAMPLER,
+        descriptor_count: MAX_BINDLESS_RESOURCES,
+    },
+    vk::DescriptorPoolSize {
+        ty: vk::DescriptorType::STORAGE_BUFFER,
+        descriptor_count: MAX_BINDLESS_RESOURCES,
+    },
+    vk::DescriptorPoolSize {
+        ty: vk::DescriptorType::UNIFORM_BUFFER,
+        descriptor_count: MAX_UNIFORM_BUFFERS,
+    },
+    vk::DescriptorPoolSize {
+        ty: vk::DescriptorType::UNIFORM_TEXEL_BUFFER,
+        descriptor_count: MAX_TEXEL_BUFFERS,
+    },
+    vk::DescriptorPoolSize {
+        ty: vk::DescriptorType::STORAGE_TEXEL_BUFFER,
+        descriptor_count: MAX_TEXEL_BUFFERS,
+    },
+    vk::DescriptorPoolSize {
+        ty: vk::DescriptorType::INPUT_ATTACHMENT,
+        descriptor_count: MAX_INPUT_ATTACHMENTS,
+    },
+];
 
 pub struct DescriptorPool {
     pool: vk::DescriptorPool,
+    device: Device,
 }
 
 impl DescriptorPool {
     pub fn new(device: &Device) -> Result<Self> {
-        let descriptor_sizes = [
-            vk::DescriptorPoolSize {
-                ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
-                descriptor_count: MAX_BINDLESS_RESOURCES,
-            },
-            vk::DescriptorPoolSize {
-                ty: vk::DescriptorType::STORAGE_BUFFER,
-                descriptor_count: MAX_BINDLESS_RESOURCES,
-            },
-        ];
+        let descriptor_sizes = POOL_DESCRIPTOR_SIZES;
+        // Update after bind is only valid with descriptor indexing enabled
+        let flags = if device.enabled_features().descriptor_indexing() {
+            vk::DescriptorPoolCreateFlags::UPDATE_AFTER_BIND
+        } else {
+            vk::DescriptorPoolCreateFlags::empty()
+        };
         let descriptor_pool_info = vk::DescriptorPoolCreateInfo::builder()
-            .flags(vk::DescriptorPoolCreateFlags::UPDATE_AFTER_BIND_EXT)
+            .flags(flags)
             .max_sets(MAX_BINDLESS_RESOURCES * descriptor_sizes.len() as u32)
             .pool_sizes(&descriptor_sizes)
             .max_sets(descriptor_sizes.len() as u32);
@@ -35,11 +92,17 @@ impl DescriptorPool {
                 .create_descriptor_pool(&descriptor_pool_info, None)?
         };
 
-        Ok(Self { pool })
+        Ok(Self {
+            pool,
+            device: device.clone(),
+        })
     }
+}
 
-    pub unsafe fn clean(&self, device: &Device) {
-        device.device.destroy_descriptor_pool(self.pool, None);
+// Sets allocated from the pool are freed with it
+impl Drop for DescriptorPool {
+    fn drop(&mut self) {
+        unsafe { self.device.device.destroy_descriptor_pool(self.pool, None) };
     }
 }
 
@@ -62,17 +125,62 @@ impl DescriptorBufferInfo {
 pub fn new_descriptor_image_info(image: &Image, sampler: &Sampler) -> vk::DescriptorImageInfo {
     vk::DescriptorImageInfo {
         image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
-        image_view: image.view,
+        image_view: image.descriptor_view(),
         sampler: sampler.sampler,
     }
 }
 
+// For an image sampled with a separate `DescriptorType::Sampler`
+pub fn new_descriptor_sampled_image_info(image: &Image) -> vk::DescriptorImageInfo {
+    vk::DescriptorImageInfo {
+        image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        image_view: image.descriptor_view(),
+        sampler: vk::Sampler::null(),
+    }
+}
+
+// Storage images are read and written in the `GENERAL` layout
+pub fn new_descriptor_storage_image_info(image: &Image) -> vk::DescriptorImageInfo {
+    vk::DescriptorImageInfo {
+        image_layout: vk::ImageLayout::GENERAL,
+        image_view: image.descriptor_view(),
+        sampler: vk::Sampler::null(),
+    }
+}
+
+pub fn new_descriptor_sampler_info(sampler: &Sampler) -> vk::DescriptorImageInfo {
+    vk::DescriptorImageInfo {
+        sampler: sampler.sampler,
+        ..Default::default()
+    }
+}
+
+// For an image used as an input attachment of the current subpass
+pub fn new_descriptor_input_attachment_info(image: &Image) -> vk::DescriptorImageInfo {
+    let image_layout = if image.aspect_mask().contains(vk::ImageAspectFlags::DEPTH) {
+        vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL
+    } else {
+        vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL
+    };
+    vk::DescriptorImageInfo {
+        image_layout,
+        image_view: image.descriptor_view(),
+        sampler: vk::Sampler::null(),
+    }
+}
+
 // TODO: Theres' some duplicated state in here and `DescriptorInfo`, get rid of it later
 #[derive(Debug, Clone)]
 pub enum DescriptorType {
     StorageBuffer,
     UniformBuffer,
     CombinedImageSampler,
+    SampledImage,
+    StorageImage,
+    Sampler,
+    UniformTexelBuffer,
+    StorageTexelBuffer,
+    InputAttachment,
 }
 
 impl From<DescriptorType> for vk::DescriptorType {
@@ -81,6 +189,12 @@ impl From<DescriptorType> for vk::DescriptorType {
             DescriptorType::StorageBuffer => vk::DescriptorType::STORAGE_BUFFER,
             DescriptorType::UniformBuffer => vk::DescriptorType::UNIFORM_BUFFER,
             DescriptorType::CombinedImageSampler => vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            DescriptorType::SampledImage => vk::DescriptorType::SAMPLED_IMAGE,
+            DescriptorType::StorageImage => vk::DescriptorType::STORAGE_IMAGE,
+            DescriptorType::Sampler => vk::DescriptorType::SAMPLER,
+            DescriptorType::UniformTexelBuffer => vk::DescriptorType::UNIFORM_TEXEL_BUFFER,
+            DescriptorType::StorageTexelBuffer => vk::DescriptorType::STORAGE_TEXEL_BUFFER,
+            DescriptorType::InputAttachment => vk::DescriptorType::INPUT_ATTACHMENT,
         }
     }
 }
@@ -91,6 +205,12 @@ impl From<&DescriptorType> for vk::DescriptorType {
             DescriptorType::StorageBuffer => vk::DescriptorType::STORAGE_BUFFER,
             DescriptorType::UniformBuffer => vk::DescriptorType::UNIFORM_BUFFER,
             DescriptorType::CombinedImageSampler => vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            DescriptorType::SampledImage => vk::DescriptorType::SAMPLED_IMAGE,
+            DescriptorType::StorageImage => vk::DescriptorType::STORAGE_IMAGE,
+            DescriptorType::Sampler => vk::DescriptorType::SAMPLER,
+            DescriptorType::UniformTexelBuffer => vk::DescriptorType::UNIFORM_TEXEL_BUFFER,
+            DescriptorType::StorageTexelBuffer => vk::DescriptorType::STORAGE_TEXEL_BUFFER,
+            DescriptorType::InputAttachment => vk::DescriptorType::INPUT_ATTACHMENT,
         }
     }
 }
@@ -99,6 +219,12 @@ pub enum DescriptorInfo {
     StorageBuffer(DescriptorBufferInfo),
     UniformBuffer(DescriptorBufferInfo),
     CombinedImageSampler(Vec<vk::DescriptorImageInfo>),
+    SampledImage(Vec<vk::DescriptorImageInfo>),
+    StorageImage(Vec<vk::DescriptorImageInfo>),
+    Sampler(Vec<vk::DescriptorImageInfo>),
+    UniformTexelBuffer(Vec<vk::BufferView>),
+    StorageTexelBuffer(Vec<vk::BufferView>),
+    InputAttachment(Vec<vk::DescriptorImageInfo>),
 }
 
 impl From<DescriptorInfo> for vk::DescriptorType {
@@ -107,6 +233,12 @@ impl From<DescriptorInfo> for vk::DescriptorType {
             DescriptorInfo::StorageBuffer(_) => vk::DescriptorType::STORAGE_BUFFER,
             DescriptorInfo::UniformBuffer(_) => vk::DescriptorType::UNIFORM_BUFFER,
             DescriptorInfo::CombinedImageSampler(_) => vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            DescriptorInfo::SampledImage(_) => vk::DescriptorType::SAMPLED_IMAGE,
+            DescriptorInfo::StorageImage(_) => vk::DescriptorType::STORAGE_IMAGE,
+            DescriptorInfo::Sampler(_) => vk::DescriptorType::SAMPLER,
+            DescriptorInfo::UniformTexelBuffer(_) => vk::DescriptorType::UNIFORM_TEXEL_BUFFER,
+            DescriptorInfo::StorageTexelBuffer(_) => vk::DescriptorType::STORAGE_TEXEL_BUFFER,
+            DescriptorInfo::InputAttachment(_) => vk::DescriptorType::INPUT_ATTACHMENT,
         }
     }
 }
@@ -117,14 +249,22 @@ impl From<&DescriptorInfo> for vk::DescriptorType {
             DescriptorInfo::StorageBuffer(_) => vk::DescriptorType::STORAGE_BUFFER,
             DescriptorInfo::UniformBuffer(_) => vk::DescriptorType::UNIFORM_BUFFER,
             DescriptorInfo::CombinedImageSampler(_) => vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            DescriptorInfo::SampledImage(_) => vk::DescriptorType::SAMPLED_IMAGE,
+            DescriptorInfo::StorageImage(_) => vk::DescriptorType::STORAGE_IMAGE,
+            DescriptorInfo::Sampler(_) => vk::DescriptorType::SAMPLER,
+            DescriptorInfo::UniformTexelBuffer(_) => vk::DescriptorType::UNIFORM_TEXEL_BUFFER,
+            DescriptorInfo::StorageTexelBuffer(_) => vk::DescriptorType::STORAGE_TEXEL_BUFFER,
+            DescriptorInfo::InputAttachment(_) => vk::DescriptorType::INPUT_ATTACHMENT,
         }
     }
 }
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum ShaderStage {
     Vertex,
     Fragment,
+    Compute,
+    AllGraphics,
 }
 
 impl From<ShaderStage> for vk::ShaderStageFlags {
@@ -132,6 +272,28 @@ impl From<ShaderStage> for vk::ShaderStageFlags {
         match stage {
             ShaderStage::Vertex => vk::ShaderStageFlags::VERTEX,
             ShaderStage::Fragment => vk::ShaderStageFlags::FRAGMENT,
+            ShaderStage::Compute => vk::ShaderStageFlags::COMPUTE,
+            ShaderStage::AllGraphics => vk::ShaderStageFlags::ALL_GRAPHICS,
+        }
+    }
+}
+
+// Any combination of graphics stages is widened to `AllGraphics`. Masks mixing compute with
+// graphics stages, or with no stages at all, can't be represented
+impl TryFrom<vk::ShaderStageFlags> for ShaderStage {
+    type Error = ReflectionError;
+
+    fn try_from(flags: vk::ShaderStageFlags) -> Result<Self, Self::Error> {
+        if flags == vk::ShaderStageFlags::VERTEX {
+            Ok(ShaderStage::Vertex)
+        } else if flags == vk::ShaderStageFlags::FRAGMENT {
+            Ok(ShaderStage::Fragment)
+        } else if flags == vk::ShaderStageFlags::COMPUTE {
+            Ok(ShaderStage::Compute)
+        } else if !flags.is_empty() && vk::ShaderStageFlags::ALL_GRAPHICS.contains(flags) {
+            Ok(ShaderStage::AllGraphics)
+        } else {
+            Err(ReflectionError::UnsupportedShaderStages(flags))
         }
     }
 }
@@ -156,7 +318,9 @@ impl BindingDesc {
 pub struct DescriptorSet {
     pub layout: vk::DescriptorSetLayout,
     pub descriptor_set: vk::DescriptorSet,
+    layout_bindings: Vec<vk::DescriptorSetLayoutBinding>,
     write_descriptor_sets: Vec<vk::WriteDescriptorSet>,
+    device: Device,
 }
 
 impl DescriptorSet {
@@ -177,18 +341,110 @@ impl DescriptorSet {
                     .build()
             })
             .collect::<Vec<_>>();
+        Self::from_layout_bindings(device, descriptor_pool, bindings, vec![])
+    }
 
-        let descriptor_set_layout_ci = vk::DescriptorSetLayoutCreateInfo::builder()
-            .bindings(&bindings)
-            .build();
+    // Runtime-sized arrays in the shader need descriptor indexing. They get
+    // `MAX_BINDLESS_RESOURCES` partially bound descriptors, and the last binding of the set has a
+    // variable count
+    pub fn from_reflection(
+        device: &Device,
+        descriptor_pool: &DescriptorPool,
+        reflection: &ShaderReflection,
+        set: u32,
+    ) -> Result<Self> {
+        let reflected = reflection.bindings_for_set(set).collect::<Vec<_>>();
+        let descriptor_indexing = device.enabled_features().descriptor_indexing();
+        for binding in &reflected {
+            if !POOL_DESCRIPTOR_SIZES
+                .iter()
+                .any(|size| size.ty == binding.ty)
+            {
+                return Err(DescriptorError::UnsupportedDescriptorType {
+                    set,
+                    binding: binding.binding,
+                    ty: binding.ty,
+                }
+                .into());
+            }
+            if binding.count == 0 && !descriptor_indexing {
+                return Err(DescriptorError::RuntimeArrayWithoutDescriptorIndexing {
+                    set,
+                    binding: binding.binding,
+                }
+                .into());
+            }
+        }
+        let binding_flags = reflected
+            .iter()
+            .enumerate()
+            .map(|(index, binding)| {
+                if binding.count != 0 {
+                    vk::DescriptorBindingFlags::empty()
+                } else if index == reflected.len() - 1 {
+                    vk::DescriptorBindingFlags::PARTIALLY_BOUND
+                        | vk::DescriptorBindingFlags::VARIABLE_DESCRIPTOR_COUNT
+                } else {
+                    vk::DescriptorBindingFlags::PARTIALLY_BOUND
+                }
+            })
+            .collect::<Vec<_>>();
+        let bindings = reflected
+            .iter()
+            .map(|binding| {
+                let count = if binding.count == 0 {
+                    MAX_BINDLESS_RESOURCES
+                } else {
+                    binding.count
+                };
+                vk::DescriptorSetLayoutBinding::builder()
+                    .binding(binding.binding)
+                    .descriptor_type(binding.ty)
+                    .descriptor_count(count)
+                    .stage_flags(binding.stage)
+                    .build()
+            })
+            .collect::<Vec<_>>();
+        Self::from_layout_bindings(device, descriptor_pool, bindings, binding_flags)
+    }
+
+    // `binding_flags` is either empty or has one entry per binding
+    fn from_layout_bindings(
+        device: &Device,
+        descriptor_pool: &DescriptorPool,
+        bindings: Vec<vk::DescriptorSetLayoutBinding>,
+        binding_flags: Vec<vk::DescriptorBindingFlags>,
+    ) -> Result<Self> {
+        let mut binding_flags_info =
+            vk::DescriptorSetLayoutBindingFlagsCreateInfo::builder().binding_flags(&binding_flags);
+        let mut descriptor_set_layout_ci =
+            vk::DescriptorSetLayoutCreateInfo::builder().bindings(&bindings);
+        if binding_flags.iter().any(|flags| !flags.is_empty()) {
+            descriptor_set_layout_ci = descriptor_set_layout_ci.push_next(&mut binding_flags_info);
+        }
         let layout = unsafe {
             device
                 .device
                 .create_descriptor_set_layout(&descriptor_set_layout_ci, None)?
         };
-        let descriptor_alloc_info = vk::DescriptorSetAllocateInfo::builder()
+
+        // Allocates the whole variable count binding
+        let variable_counts = binding_flags
+            .last()
+            .filter(|flags| flags.contains(vk::DescriptorBindingFlags::VARIABLE_DESCRIPTOR_COUNT))
+            .and(bindings.last())
+            .map(|binding| binding.descriptor_count)
+            .into_iter()
+            .collect::<Vec<_>>();
+        let mut variable_count_info =
+            vk::DescriptorSetVariableDescriptorCountAllocateInfo::builder()
+                .descriptor_counts(&variable_counts);
+        let mut descriptor_alloc_info = vk::DescriptorSetAllocateInfo::builder()
             .descriptor_pool(descriptor_pool.pool)
             .set_layouts(std::slice::from_ref(&layout));
+        if !variable_counts.is_empty() {
+            descriptor_alloc_info = descriptor_alloc_info.push_next(&mut variable_count_info);
+        }
         let descriptor_set = unsafe {
             device
                 .device
@@ -198,18 +454,34 @@ impl DescriptorSet {
         Ok(Self {
             layout,
             descriptor_set,
+            layout_bindings: bindings,
             write_descriptor_sets: Default::default(),
+            device: device.clone(),
         })
     }
 
-    pub fn bind(&mut self, infos: &[DescriptorInfo]) {
+    pub fn layout_binding(&self, binding: u32) -> Option<&vk::DescriptorSetLayoutBinding> {
+        self.layout_bindings
+            .iter()
+            .find(|layout_binding| layout_binding.binding == binding)
+    }
+
+    // Infos are matched to the set's bindings in order, which may not be contiguous for sets
+    // created from reflection. Every binding needs an info
+    pub fn bind(&mut self, infos: &[DescriptorInfo]) -> Result<(), DescriptorError> {
+        if infos.len() != self.layout_bindings.len() {
+            return Err(DescriptorError::BindingCountMismatch {
+                expected: self.layout_bindings.len(),
+                found: infos.len(),
+            });
+        }
         self.write_descriptor_sets = infos
             .iter()
-            .enumerate()
-            .map(|(idx, info)| {
+            .zip(self.layout_bindings.iter())
+            .map(|(info, layout_binding)| {
                 let write = vk::WriteDescriptorSet::builder()
                     .dst_set(self.descriptor_set)
-                    .dst_binding(idx as u32)
+                    .dst_binding(layout_binding.binding)
                     .descriptor_type(info.into());
                 match info {
                     DescriptorInfo::StorageBuffer(info) => {
@@ -218,11 +490,18 @@ impl DescriptorSet {
                     DescriptorInfo::UniformBuffer(info) => {
                         write.buffer_info(std::slice::from_ref(&info.info))
                     }
-                    DescriptorInfo::CombinedImageSampler(infos) => write.image_info(infos),
+                    DescriptorInfo::CombinedImageSampler(infos)
+                    | DescriptorInfo::SampledImage(infos)
+                    | DescriptorInfo::StorageImage(infos)
+                    | DescriptorInfo::Sampler(infos)
+                    | DescriptorInfo::InputAttachment(infos) => write.image_info(infos),
+                    DescriptorInfo::UniformTexelBuffer(views)
+                    | DescriptorInfo::StorageTexelBuffer(views) => write.texel_buffer_view(views),
                 }
                 .build()
             })
             .collect::<Vec<_>>();
+        Ok(())
     }
 
     pub fn update(&self, device: &Device) {
@@ -233,10 +512,38 @@ impl DescriptorSet {
                 .update_descriptor_sets(&self.write_descriptor_sets, &[])
         };
     }
+}
+
+impl Drop for DescriptorSet {
+    fn drop(&mut self) {
+        unsafe {
+            self.device
+                .device
+                .destroy_descriptor_set_layout(self.layout, None)
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    pub unsafe fn clean(&self, device: &Device) {
-        device
-            .device
-            .destroy_descriptor_set_layout(self.layout, None);
+    #[test]
+    fn pool_provides_every_descriptor_type() {
+        let types = [
+            DescriptorType::StorageBuffer,
+            DescriptorType::UniformBuffer,
+            DescriptorType::CombinedImageSampler,
+            DescriptorType::SampledImage,
+            DescriptorType::StorageImage,
+            DescriptorType::Sampler,
+            DescriptorType::UniformTexelBuffer,
+            DescriptorType::StorageTexelBuffer,
+            DescriptorType::InputAttachment,
+        ];
+        for ty in types {
+            let ty = vk::DescriptorType::from(ty);
+            assert!(POOL_DESCRIPTOR_SIZES.iter().any(|size| size.ty == ty));
+        }
     }
 }
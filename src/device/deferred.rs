@@ -0,0 +1,224 @@
+use crate::mem::{Allocation, Allocator};
+use ash::vk;
+
+// Raw handles of a resource whose destruction has been deferred. They no longer hold a `Device`,
+// so the queue living inside the device does not keep it alive.
+pub enum RetiredResource {
+    Buffer {
+        buffer: vk::Buffer,
+        allocation: Allocation,
+    },
+    Image {
+        image: vk::Image,
+        view: vk::ImageView,
+        depth_view: Option<vk::ImageView>,
+        // `None` for images aliasing memory they don't own
+        allocation: Option<Allocation>,
+    },
+    Sampler(vk::Sampler),
+    // Memory not owned by any of the resources, like the memory aliased by render graph images
+    Memory(Allocation),
+    // Destroyed in order, so resources can come before the memory they alias
+    Group(Vec<RetiredResource>),
+}
+
+impl RetiredResource {
+    pub(crate) unsafe fn destroy(self, device: &ash::Device, allocator: &mut Allocator) {
+        match self {
+            RetiredResource::Buffer { buffer, allocation } => {
+                device.destroy_buffer(buffer, None);
+                allocator.free(device, &allocation);
+            }
+            RetiredResource::Image {
+                image,
+                view,
+                depth_view,
+                allocation,
+            } => {
+                device.destroy_image_view(view, None);
+                if let Some(depth_view) = depth_view {
+                    device.destroy_image_view(depth_view, None);
+                }
+                device.destroy_image(image, None);
+                if let Some(allocation) = allocation {
+                    allocator.free(device, &allocation);
+                }
+            }
+            RetiredResource::Sampler(sampler) => device.destroy_sampler(sampler, None),
+            RetiredResource::Memory(allocation) => allocator.free(device, &allocation),
+            RetiredResource::Group(resources) => {
+                for resource in resources {
+                    resource.destroy(device, allocator);
+                }
+            }
+        }
+    }
+}
+
+// Resources that can be handed to `Device::retire`. Implementations take the handles out of the
+// resource without running its `Drop`.
+pub trait Retire {
+    #[doc(hidden)]
+    fn into_retired(self) -> RetiredResource;
+}
+
+// Moves every field out of a resource without running its `Drop`, for `Retire` implementations.
+// The pattern has to name all the fields, so adding one to the resource fails to compile here
+// instead of leaking it. Fields that aren't used are dropped at the end of the scope.
+//
+//     take_fields!(let Sampler { sampler, device } = self);
+macro_rules! take_fields {
+    (let $ty:ident { $($field:ident),* $(,)? } = $value:expr) => {
+        let value = std::mem::ManuallyDrop::new($value);
+        let $ty { $($field: _),* } = &*value;
+        // Each field is read exactly once, and `value` is never dropped
+        $(
+            #[allow(unused_variables)]
+            let $field = unsafe { std::ptr::read(&value.$field) };
+        )*
+    };
+}
+pub(crate) use take_fields;
+
+// What the last submission that used a resource signals on completion
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) enum RetireAfter {
+    Fence(vk::Fence),
+    Timeline(vk::Semaphore, u64),
+}
+
+#[derive(Default)]
+pub(crate) struct DeferredQueue {
+    pending: Vec<(RetireAfter, RetiredResource)>,
+}
+
+impl DeferredQueue {
+    pub(crate) fn push(&mut self, after: RetireAfter, resource: RetiredResource) {
+        self.pending.push((after, resource));
+    }
+
+    pub(crate) fn conditions(&self) -> Vec<RetireAfter> {
+        let mut conditions = Vec::new();
+        for (after, _) in &self.pending {
+            if !conditions.contains(after) {
+                conditions.push(*after);
+            }
+        }
+        conditions
+    }
+
+    // Removes every resource whose condition satisfies `is_complete`
+    pub(crate) fn take_completed<F>(&mut self, mut is_complete: F) -> Vec<RetiredResource>
+    where
+        F: FnMut(RetireAfter) -> bool,
+    {
+        let (completed, pending): (Vec<_>, Vec<_>) = self
+            .pending
+            .drain(..)
+            .partition(|(after, _)| is_complete(*after));
+        self.pending = pending;
+        completed
+            .into_iter()
+            .map(|(_, resource)| resource)
+            .collect()
+    }
+
+    pub(crate) fn take_all(&mut self) -> Vec<RetiredResource> {
+        self.take_completed(|_| true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ash::vk::Handle;
+    use std::{cell::Cell, rc::Rc};
+
+    fn sampler(raw: u64) -> RetiredResource {
+        RetiredResource::Sampler(vk::Sampler::from_raw(raw))
+    }
+
+    fn samplers(resources: &[RetiredResource]) -> Vec<u64> {
+        resources
+            .iter()
+            .map(|resource| match resource {
+                RetiredResource::Sampler(sampler) => sampler.as_raw(),
+                _ => unreachable!(),
+            })
+            .collect()
+    }
+
+    fn fence(raw: u64) -> RetireAfter {
+        RetireAfter::Fence(vk::Fence::from_raw(raw))
+    }
+
+    #[test]
+    fn conditions_are_deduplicated() {
+        let timeline = vk::Semaphore::from_raw(3);
+        let mut queue = DeferredQueue::default();
+        assert!(queue.conditions().is_empty());
+        queue.push(fence(1), sampler(10));
+        queue.push(RetireAfter::Timeline(timeline, 5), sampler(11));
+        queue.push(fence(1), sampler(12));
+        queue.push(RetireAfter::Timeline(timeline, 6), sampler(13));
+        assert_eq!(
+            queue.conditions(),
+            [
+                fence(1),
+                RetireAfter::Timeline(timeline, 5),
+                RetireAfter::Timeline(timeline, 6)
+            ]
+        );
+    }
+
+    #[test]
+    fn take_completed_keeps_pending() {
+        let mut queue = DeferredQueue::default();
+        queue.push(fence(1), sampler(10));
+        queue.push(fence(2), sampler(11));
+        queue.push(fence(1), sampler(12));
+
+        let completed = queue.take_completed(|after| after == fence(1));
+        assert_eq!(samplers(&completed), [10, 12]);
+        assert_eq!(queue.conditions(), [fence(2)]);
+
+        // Nothing completes twice
+        assert!(queue.take_completed(|after| after == fence(1)).is_empty());
+        assert_eq!(samplers(&queue.take_all()), [11]);
+        assert!(queue.conditions().is_empty());
+    }
+
+    struct Counted(Rc<Cell<u32>>);
+
+    impl Drop for Counted {
+        fn drop(&mut self) {
+            self.0.set(self.0.get() + 1);
+        }
+    }
+
+    struct Resource {
+        handle: u64,
+        field: Counted,
+    }
+
+    impl Drop for Resource {
+        fn drop(&mut self) {
+            panic!("`take_fields` ran the resource's `Drop`");
+        }
+    }
+
+    #[test]
+    fn take_fields_drops_unused_fields() {
+        let drops = Rc::new(Cell::new(0));
+        let resource = Resource {
+            handle: 7,
+            field: Counted(drops.clone()),
+        };
+        {
+            take_fields!(let Resource { handle, field } = resource);
+            assert_eq!(handle, 7);
+            assert_eq!(drops.get(), 0);
+        }
+        assert_eq!(drops.get(), 1);
+    }
+}
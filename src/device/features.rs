@@ -0,0 +1,588 @@
+use crate::{entry::Entry, error::Result};
+use ash::{extensions::khr, vk};
+use std::ffi::{CStr, CString};
+
+// Vulkan feature structs are a header followed by nothing but `vk::Bool32`s
+trait FeatureStruct {
+    const NAMES: &'static [&'static str];
+    const OFFSET: usize;
+}
+
+impl FeatureStruct for vk::PhysicalDeviceFeatures {
+    const NAMES: &'static [&'static str] = &[
+        "robust_buffer_access",
+        "full_draw_index_uint32",
+        "image_cube_array",
+        "independent_blend",
+        "geometry_shader",
+        "tessellation_shader",
+        "sample_rate_shading",
+        "dual_src_blend",
+        "logic_op",
+        "multi_draw_indirect",
+        "draw_indirect_first_instance",
+        "depth_clamp",
+        "depth_bias_clamp",
+        "fill_mode_non_solid",
+        "depth_bounds",
+        "wide_lines",
+        "large_points",
+        "alpha_to_one",
+        "multi_viewport",
+        "sampler_anisotropy",
+        "texture_compression_etc2",
+        "texture_compression_astc_ldr",
+        "texture_compression_bc",
+        "occlusion_query_precise",
+        "pipeline_statistics_query",
+        "vertex_pipeline_stores_and_atomics",
+        "fragment_stores_and_atomics",
+        "shader_tessellation_and_geometry_point_size",
+        "shader_image_gather_extended",
+        "shader_storage_image_extended_formats",
+        "shader_storage_image_multisample",
+        "shader_storage_image_read_without_format",
+        "shader_storage_image_write_without_format",
+        "shader_uniform_buffer_array_dynamic_indexing",
+        "shader_sampled_image_array_dynamic_indexing",
+        "shader_storage_buffer_array_dynamic_indexing",
+        "shader_storage_image_array_dynamic_indexing",
+        "shader_clip_distance",
+        "shader_cull_distance",
+        "shader_float64",
+        "shader_int64",
+        "shader_int16",
+        "shader_resource_residency",
+        "shader_resource_min_lod",
+        "sparse_binding",
+        "sparse_residency_buffer",
+        "sparse_residency_image2_d",
+        "sparse_residency_image3_d",
+        "sparse_residency2_samples",
+        "sparse_residency4_samples",
+        "sparse_residency8_samples",
+        "sparse_residency16_samples",
+        "sparse_residency_aliased",
+        "variable_multisample_rate",
+        "inherited_queries",
+    ];
+    const OFFSET: usize = 0;
+}
+
+impl FeatureStruct for vk::PhysicalDeviceVulkan11Features {
+    const NAMES: &'static [&'static str] = &[
+        "storage_buffer16_bit_access",
+        "uniform_and_storage_buffer16_bit_access",
+        "storage_push_constant16",
+        "storage_input_output16",
+        "multiview",
+        "multiview_geometry_shader",
+        "multiview_tessellation_shader",
+        "variable_pointers_storage_buffer",
+        "variable_pointers",
+        "protected_memory",
+        "sampler_ycbcr_conversion",
+        "shader_draw_parameters",
+    ];
+    const OFFSET: usize = std::mem::offset_of!(
+        vk::PhysicalDeviceVulkan11Features,
+        storage_buffer16_bit_access
+    );
+}
+
+impl FeatureStruct for vk::PhysicalDeviceVulkan12Features {
+    const NAMES: &'static [&'static str] = &[
+        "sampler_mirror_clamp_to_edge",
+        "draw_indirect_count",
+        "storage_buffer8_bit_access",
+        "uniform_and_storage_buffer8_bit_access",
+        "storage_push_constant8",
+        "shader_buffer_int64_atomics",
+        "shader_shared_int64_atomics",
+        "shader_float16",
+        "shader_int8",
+        "descriptor_indexing",
+        "shader_input_attachment_array_dynamic_indexing",
+        "shader_uniform_texel_buffer_array_dynamic_indexing",
+        "shader_storage_texel_buffer_array_dynamic_indexing",
+        "shader_uniform_buffer_array_non_uniform_indexing",
+        "shader_sampled_image_array_non_uniform_indexing",
+        "shader_storage_buffer_array_non_uniform_indexing",
+        "shader_storage_image_array_non_uniform_indexing",
+        "shader_input_attachment_array_non_uniform_indexing",
+        "shader_uniform_texel_buffer_array_non_uniform_indexing",
+        "shader_storage_texel_buffer_array_non_uniform_indexing",
+        "descriptor_binding_uniform_buffer_update_after_bind",
+        "descriptor_binding_sampled_image_update_after_bind",
+        "descriptor_binding_storage_image_update_after_bind",
+        "descriptor_binding_storage_buffer_update_after_bind",
+        "descriptor_binding_uniform_texel_buffer_update_after_bind",
+        "descriptor_binding_storage_texel_buffer_update_after_bind",
+        "descriptor_binding_update_unused_while_pending",
+        "descriptor_binding_partially_bound",
+        "descriptor_binding_variable_descriptor_count",
+        "runtime_descriptor_array",
+        "sampler_filter_minmax",
+        "scalar_block_layout",
+        "imageless_framebuffer",
+        "uniform_buffer_standard_layout",
+        "shader_subgroup_extended_types",
+        "separate_depth_stencil_layouts",
+        "host_query_reset",
+        "timeline_semaphore",
+        "buffer_device_address",
+        "buffer_device_address_capture_replay",
+        "buffer_device_address_multi_device",
+        "vulkan_memory_model",
+        "vulkan_memory_model_device_scope",
+        "vulkan_memory_model_availability_visibility_chains",
+        "shader_output_viewport_index",
+        "shader_output_layer",
+        "subgroup_broadcast_dynamic_id",
+    ];
+    const OFFSET: usize = std::mem::offset_of!(
+        vk::PhysicalDeviceVulkan12Features,
+        sampler_mirror_clamp_to_edge
+    );
+}
+
+impl FeatureStruct for vk::PhysicalDeviceVulkan13Features {
+    const NAMES: &'static [&'static str] = &[
+        "robust_image_access",
+        "inline_uniform_block",
+        "descriptor_binding_inline_uniform_block_update_after_bind",
+        "pipeline_creation_cache_control",
+        "private_data",
+        "shader_demote_to_helper_invocation",
+        "shader_terminate_invocation",
+        "subgroup_size_control",
+        "compute_full_subgroups",
+        "synchronization2",
+        "texture_compression_astc_hdr",
+        "shader_zero_initialize_workgroup_memory",
+        "dynamic_rendering",
+        "shader_integer_dot_product",
+        "maintenance4",
+    ];
+    const OFFSET: usize =
+        std::mem::offset_of!(vk::PhysicalDeviceVulkan13Features, robust_image_access);
+}
+
+// `NAMES` has to cover every field from `OFFSET` up to and including the last one
+macro_rules! assert_covers_last_field {
+    ($ty:ty, $last:ident) => {
+        assert!(
+            std::mem::offset_of!($ty, $last) + 4
+                == <$ty as FeatureStruct>::OFFSET + <$ty as FeatureStruct>::NAMES.len() * 4
+        );
+    };
+}
+
+const _: () = {
+    assert_covers_last_field!(vk::PhysicalDeviceFeatures, inherited_queries);
+    assert_covers_last_field!(vk::PhysicalDeviceVulkan11Features, shader_draw_parameters);
+    assert_covers_last_field!(
+        vk::PhysicalDeviceVulkan12Features,
+        subgroup_broadcast_dynamic_id
+    );
+    assert_covers_last_field!(vk::PhysicalDeviceVulkan13Features, maintenance4);
+};
+
+fn bits<T: FeatureStruct>(features: &T) -> &[vk::Bool32] {
+    unsafe {
+        std::slice::from_raw_parts(
+            (features as *const T as *const u8).add(T::OFFSET) as *const vk::Bool32,
+            T::NAMES.len(),
+        )
+    }
+}
+
+fn bits_mut<T: FeatureStruct>(features: &mut T) -> &mut [vk::Bool32] {
+    unsafe {
+        std::slice::from_raw_parts_mut(
+            (features as *mut T as *mut u8).add(T::OFFSET) as *mut vk::Bool32,
+            T::NAMES.len(),
+        )
+    }
+}
+
+fn merge<T: FeatureStruct>(features: &mut T, other: &T) {
+    for (bit, other) in bits_mut(features).iter_mut().zip(bits(other)) {
+        *bit |= *other;
+    }
+}
+
+fn is_empty<T: FeatureStruct>(features: &T) -> bool {
+    bits(features).iter().all(|bit| *bit == vk::FALSE)
+}
+
+fn missing<T: FeatureStruct>(required: &T, supported: &T, missing: &mut Vec<&'static str>) {
+    missing.extend(
+        bits(required)
+            .iter()
+            .zip(bits(supported))
+            .zip(T::NAMES)
+            .filter(|((required, supported), _)| **required != 0 && **supported == 0)
+            .map(|(_, name)| *name),
+    );
+}
+
+// Features and extensions to enable on a device. Also used for the set a physical device
+// supports, and the set a `Device` was created with. The 1.1 and 1.2 structs need Vulkan 1.2,
+// the 1.3 struct Vulkan 1.3, on both the instance and the device.
+#[derive(Debug, Clone, Default)]
+pub struct DeviceFeatures {
+    vulkan_10: vk::PhysicalDeviceFeatures,
+    vulkan_11: vk::PhysicalDeviceVulkan11Features,
+    vulkan_12: vk::PhysicalDeviceVulkan12Features,
+    vulkan_13: vk::PhysicalDeviceVulkan13Features,
+    extensions: Vec<CString>,
+}
+
+// `p_next` is always null outside of `query` and `Device` creation
+unsafe impl Send for DeviceFeatures {}
+unsafe impl Sync for DeviceFeatures {}
+
+impl DeviceFeatures {
+    pub fn with_vulkan_10(mut self, features: vk::PhysicalDeviceFeatures) -> Self {
+        merge(&mut self.vulkan_10, &features);
+        self
+    }
+
+    pub fn with_vulkan_11(mut self, features: vk::PhysicalDeviceVulkan11Features) -> Self {
+        merge(&mut self.vulkan_11, &features);
+        self
+    }
+
+    pub fn with_vulkan_12(mut self, features: vk::PhysicalDeviceVulkan12Features) -> Self {
+        merge(&mut self.vulkan_12, &features);
+        self
+    }
+
+    pub fn with_vulkan_13(mut self, features: vk::PhysicalDeviceVulkan13Features) -> Self {
+        merge(&mut self.vulkan_13, &features);
+        self
+    }
+
+    pub fn with_extension(mut self, extension: &CStr) -> Self {
+        if !self.has_extension(extension) {
+            self.extensions.push(extension.to_owned());
+        }
+        self
+    }
+
+    // Everything `DescriptorPool` needs for bindless storage buffers and sampled images
+    pub fn with_descriptor_indexing(mut self) -> Self {
+        let features = &mut self.vulkan_12;
+        features.descriptor_indexing = vk::TRUE;
+        features.runtime_descriptor_array = vk::TRUE;
+        features.descriptor_binding_partially_bound = vk::TRUE;
+        features.descriptor_binding_variable_descriptor_count = vk::TRUE;
+        features.descriptor_binding_update_unused_while_pending = vk::TRUE;
+        features.descriptor_binding_sampled_image_update_after_bind = vk::TRUE;
+        features.descriptor_binding_storage_buffer_update_after_bind = vk::TRUE;
+        features.shader_sampled_image_array_non_uniform_indexing = vk::TRUE;
+        features.shader_storage_buffer_array_non_uniform_indexing = vk::TRUE;
+        self
+    }
+
+    // Core in Vulkan 1.2, enabled through `VK_KHR_timeline_semaphore` on Vulkan 1.1 devices
+    pub fn with_timeline_semaphores(mut self) -> Self {
+        self.vulkan_12.timeline_semaphore = vk::TRUE;
+        self
+    }
+
+    pub fn with_buffer_device_address(mut self) -> Self {
+        self.vulkan_12.buffer_device_address = vk::TRUE;
+        self
+    }
+
+    // Core in Vulkan 1.3, enabled through `VK_KHR_dynamic_rendering` on Vulkan 1.2 devices
+    pub fn with_dynamic_rendering(mut self) -> Self {
+        self.vulkan_13.dynamic_rendering = vk::TRUE;
+        self
+    }
+
+    pub fn with_synchronization2(mut self) -> Self {
+        self.vulkan_13.synchronization2 = vk::TRUE;
+        self
+    }
+
+    pub fn vulkan_10(&self) -> &vk::PhysicalDeviceFeatures {
+        &self.vulkan_10
+    }
+
+    pub fn vulkan_11(&self) -> &vk::PhysicalDeviceVulkan11Features {
+        &self.vulkan_11
+    }
+
+    pub fn vulkan_12(&self) -> &vk::PhysicalDeviceVulkan12Features {
+        &self.vulkan_12
+    }
+
+    pub fn vulkan_13(&self) -> &vk::PhysicalDeviceVulkan13Features {
+        &self.vulkan_13
+    }
+
+    pub fn extensions(&self) -> &[CString] {
+        &self.extensions
+    }
+
+    pub fn has_extension(&self, extension: &CStr) -> bool {
+        self.extensions
+            .iter()
+            .any(|enabled| enabled.as_c_str() == extension)
+    }
+
+    pub fn descriptor_indexing(&self) -> bool {
+        self.vulkan_12
+            .descriptor_binding_sampled_image_update_after_bind
+            == vk::TRUE
+            && self
+                .vulkan_12
+                .descriptor_binding_storage_buffer_update_after_bind
+                == vk::TRUE
+    }
+
+    pub fn timeline_semaphores(&self) -> bool {
+        self.vulkan_12.timeline_semaphore == vk::TRUE
+    }
+
+    pub fn buffer_device_address(&self) -> bool {
+        self.vulkan_12.buffer_device_address == vk::TRUE
+    }
+
+    pub fn dynamic_rendering(&self) -> bool {
+        self.vulkan_13.dynamic_rendering == vk::TRUE
+    }
+
+    pub fn synchronization2(&self) -> bool {
+        self.vulkan_13.synchronization2 == vk::TRUE
+    }
+
+    // Names of the features in `self` that `supported` lacks
+    pub fn missing_features(&self, supported: &DeviceFeatures) -> Vec<&'static str> {
+        let mut names = Vec::new();
+        missing(&self.vulkan_10, &supported.vulkan_10, &mut names);
+        missing(&self.vulkan_11, &supported.vulkan_11, &mut names);
+        missing(&self.vulkan_12, &supported.vulkan_12, &mut names);
+        missing(&self.vulkan_13, &supported.vulkan_13, &mut names);
+        names
+    }
+
+    pub fn missing_extensions(&self, supported: &DeviceFeatures) -> Vec<CString> {
+        self.extensions
+            .iter()
+            .filter(|extension| !supported.has_extension(extension))
+            .cloned()
+            .collect()
+    }
+
+    // What `p_device` supports. Structs the instance or device version doesn't cover are left
+    // empty.
+    pub(crate) fn query(
+        entry: &Entry,
+        p_device: vk::PhysicalDevice,
+        properties: &vk::PhysicalDeviceProperties,
+    ) -> Result<Self> {
+        let instance = &entry.instance;
+        let api_version = entry.api_version.min(properties.api_version);
+        let mut supported = Self::default();
+
+        for properties in unsafe { instance.enumerate_device_extension_properties(p_device)? } {
+            let extension = unsafe { CStr::from_ptr(properties.extension_name.as_ptr()) };
+            supported = supported.with_extension(extension);
+        }
+
+        if api_version >= vk::API_VERSION_1_2 {
+            let mut vulkan_11 = vk::PhysicalDeviceVulkan11Features::default();
+            let mut vulkan_12 = vk::PhysicalDeviceVulkan12Features::default();
+            let mut vulkan_13 = vk::PhysicalDeviceVulkan13Features::default();
+            let mut dynamic_rendering = vk::PhysicalDeviceDynamicRenderingFeatures::default();
+            let mut features = vk::PhysicalDeviceFeatures2::builder()
+                .push_next(&mut vulkan_11)
+                .push_next(&mut vulkan_12);
+            if api_version >= vk::API_VERSION_1_3 {
+                features = features.push_next(&mut vulkan_13);
+            } else if supported.has_extension(khr::DynamicRendering::name()) {
+                features = features.push_next(&mut dynamic_rendering);
+            }
+            let mut features = features.build();
+            unsafe { instance.get_physical_device_features2(p_device, &mut features) };
+            vulkan_13.dynamic_rendering |= dynamic_rendering.dynamic_rendering;
+            supported.vulkan_10 = features.features;
+            supported = supported
+                .with_vulkan_11(vulkan_11)
+                .with_vulkan_12(vulkan_12)
+                .with_vulkan_13(vulkan_13);
+        } else if api_version >= vk::API_VERSION_1_1 {
+            let mut timeline_semaphore = vk::PhysicalDeviceTimelineSemaphoreFeatures::default();
+            let mut features = vk::PhysicalDeviceFeatures2::builder();
+            if supported.has_extension(khr::TimelineSemaphore::name()) {
+                features = features.push_next(&mut timeline_semaphore);
+            }
+            let mut features = features.build();
+            unsafe { instance.get_physical_device_features2(p_device, &mut features) };
+            supported.vulkan_10 = features.features;
+            supported.vulkan_12.timeline_semaphore = timeline_semaphore.timeline_semaphore;
+        } else {
+            supported.vulkan_10 = unsafe { instance.get_physical_device_features(p_device) };
+        }
+
+        Ok(supported)
+    }
+
+    // Whether dynamic rendering goes through `VK_KHR_dynamic_rendering` on a device of
+    // `api_version`
+    pub(crate) fn dynamic_rendering_extension(&self, api_version: u32) -> bool {
+        self.dynamic_rendering() && api_version < vk::API_VERSION_1_3
+    }
+
+    // Whether timeline semaphores go through `VK_KHR_timeline_semaphore` on a device of
+    // `api_version`
+    pub(crate) fn timeline_semaphore_extension(&self, api_version: u32) -> bool {
+        self.timeline_semaphores() && api_version < vk::API_VERSION_1_2
+    }
+
+    // Calls `f` with a create info for `queue_infos` enabling everything in `self`, on a device
+    // of `api_version`
+    pub(crate) fn with_create_info<F, T>(
+        &self,
+        api_version: u32,
+        queue_infos: &[vk::DeviceQueueCreateInfo],
+        f: F,
+    ) -> T
+    where
+        F: FnOnce(&vk::DeviceCreateInfo) -> T,
+    {
+        let mut vulkan_11 = self.vulkan_11;
+        let mut vulkan_12 = self.vulkan_12;
+        let mut vulkan_13 = self.vulkan_13;
+        let mut dynamic_rendering = vk::PhysicalDeviceDynamicRenderingFeatures::default();
+        let mut timeline_semaphore = vk::PhysicalDeviceTimelineSemaphoreFeatures::default();
+        let mut extensions = self.extensions.clone();
+        if self.dynamic_rendering_extension(api_version) {
+            vulkan_13.dynamic_rendering = vk::FALSE;
+            dynamic_rendering.dynamic_rendering = vk::TRUE;
+            if !self.has_extension(khr::DynamicRendering::name()) {
+                extensions.push(khr::DynamicRendering::name().to_owned());
+            }
+        }
+        if self.timeline_semaphore_extension(api_version) {
+            vulkan_12.timeline_semaphore = vk::FALSE;
+            timeline_semaphore.timeline_semaphore = vk::TRUE;
+            if !self.has_extension(khr::TimelineSemaphore::name()) {
+                extensions.push(khr::TimelineSemaphore::name().to_owned());
+            }
+        }
+        let extensions = extensions
+            .iter()
+            .map(|extension| extension.as_ptr())
+            .collect::<Vec<_>>();
+
+        let mut create_info = vk::DeviceCreateInfo::builder()
+            .queue_create_infos(queue_infos)
+            .enabled_extension_names(&extensions)
+            .enabled_features(&self.vulkan_10);
+        if !is_empty(&vulkan_11) {
+            create_info = create_info.push_next(&mut vulkan_11);
+        }
+        if !is_empty(&vulkan_12) {
+            create_info = create_info.push_next(&mut vulkan_12);
+        }
+        if !is_empty(&vulkan_13) {
+            create_info = create_info.push_next(&mut vulkan_13);
+        }
+        if dynamic_rendering.dynamic_rendering == vk::TRUE {
+            create_info = create_info.push_next(&mut dynamic_rendering);
+        }
+        if timeline_semaphore.timeline_semaphore == vk::TRUE {
+            create_info = create_info.push_next(&mut timeline_semaphore);
+        }
+        f(&create_info)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn missing_features_are_named() {
+        let required = DeviceFeatures::default()
+            .with_vulkan_10(vk::PhysicalDeviceFeatures {
+                robust_buffer_access: vk::TRUE,
+                inherited_queries: vk::TRUE,
+                ..Default::default()
+            })
+            .with_vulkan_11(vk::PhysicalDeviceVulkan11Features {
+                shader_draw_parameters: vk::TRUE,
+                ..Default::default()
+            })
+            .with_timeline_semaphores()
+            .with_dynamic_rendering()
+            .with_vulkan_13(vk::PhysicalDeviceVulkan13Features {
+                maintenance4: vk::TRUE,
+                ..Default::default()
+            });
+        let supported = DeviceFeatures::default()
+            .with_vulkan_10(vk::PhysicalDeviceFeatures {
+                robust_buffer_access: vk::TRUE,
+                ..Default::default()
+            })
+            .with_dynamic_rendering();
+        assert_eq!(
+            required.missing_features(&supported),
+            [
+                "inherited_queries",
+                "shader_draw_parameters",
+                "timeline_semaphore",
+                "maintenance4"
+            ]
+        );
+        assert!(supported.missing_features(&required).is_empty());
+        assert!(required.missing_features(&required).is_empty());
+    }
+
+    // Structure types chained to `create_info`, and its extensions
+    fn create_info_contents(
+        create_info: &vk::DeviceCreateInfo,
+    ) -> (Vec<vk::StructureType>, Vec<CString>) {
+        let mut chain = Vec::new();
+        let mut next = create_info.p_next as *const vk::BaseInStructure;
+        while !next.is_null() {
+            let structure = unsafe { &*next };
+            chain.push(structure.s_type);
+            next = structure.p_next;
+        }
+        let extensions = (0..create_info.enabled_extension_count as usize)
+            .map(|index| unsafe {
+                CStr::from_ptr(*create_info.pp_enabled_extension_names.add(index)).to_owned()
+            })
+            .collect();
+        (chain, extensions)
+    }
+
+    #[test]
+    fn timeline_semaphores_use_the_extension_before_vulkan_12() {
+        let features = DeviceFeatures::default().with_timeline_semaphores();
+        assert!(features.timeline_semaphore_extension(vk::API_VERSION_1_1));
+        assert!(!features.timeline_semaphore_extension(vk::API_VERSION_1_2));
+
+        let (chain, extensions) =
+            features.with_create_info(vk::API_VERSION_1_1, &[], create_info_contents);
+        assert_eq!(
+            chain,
+            [vk::StructureType::PHYSICAL_DEVICE_TIMELINE_SEMAPHORE_FEATURES]
+        );
+        assert_eq!(extensions, [khr::TimelineSemaphore::name().to_owned()]);
+
+        let (chain, extensions) =
+            features.with_create_info(vk::API_VERSION_1_2, &[], create_info_contents);
+        assert_eq!(
+            chain,
+            [vk::StructureType::PHYSICAL_DEVICE_VULKAN_1_2_FEATURES]
+        );
+        assert!(extensions.is_empty());
+    }
+}
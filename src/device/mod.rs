@@ -1,63 +1,162 @@
 use crate::{
     context::Context,
+    descriptors::DescriptorSet,
     entry::Entry,
-    pipeline::{GraphicsPipeline, PipelineStages},
+    error::Result,
+    mem::{Allocation, AllocationKind, Allocator, HeapUsage},
+    pipeline::{Pipeline, PipelineStages},
+    render_pass::{RenderPass, RenderingDesc},
     resources::buffer::Buffer,
     surface::Surface,
     swapchain::Swapchain,
-    sync::{Fence, ImageMemoryBarrier, Semaphore},
+    sync::{Fence, ImageMemoryBarrier, PipelineBarrier, Semaphore, TimelineValue},
     PushConstant,
 };
-use anyhow::Result;
-use ash::vk;
+use ash::{extensions::khr, vk};
+use std::{
+    ops::Deref,
+    sync::{Arc, Mutex},
+};
 use thiserror::Error;
 
+mod deferred;
+mod features;
+mod queue;
+mod selector;
+
+pub(crate) use deferred::take_fields;
+use deferred::{DeferredQueue, RetireAfter};
+pub use deferred::{Retire, RetiredResource};
+pub use features::DeviceFeatures;
+use queue::QueueFamilies;
+pub use queue::{Queue, QueueType};
+pub use selector::{
+    CandidateReport, DeviceCandidate, DevicePreference, DeviceSelector, DeviceType,
+    RejectionReason, SelectionReport,
+};
+
 #[derive(Debug, Error)]
 pub enum DeviceCreationError {
-    #[error("no device found that fullfills all requirements")]
-    NoSuitableDevice,
+    #[error("no device found that fullfills all requirements:{0}")]
+    NoSuitableDevice(SelectionReport),
 }
 
-pub struct Device {
+pub struct DeviceInner {
     pub p_device: vk::PhysicalDevice,
     pub properties: vk::PhysicalDeviceProperties,
     pub memory_properties: vk::PhysicalDeviceMemoryProperties,
     pub device: ash::Device,
-    pub queue_family_index: u32,
-    pub present_queue: vk::Queue,
-    pub command_pool: vk::CommandPool,
+    // Also used for presenting
+    pub graphics_queue: Queue,
+    pub compute_queue: Queue,
+    pub transfer_queue: Queue,
+    enabled_features: DeviceFeatures,
+    // Only loaded when dynamic rendering is enabled on a device older than Vulkan 1.3
+    dynamic_rendering: Option<khr::DynamicRendering>,
+    // Only loaded when timeline semaphores are enabled on a device older than Vulkan 1.2
+    timeline_semaphore: Option<khr::TimelineSemaphore>,
+    allocator: Mutex<Allocator>,
+    retired: Mutex<DeferredQueue>,
+    // Keeps the instance alive for as long as the device is
+    entry: Entry,
+}
+
+impl Drop for DeviceInner {
+    fn drop(&mut self) {
+        unsafe {
+            self.device.device_wait_idle().ok();
+            for queue in [
+                &self.graphics_queue,
+                &self.compute_queue,
+                &self.transfer_queue,
+            ] {
+                if queue.dedicated {
+                    self.device.destroy_command_pool(queue.command_pool, None);
+                }
+            }
+            let mut allocator = self.allocator.lock().unwrap();
+            for resource in self.retired.lock().unwrap().take_all() {
+                resource.destroy(&self.device, &mut allocator);
+            }
+            allocator.clean(&self.device);
+            self.device.destroy_device(None);
+        }
+    }
+}
+
+// Cheap to clone. Every resource holds a clone, so the logical device is only destroyed once
+// all of them have been dropped.
+#[derive(Clone)]
+pub struct Device {
+    inner: Arc<DeviceInner>,
+}
+
+impl std::fmt::Debug for Device {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Device")
+            .field("p_device", &self.p_device)
+            .field("graphics_queue", &self.graphics_queue)
+            .field("compute_queue", &self.compute_queue)
+            .field("transfer_queue", &self.transfer_queue)
+            .finish()
+    }
+}
+
+impl Deref for Device {
+    type Target = DeviceInner;
+
+    fn deref(&self) -> &Self::Target {
+        &self.inner
+    }
 }
 
 impl Device {
     pub fn new(entry: &Entry, surface: &Surface) -> Result<Self> {
-        let pdevices = unsafe { entry.instance.enumerate_physical_devices()? };
-        let (p_device, queue_family_index) = pdevices
-            .iter()
-            .find_map(|pdevice| unsafe {
-                entry
-                    .instance
-                    .get_physical_device_queue_family_properties(*pdevice)
-                    .iter()
-                    .enumerate()
-                    .find_map(|(index, info)| {
-                        let supports_graphic_and_surface =
-                            info.queue_flags.contains(vk::QueueFlags::GRAPHICS)
-                                && surface
-                                    .loader
-                                    .get_physical_device_surface_support(
-                                        *pdevice,
-                                        index as u32,
-                                        surface.raw,
-                                    )
-                                    .unwrap();
-                        if supports_graphic_and_surface {
-                            Some((*pdevice, index as u32))
-                        } else {
-                            None
-                        }
-                    })
-            })
-            .ok_or(DeviceCreationError::NoSuitableDevice)?;
+        Self::from_selector(
+            entry,
+            &DeviceSelector::default()
+                .with_surface(surface)
+                .with_required_features(Self::default_features()),
+        )
+    }
+
+    pub fn new_headless(entry: &Entry) -> Result<Self> {
+        Self::from_selector(
+            entry,
+            &DeviceSelector::default().with_required_features(Self::default_features()),
+        )
+    }
+
+    // What `new` and `new_headless` enable
+    pub fn default_features() -> DeviceFeatures {
+        DeviceFeatures::default().with_vulkan_10(vk::PhysicalDeviceFeatures {
+            shader_clip_distance: vk::TRUE,
+            ..Default::default()
+        })
+    }
+
+    // Enables exactly the selector's required extensions and features, devices that don't support
+    // all of them are never selected
+    pub fn from_selector(entry: &Entry, selector: &DeviceSelector) -> Result<Self> {
+        let report = selector.evaluate(entry)?;
+        let candidate = match report.selected() {
+            Some(candidate) => candidate,
+            None => return Err(DeviceCreationError::NoSuitableDevice(report).into()),
+        };
+        Self::from_physical_device(
+            entry,
+            candidate.p_device,
+            candidate.queue_family_index.unwrap(),
+            selector.required_features(),
+        )
+    }
+
+    fn from_physical_device(
+        entry: &Entry,
+        p_device: vk::PhysicalDevice,
+        queue_family_index: u32,
+        enabled_features: DeviceFeatures,
+    ) -> Result<Self> {
         let properties = unsafe { entry.instance.get_physical_device_properties(p_device) };
         let memory_properties = unsafe {
             entry
@@ -65,44 +164,180 @@ impl Device {
                 .get_physical_device_memory_properties(p_device)
         };
 
+        let queue_families = QueueFamilies::find(
+            &unsafe {
+                entry
+                    .instance
+                    .get_physical_device_queue_family_properties(p_device)
+            },
+            queue_family_index,
+        );
         let priorities = [1.0];
-        let queue_info = vk::DeviceQueueCreateInfo::builder()
-            .queue_family_index(queue_family_index)
-            .queue_priorities(&priorities);
+        let queue_infos = queue_families
+            .unique()
+            .into_iter()
+            .map(|family_index| {
+                vk::DeviceQueueCreateInfo::builder()
+                    .queue_family_index(family_index)
+                    .queue_priorities(&priorities)
+                    .build()
+            })
+            .collect::<Vec<_>>();
 
-        let device_extension_names_raw = [ash::extensions::khr::Swapchain::name().as_ptr()];
-        let features = vk::PhysicalDeviceFeatures {
-            shader_clip_distance: 1,
-            ..Default::default()
-        };
-        let device_create_info = vk::DeviceCreateInfo::builder()
-            .queue_create_infos(std::slice::from_ref(&queue_info))
-            .enabled_extension_names(&device_extension_names_raw)
-            .enabled_features(&features);
+        let api_version = entry.api_version.min(properties.api_version);
+        let device = enabled_features.with_create_info(
+            api_version,
+            &queue_infos,
+            |device_create_info| unsafe {
+                entry
+                    .instance
+                    .create_device(p_device, device_create_info, None)
+            },
+        )?;
+        let dynamic_rendering = enabled_features
+            .dynamic_rendering_extension(api_version)
+            .then(|| khr::DynamicRendering::new(&entry.instance, &device));
+        let timeline_semaphore = enabled_features
+            .timeline_semaphore_extension(api_version)
+            .then(|| khr::TimelineSemaphore::new(&entry.instance, &device));
 
-        let device = unsafe {
-            entry
+        let (graphics_queue, compute_queue, transfer_queue) =
+            queue_families.create_queues(&device)?;
+
+        let allocator = Mutex::new(Allocator::new(memory_properties, &properties.limits));
+
+        Ok(Self {
+            inner: Arc::new(DeviceInner {
+                p_device,
+                properties,
+                memory_properties,
+                device,
+                graphics_queue,
+                compute_queue,
+                transfer_queue,
+                enabled_features,
+                dynamic_rendering,
+                timeline_semaphore,
+                allocator,
+                retired: Default::default(),
+                entry: entry.clone(),
+            }),
+        })
+    }
+
+    pub fn queue(&self, ty: QueueType) -> &Queue {
+        match ty {
+            QueueType::Graphics => &self.graphics_queue,
+            QueueType::Compute => &self.compute_queue,
+            QueueType::Transfer => &self.transfer_queue,
+        }
+    }
+
+    pub fn enabled_features(&self) -> &DeviceFeatures {
+        &self.enabled_features
+    }
+
+    // Only D16 and one of D24S8 and D32S8 are guaranteed to be usable as depth attachments
+    pub fn supports_depth_format(&self, format: impl Into<vk::Format>) -> bool {
+        let properties = unsafe {
+            self.entry
                 .instance
-                .create_device(p_device, &device_create_info, None)?
+                .get_physical_device_format_properties(self.p_device, format.into())
         };
+        properties
+            .optimal_tiling_features
+            .contains(vk::FormatFeatureFlags::DEPTH_STENCIL_ATTACHMENT)
+    }
 
-        let present_queue = unsafe { device.get_device_queue(queue_family_index, 0) };
+    pub fn allocate(
+        &self,
+        requirements: &vk::MemoryRequirements,
+        memory_type_index: u32,
+        kind: AllocationKind,
+    ) -> Result<Allocation> {
+        self.allocator
+            .lock()
+            .unwrap()
+            .allocate(&self.device, requirements, memory_type_index, kind)
+    }
 
-        let pool_create_info = vk::CommandPoolCreateInfo::builder()
-            .flags(vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER)
-            .queue_family_index(queue_family_index);
+    pub unsafe fn free(&self, allocation: &Allocation) {
+        self.allocator
+            .lock()
+            .unwrap()
+            .free(&self.device, allocation);
+    }
 
-        let command_pool = unsafe { device.create_command_pool(&pool_create_info, None)? };
+    // Defers destroying `resource` until `fence` is signaled. `fence` should be the one of the last
+    // submission that used the resource, and must already have been submitted.
+    pub fn retire(&self, resource: impl Retire, fence: &Fence) {
+        self.retired
+            .lock()
+            .unwrap()
+            .push(RetireAfter::Fence(fence.fence), resource.into_retired());
+    }
 
-        Ok(Self {
-            p_device,
-            properties,
-            memory_properties,
-            device,
-            queue_family_index,
-            present_queue,
-            command_pool,
-        })
+    // Like `retire`, for submissions that signal a timeline value
+    pub fn retire_timeline(&self, resource: impl Retire, timeline_value: TimelineValue) {
+        self.retired.lock().unwrap().push(
+            RetireAfter::Timeline(timeline_value.semaphore.semaphore, timeline_value.value),
+            resource.into_retired(),
+        );
+    }
+
+    // Destroys every retired resource whose fence or timeline value has been signaled
+    pub fn destroy_retired(&self) -> Result<()> {
+        let mut retired = self.retired.lock().unwrap();
+        let mut signaled = Vec::new();
+        for after in retired.conditions() {
+            let is_signaled = match after {
+                RetireAfter::Fence(fence) => unsafe { self.device.get_fence_status(fence)? },
+                RetireAfter::Timeline(semaphore, value) => {
+                    let current = self.semaphore_counter_value(semaphore)?;
+                    current >= value
+                }
+            };
+            if is_signaled {
+                signaled.push(after);
+            }
+        }
+        let resources = retired.take_completed(|after| signaled.contains(&after));
+        drop(retired);
+        self.destroy_resources(resources);
+        Ok(())
+    }
+
+    // A fence can only be destroyed once its submission has completed, so anything retired against
+    // it can go as well
+    pub(crate) fn release_fence(&self, fence: vk::Fence) {
+        let resources = self
+            .retired
+            .lock()
+            .unwrap()
+            .take_completed(|after| after == RetireAfter::Fence(fence));
+        self.destroy_resources(resources);
+    }
+
+    // Same as `release_fence`, for every value of a timeline semaphore
+    pub(crate) fn release_timeline(&self, semaphore: vk::Semaphore) {
+        let resources = self.retired.lock().unwrap().take_completed(
+            |after| matches!(after, RetireAfter::Timeline(retired, _) if retired == semaphore),
+        );
+        self.destroy_resources(resources);
+    }
+
+    fn destroy_resources(&self, resources: Vec<RetiredResource>) {
+        if resources.is_empty() {
+            return;
+        }
+        let mut allocator = self.allocator.lock().unwrap();
+        for resource in resources {
+            unsafe { resource.destroy(&self.device, &mut allocator) };
+        }
+    }
+
+    pub fn memory_usage(&self) -> Vec<HeapUsage> {
+        self.allocator.lock().unwrap().usage()
     }
 
     // TODO: Better name/abstraction
@@ -116,6 +351,91 @@ impl Device {
         }
     }
 
+    pub fn set_rendering_viewport_and_scissor(&self, context: &Context, desc: &RenderingDesc) {
+        unsafe {
+            self.device
+                .cmd_set_viewport(context.command_buffer, 0, &[desc.viewport()]);
+            self.device
+                .cmd_set_scissor(context.command_buffer, 0, &[desc.scissor()]);
+        }
+    }
+
+    pub(crate) fn cmd_begin_rendering(
+        &self,
+        context: &Context,
+        rendering_info: &vk::RenderingInfo,
+    ) {
+        unsafe {
+            match &self.dynamic_rendering {
+                Some(dynamic_rendering) => {
+                    dynamic_rendering.cmd_begin_rendering(context.command_buffer, rendering_info)
+                }
+                None => self
+                    .device
+                    .cmd_begin_rendering(context.command_buffer, rendering_info),
+            }
+        }
+    }
+
+    pub(crate) fn cmd_end_rendering(&self, context: &Context) {
+        unsafe {
+            match &self.dynamic_rendering {
+                Some(dynamic_rendering) => {
+                    dynamic_rendering.cmd_end_rendering(context.command_buffer)
+                }
+                None => self.device.cmd_end_rendering(context.command_buffer),
+            }
+        }
+    }
+
+    pub(crate) fn signal_semaphore(&self, signal_info: &vk::SemaphoreSignalInfo) -> Result<()> {
+        unsafe {
+            match &self.timeline_semaphore {
+                Some(timeline_semaphore) => timeline_semaphore.signal_semaphore(signal_info)?,
+                None => self.device.signal_semaphore(signal_info)?,
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) fn wait_semaphores(
+        &self,
+        wait_info: &vk::SemaphoreWaitInfo,
+        timeout: u64,
+    ) -> ash::prelude::VkResult<()> {
+        unsafe {
+            match &self.timeline_semaphore {
+                Some(timeline_semaphore) => timeline_semaphore.wait_semaphores(wait_info, timeout),
+                None => self.device.wait_semaphores(wait_info, timeout),
+            }
+        }
+    }
+
+    pub(crate) fn semaphore_counter_value(&self, semaphore: vk::Semaphore) -> Result<u64> {
+        let value = unsafe {
+            match &self.timeline_semaphore {
+                Some(timeline_semaphore) => {
+                    timeline_semaphore.get_semaphore_counter_value(semaphore)?
+                }
+                None => self.device.get_semaphore_counter_value(semaphore)?,
+            }
+        };
+        Ok(value)
+    }
+
+    pub fn set_render_pass_viewport_and_scissor(
+        &self,
+        context: &Context,
+        render_pass: &RenderPass,
+    ) {
+        unsafe {
+            self.device
+                .cmd_set_viewport(context.command_buffer, 0, &[render_pass.viewport()]);
+            self.device
+                .cmd_set_scissor(context.command_buffer, 0, &[render_pass.scissor()]);
+        }
+    }
+
     pub fn bind_vertex_buffers(&self, context: &Context, buffers: &[&Buffer]) {
         // TODO: I should not need to collect here, reduces perf
         let buffers = buffers
@@ -140,6 +460,82 @@ impl Device {
         }
     }
 
+    pub fn copy_buffer(&self, context: &Context, src: &Buffer, dst: &Buffer) {
+        let region = vk::BufferCopy {
+            src_offset: 0,
+            dst_offset: 0,
+            size: src.size.min(dst.size),
+        };
+        unsafe {
+            self.device
+                .cmd_copy_buffer(context.command_buffer, src.buffer, dst.buffer, &[region]);
+        }
+    }
+
+    pub fn bind_pipeline(&self, context: &Context, pipeline: &impl Pipeline) {
+        unsafe {
+            self.device.cmd_bind_pipeline(
+                context.command_buffer,
+                pipeline.bind_point(),
+                pipeline.raw(),
+            )
+        };
+    }
+
+    pub fn bind_descriptor_sets(
+        &self,
+        context: &Context,
+        pipeline: &impl Pipeline,
+        first_set: u32,
+        descriptor_sets: &[&DescriptorSet],
+    ) {
+        let descriptor_sets = descriptor_sets
+            .iter()
+            .map(|set| set.descriptor_set)
+            .collect::<Vec<_>>();
+        unsafe {
+            self.device.cmd_bind_descriptor_sets(
+                context.command_buffer,
+                pipeline.bind_point(),
+                pipeline.layout(),
+                first_set,
+                &descriptor_sets,
+                &[],
+            );
+        }
+    }
+
+    pub fn dispatch(
+        &self,
+        context: &Context,
+        group_count_x: u32,
+        group_count_y: u32,
+        group_count_z: u32,
+    ) {
+        unsafe {
+            self.device.cmd_dispatch(
+                context.command_buffer,
+                group_count_x,
+                group_count_y,
+                group_count_z,
+            );
+        }
+    }
+
+    pub fn dispatch_indirect(&self, context: &Context, buffer: &Buffer, offset: u64) {
+        unsafe {
+            self.device
+                .cmd_dispatch_indirect(context.command_buffer, buffer.buffer, offset);
+        }
+    }
+
+    pub fn draw(&self, context: &Context, first_vertex: u32, vertex_count: u32) {
+        unsafe {
+            self.device
+                .cmd_draw(context.command_buffer, vertex_count, 1, first_vertex, 0);
+        }
+    }
+
     pub fn draw_indexed(&self, context: &Context, first_index: u32, index_count: u32) {
         unsafe {
             self.device
@@ -150,14 +546,14 @@ impl Device {
     pub fn push_constant(
         &self,
         context: &Context,
-        pipeline: &GraphicsPipeline,
+        pipeline: &impl Pipeline,
         push_constant: &PushConstant,
         data: &[u8],
     ) {
         unsafe {
             self.device.cmd_push_constants(
                 context.command_buffer,
-                pipeline.layout,
+                pipeline.layout(),
                 push_constant.stage.into(),
                 push_constant.offset,
                 data,
@@ -165,13 +561,38 @@ impl Device {
         }
     }
 
+    // Submits to the queue `context` was allocated for
     pub fn queue_submit(
         &self,
         context: &Context,
-        wait_semaphores: &[Semaphore],
-        signal_semaphores: &[Semaphore],
+        wait_semaphores: &[&Semaphore],
+        signal_semaphores: &[&Semaphore],
         fence: &Fence,
         wait_mask: &[vk::PipelineStageFlags],
+    ) -> Result<()> {
+        self.queue_submit_timeline(
+            context,
+            wait_semaphores,
+            &[],
+            signal_semaphores,
+            &[],
+            Some(fence),
+            wait_mask,
+        )
+    }
+
+    // Like `queue_submit`, also waiting on and signaling timeline values. `wait_mask` has an entry
+    // per binary wait semaphore, followed by one per timeline wait.
+    #[allow(clippy::too_many_arguments)]
+    pub fn queue_submit_timeline(
+        &self,
+        context: &Context,
+        wait_semaphores: &[&Semaphore],
+        wait_timelines: &[TimelineValue],
+        signal_semaphores: &[&Semaphore],
+        signal_timelines: &[TimelineValue],
+        fence: Option<&Fence>,
+        wait_mask: &[vk::PipelineStageFlags],
     ) -> Result<()> {
         let command_buffers = vec![context.command_buffer];
 
@@ -180,22 +601,44 @@ impl Device {
         let wait_semaphores = wait_semaphores
             .iter()
             .map(|semaphore| semaphore.semaphore)
+            .chain(wait_timelines.iter().map(|wait| wait.semaphore.semaphore))
             .collect::<Vec<_>>();
         let signal_semaphores = signal_semaphores
             .iter()
             .map(|semaphore| semaphore.semaphore)
+            .chain(
+                signal_timelines
+                    .iter()
+                    .map(|signal| signal.semaphore.semaphore),
+            )
+            .collect::<Vec<_>>();
+        // Values of binary semaphores are ignored, but there has to be one for each of them
+        let wait_values = std::iter::repeat_n(0, wait_semaphores.len() - wait_timelines.len())
+            .chain(wait_timelines.iter().map(|wait| wait.value))
             .collect::<Vec<_>>();
+        let signal_values =
+            std::iter::repeat_n(0, signal_semaphores.len() - signal_timelines.len())
+                .chain(signal_timelines.iter().map(|signal| signal.value))
+                .collect::<Vec<_>>();
 
-        let submit_info = vk::SubmitInfo::builder()
+        let mut timeline_info = vk::TimelineSemaphoreSubmitInfo::builder()
+            .wait_semaphore_values(&wait_values)
+            .signal_semaphore_values(&signal_values);
+        let mut submit_info = vk::SubmitInfo::builder()
             .wait_semaphores(&wait_semaphores)
             .wait_dst_stage_mask(wait_mask)
             .command_buffers(&command_buffers)
-            .signal_semaphores(&signal_semaphores)
-            .build();
+            .signal_semaphores(&signal_semaphores);
+        if !wait_timelines.is_empty() || !signal_timelines.is_empty() {
+            submit_info = submit_info.push_next(&mut timeline_info);
+        }
 
         unsafe {
-            self.device
-                .queue_submit(self.present_queue, &[submit_info], fence.fence)?
+            self.device.queue_submit(
+                self.queue(context.queue_type()).queue,
+                &[submit_info.build()],
+                fence.map_or(vk::Fence::null(), |fence| fence.fence),
+            )?
         };
         Ok(())
     }
@@ -207,20 +650,22 @@ impl Device {
         dst_stage: PipelineStages,
         image_barriers: &[ImageMemoryBarrier],
     ) {
-        let image_barriers = image_barriers
-            .iter()
-            .map(|image_barrier| image_barrier.raw)
-            .collect::<Vec<_>>();
-        // TODO: Hook up dependency flags
+        self.pipeline_barrier(
+            context,
+            &PipelineBarrier::new(src_stage, dst_stage).with_image_barriers(image_barriers),
+        );
+    }
+
+    pub fn pipeline_barrier(&self, context: &Context, barrier: &PipelineBarrier) {
         unsafe {
             self.device.cmd_pipeline_barrier(
                 context.command_buffer,
-                src_stage.into(),
-                dst_stage.into(),
-                vk::DependencyFlags::empty(),
-                &[],
-                &[],
-                &image_barriers,
+                barrier.src_stage,
+                barrier.dst_stage,
+                barrier.dependency_flags,
+                &barrier.memory_barriers,
+                &barrier.buffer_barriers,
+                &barrier.image_barriers,
             );
         }
     }
@@ -231,11 +676,4 @@ impl Device {
         }
         Ok(())
     }
-
-    pub unsafe fn clean(&self) {
-        unsafe {
-            self.device.destroy_command_pool(self.command_pool, None);
-            self.device.destroy_device(None);
-        }
-    }
 }
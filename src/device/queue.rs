@@ -0,0 +1,88 @@
+use crate::error::Result;
+use ash::vk;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum QueueType {
+    Graphics,
+    Compute,
+    Transfer,
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct Queue {
+    pub queue: vk::Queue,
+    pub family_index: u32,
+    pub command_pool: vk::CommandPool,
+    // False for compute and transfer queues that fall back to the queue of another type, in which
+    // case submissions to both serialize
+    pub dedicated: bool,
+}
+
+// Compute falls back to the graphics family, transfer to the compute family
+pub(crate) struct QueueFamilies {
+    graphics: u32,
+    compute: Option<u32>,
+    transfer: Option<u32>,
+}
+
+impl QueueFamilies {
+    pub(crate) fn find(families: &[vk::QueueFamilyProperties], graphics: u32) -> Self {
+        let find = |wanted: vk::QueueFlags, unwanted: vk::QueueFlags| {
+            families
+                .iter()
+                .enumerate()
+                .find(|(_, info)| {
+                    info.queue_count > 0
+                        && info.queue_flags.contains(wanted)
+                        && !info.queue_flags.intersects(unwanted)
+                })
+                .map(|(index, _)| index as u32)
+        };
+        Self {
+            graphics,
+            compute: find(vk::QueueFlags::COMPUTE, vk::QueueFlags::GRAPHICS),
+            transfer: find(
+                vk::QueueFlags::TRANSFER,
+                vk::QueueFlags::GRAPHICS | vk::QueueFlags::COMPUTE,
+            ),
+        }
+    }
+
+    // One queue is created per family
+    pub(crate) fn unique(&self) -> Vec<u32> {
+        let mut families = vec![self.graphics];
+        families.extend(self.compute);
+        families.extend(self.transfer);
+        families
+    }
+
+    // Creates a command pool per family, and returns the graphics, compute and transfer queues
+    pub(crate) fn create_queues(&self, device: &ash::Device) -> Result<(Queue, Queue, Queue)> {
+        let create_queue = |family_index: u32| -> Result<Queue> {
+            let pool_create_info = vk::CommandPoolCreateInfo::builder()
+                .flags(vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER)
+                .queue_family_index(family_index);
+            Ok(Queue {
+                queue: unsafe { device.get_device_queue(family_index, 0) },
+                family_index,
+                command_pool: unsafe { device.create_command_pool(&pool_create_info, None)? },
+                dedicated: true,
+            })
+        };
+        let shared = |queue: Queue| Queue {
+            dedicated: false,
+            ..queue
+        };
+
+        let graphics = create_queue(self.graphics)?;
+        let compute = match self.compute {
+            Some(family_index) => create_queue(family_index)?,
+            None => shared(graphics),
+        };
+        let transfer = match self.transfer {
+            Some(family_index) => create_queue(family_index)?,
+            None => shared(compute),
+        };
+        Ok((graphics, compute, transfer))
+    }
+}
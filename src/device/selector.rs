@@ -0,0 +1,491 @@
+use crate::{device::DeviceFeatures, entry::Entry, error::Result, surface::Surface};
+use ash::vk;
+use std::{
+    ffi::{CStr, CString},
+    fmt,
+};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DeviceType {
+    Discrete,
+    Integrated,
+    Virtual,
+    Cpu,
+    Other,
+}
+
+impl From<vk::PhysicalDeviceType> for DeviceType {
+    fn from(ty: vk::PhysicalDeviceType) -> Self {
+        match ty {
+            vk::PhysicalDeviceType::DISCRETE_GPU => DeviceType::Discrete,
+            vk::PhysicalDeviceType::INTEGRATED_GPU => DeviceType::Integrated,
+            vk::PhysicalDeviceType::VIRTUAL_GPU => DeviceType::Virtual,
+            vk::PhysicalDeviceType::CPU => DeviceType::Cpu,
+            _ => DeviceType::Other,
+        }
+    }
+}
+
+// Earlier preferences weigh more than all of the later ones combined
+#[derive(Debug, Clone)]
+pub enum DevicePreference {
+    Type(DeviceType),
+    // Case insensitive substring of the device name
+    Name(String),
+    // Position in the instance's physical device enumeration
+    Index(usize),
+    MostDeviceLocalMemory,
+}
+
+#[derive(Debug, Clone)]
+pub struct DeviceCandidate {
+    pub index: usize,
+    pub p_device: vk::PhysicalDevice,
+    pub properties: vk::PhysicalDeviceProperties,
+    pub memory_properties: vk::PhysicalDeviceMemoryProperties,
+    // Everything the device supports
+    pub features: DeviceFeatures,
+    // First queue family that can be used by the device, if any
+    pub queue_family_index: Option<u32>,
+    // Set when asking whether a queue family can present to the surface failed
+    pub surface_support_error: Option<vk::Result>,
+}
+
+impl DeviceCandidate {
+    pub fn name(&self) -> String {
+        unsafe { CStr::from_ptr(self.properties.device_name.as_ptr()) }
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    pub fn device_type(&self) -> DeviceType {
+        self.properties.device_type.into()
+    }
+
+    pub fn device_local_memory(&self) -> u64 {
+        self.memory_properties.memory_heaps[..self.memory_properties.memory_heap_count as usize]
+            .iter()
+            .filter(|heap| heap.flags.contains(vk::MemoryHeapFlags::DEVICE_LOCAL))
+            .map(|heap| heap.size)
+            .sum()
+    }
+
+    pub fn supports_extension(&self, extension: &CStr) -> bool {
+        self.features.has_extension(extension)
+    }
+
+    fn matches(&self, preference: &DevicePreference) -> bool {
+        match preference {
+            DevicePreference::Type(ty) => self.device_type() == *ty,
+            DevicePreference::Name(name) => {
+                self.name().to_lowercase().contains(&name.to_lowercase())
+            }
+            DevicePreference::Index(index) => self.index == *index,
+            // Handled by the caller, it depends on the other candidates
+            DevicePreference::MostDeviceLocalMemory => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RejectionReason {
+    NoSuitableQueue,
+    SurfaceSupportQueryFailed(vk::Result),
+    MissingExtension(CString),
+    MissingFeature(&'static str),
+    // The selection environment variable asks for a different device
+    NotSelectedByEnv(String),
+}
+
+impl fmt::Display for RejectionReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RejectionReason::NoSuitableQueue => write!(f, "no suitable queue family"),
+            RejectionReason::SurfaceSupportQueryFailed(result) => {
+                write!(f, "could not query surface support ({})", result)
+            }
+            RejectionReason::MissingExtension(extension) => {
+                write!(f, "missing extension {}", extension.to_string_lossy())
+            }
+            RejectionReason::MissingFeature(feature) => write!(f, "missing feature {}", feature),
+            RejectionReason::NotSelectedByEnv(value) => {
+                write!(f, "not the device selected by the environment ({})", value)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CandidateReport {
+    pub candidate: DeviceCandidate,
+    // Whether each preference matched, in preference order. Compared lexicographically, so higher
+    // is better. `Err` if the candidate can't be used at all
+    pub score: std::result::Result<Vec<bool>, Vec<RejectionReason>>,
+}
+
+#[derive(Debug, Clone)]
+pub struct SelectionReport {
+    pub candidates: Vec<CandidateReport>,
+    // Index into `candidates`
+    pub selected: Option<usize>,
+}
+
+impl SelectionReport {
+    pub fn selected(&self) -> Option<&DeviceCandidate> {
+        self.selected
+            .map(|selected| &self.candidates[selected].candidate)
+    }
+}
+
+// One digit per preference, 1 where it matched
+fn format_score(score: &[bool]) -> String {
+    if score.is_empty() {
+        return "without preferences".to_owned();
+    }
+    let digits = score
+        .iter()
+        .map(|matched| if *matched { '1' } else { '0' })
+        .collect::<String>();
+    format!("score {}", digits)
+}
+
+impl fmt::Display for SelectionReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.candidates.is_empty() {
+            return write!(f, "no physical devices found");
+        }
+        for (index, report) in self.candidates.iter().enumerate() {
+            let candidate = &report.candidate;
+            write!(
+                f,
+                "\n  [{}] {} ({:?}): ",
+                candidate.index,
+                candidate.name(),
+                candidate.device_type()
+            )?;
+            match &report.score {
+                Ok(score) if self.selected == Some(index) => {
+                    write!(f, "selected, {}", format_score(score))?
+                }
+                Ok(score) => write!(f, "{}", format_score(score))?,
+                Err(reasons) => {
+                    let reasons = reasons
+                        .iter()
+                        .map(|reason| reason.to_string())
+                        .collect::<Vec<_>>();
+                    write!(f, "rejected, {}", reasons.join(", "))?
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+pub struct DeviceSelector<'a> {
+    surface: Option<&'a Surface>,
+    features: DeviceFeatures,
+    preferences: Vec<DevicePreference>,
+    env_var: Option<String>,
+}
+
+impl<'a> Default for DeviceSelector<'a> {
+    fn default() -> Self {
+        Self {
+            surface: None,
+            features: Default::default(),
+            preferences: vec![
+                DevicePreference::Type(DeviceType::Discrete),
+                DevicePreference::Type(DeviceType::Integrated),
+            ],
+            env_var: None,
+        }
+    }
+}
+
+impl<'a> DeviceSelector<'a> {
+    // Requires a queue family that can present to `surface`, and the swapchain extension
+    pub fn with_surface(mut self, surface: &'a Surface) -> Self {
+        self.surface = Some(surface);
+        self
+    }
+
+    pub fn with_required_extension(mut self, extension: &CStr) -> Self {
+        self.features = self.features.with_extension(extension);
+        self
+    }
+
+    // Replaces the features and extensions set so far
+    pub fn with_required_features(mut self, features: DeviceFeatures) -> Self {
+        self.features = features;
+        self
+    }
+
+    // Replaces the default of discrete over integrated GPUs
+    pub fn with_preferences(mut self, preferences: &[DevicePreference]) -> Self {
+        self.preferences = preferences.to_vec();
+        self
+    }
+
+    // If `name` is set, it overrides every preference: a number selects the device at that index,
+    // anything else is matched against device names like `DevicePreference::Name`
+    pub fn with_env_var(mut self, name: impl Into<String>) -> Self {
+        self.env_var = Some(name.into());
+        self
+    }
+
+    // The features the created device is going to have enabled
+    pub fn required_features(&self) -> DeviceFeatures {
+        match self.surface {
+            Some(_) => self
+                .features
+                .clone()
+                .with_extension(ash::extensions::khr::Swapchain::name()),
+            None => self.features.clone(),
+        }
+    }
+
+    pub fn enumerate(&self, entry: &Entry) -> Result<Vec<DeviceCandidate>> {
+        let pdevices = unsafe { entry.instance.enumerate_physical_devices()? };
+        pdevices
+            .into_iter()
+            .enumerate()
+            .map(|(index, p_device)| self.candidate(entry, index, p_device))
+            .collect()
+    }
+
+    fn candidate(
+        &self,
+        entry: &Entry,
+        index: usize,
+        p_device: vk::PhysicalDevice,
+    ) -> Result<DeviceCandidate> {
+        let instance = &entry.instance;
+        let (properties, memory_properties, queue_families) = unsafe {
+            (
+                instance.get_physical_device_properties(p_device),
+                instance.get_physical_device_memory_properties(p_device),
+                instance.get_physical_device_queue_family_properties(p_device),
+            )
+        };
+        let features = DeviceFeatures::query(entry, p_device, &properties)?;
+
+        let mut queue_family_index = None;
+        let mut surface_support_error = None;
+        for (family_index, info) in queue_families.iter().enumerate() {
+            let family_index = family_index as u32;
+            let suitable = match self.surface {
+                Some(surface) => {
+                    info.queue_flags.contains(vk::QueueFlags::GRAPHICS)
+                        && match unsafe {
+                            surface.loader.get_physical_device_surface_support(
+                                p_device,
+                                family_index,
+                                surface.raw,
+                            )
+                        } {
+                            Ok(supported) => supported,
+                            // Reported as a rejection if no other family works
+                            Err(err) => {
+                                surface_support_error = Some(err);
+                                false
+                            }
+                        }
+                }
+                None => info
+                    .queue_flags
+                    .contains(vk::QueueFlags::GRAPHICS | vk::QueueFlags::COMPUTE),
+            };
+            if suitable {
+                queue_family_index = Some(family_index);
+                break;
+            }
+        }
+
+        Ok(DeviceCandidate {
+            index,
+            p_device,
+            properties,
+            memory_properties,
+            features,
+            queue_family_index,
+            surface_support_error,
+        })
+    }
+
+    fn rejection_reasons(
+        &self,
+        required: &DeviceFeatures,
+        candidate: &DeviceCandidate,
+    ) -> Vec<RejectionReason> {
+        let mut reasons = Vec::new();
+        if candidate.queue_family_index.is_none() {
+            reasons.push(match candidate.surface_support_error {
+                Some(err) => RejectionReason::SurfaceSupportQueryFailed(err),
+                None => RejectionReason::NoSuitableQueue,
+            });
+        }
+        reasons.extend(
+            required
+                .missing_extensions(&candidate.features)
+                .into_iter()
+                .map(RejectionReason::MissingExtension),
+        );
+        reasons.extend(
+            required
+                .missing_features(&candidate.features)
+                .into_iter()
+                .map(RejectionReason::MissingFeature),
+        );
+        if let Some(value) = self
+            .env_var
+            .as_ref()
+            .and_then(|name| std::env::var(name).ok())
+        {
+            let preference = match value.parse::<usize>() {
+                Ok(index) => DevicePreference::Index(index),
+                Err(_) => DevicePreference::Name(value.clone()),
+            };
+            if !candidate.matches(&preference) {
+                reasons.push(RejectionReason::NotSelectedByEnv(value));
+            }
+        }
+        reasons
+    }
+
+    // Scores every device. Ties go to the device enumerated first.
+    pub fn evaluate(&self, entry: &Entry) -> Result<SelectionReport> {
+        let candidates = self.enumerate(entry)?;
+        Ok(self.select(candidates))
+    }
+
+    fn select(&self, candidates: Vec<DeviceCandidate>) -> SelectionReport {
+        let required = self.required_features();
+        let reasons = candidates
+            .iter()
+            .map(|candidate| self.rejection_reasons(&required, candidate))
+            .collect::<Vec<_>>();
+        // Only compared between devices that can be used
+        let most_memory = candidates
+            .iter()
+            .zip(&reasons)
+            .filter(|(_, reasons)| reasons.is_empty())
+            .map(|(candidate, _)| candidate.device_local_memory())
+            .max()
+            .unwrap_or(0);
+
+        let candidates = candidates
+            .into_iter()
+            .zip(reasons)
+            .map(|(candidate, reasons)| {
+                let score = if reasons.is_empty() {
+                    Ok(self
+                        .preferences
+                        .iter()
+                        .map(|preference| match preference {
+                            DevicePreference::MostDeviceLocalMemory => {
+                                candidate.device_local_memory() == most_memory
+                            }
+                            _ => candidate.matches(preference),
+                        })
+                        .collect())
+                } else {
+                    Err(reasons)
+                };
+                CandidateReport { candidate, score }
+            })
+            .collect::<Vec<_>>();
+
+        let mut selected: Option<usize> = None;
+        for (index, report) in candidates.iter().enumerate() {
+            if let Ok(score) = &report.score {
+                let is_better = match selected {
+                    Some(selected) => candidates[selected].score.as_ref().unwrap() < score,
+                    None => true,
+                };
+                if is_better {
+                    selected = Some(index);
+                }
+            }
+        }
+
+        SelectionReport {
+            candidates,
+            selected,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn candidate(index: usize, ty: vk::PhysicalDeviceType, memory: u64) -> DeviceCandidate {
+        let mut memory_properties = vk::PhysicalDeviceMemoryProperties {
+            memory_heap_count: 1,
+            ..Default::default()
+        };
+        memory_properties.memory_heaps[0] = vk::MemoryHeap {
+            size: memory,
+            flags: vk::MemoryHeapFlags::DEVICE_LOCAL,
+        };
+        DeviceCandidate {
+            index,
+            p_device: vk::PhysicalDevice::null(),
+            properties: vk::PhysicalDeviceProperties {
+                device_type: ty,
+                ..Default::default()
+            },
+            memory_properties,
+            features: DeviceFeatures::default(),
+            queue_family_index: Some(0),
+            surface_support_error: None,
+        }
+    }
+
+    #[test]
+    fn most_memory_ignores_rejected_devices() {
+        let mut rejected = candidate(0, vk::PhysicalDeviceType::DISCRETE_GPU, 16 << 30);
+        rejected.queue_family_index = None;
+        let selector =
+            DeviceSelector::default().with_preferences(&[DevicePreference::MostDeviceLocalMemory]);
+        let report = selector.select(vec![
+            rejected,
+            candidate(1, vk::PhysicalDeviceType::INTEGRATED_GPU, 2 << 30),
+            candidate(2, vk::PhysicalDeviceType::INTEGRATED_GPU, 4 << 30),
+        ]);
+        assert_eq!(report.candidates[2].score.as_ref().unwrap(), &[true]);
+        assert_eq!(report.candidates[1].score.as_ref().unwrap(), &[false]);
+        assert_eq!(report.selected().unwrap().index, 2);
+    }
+
+    #[test]
+    fn earlier_preferences_win_with_many_preferences() {
+        // More preferences than bits in a `u32`
+        let mut preferences = vec![DevicePreference::Index(1)];
+        preferences.extend((0..40).map(|_| DevicePreference::Index(0)));
+        let selector = DeviceSelector::default().with_preferences(&preferences);
+        let report = selector.select(vec![
+            candidate(0, vk::PhysicalDeviceType::DISCRETE_GPU, 1 << 30),
+            candidate(1, vk::PhysicalDeviceType::DISCRETE_GPU, 1 << 30),
+        ]);
+        assert_eq!(report.selected().unwrap().index, 1);
+    }
+
+    #[test]
+    fn surface_support_errors_are_rejections() {
+        let mut failed = candidate(0, vk::PhysicalDeviceType::DISCRETE_GPU, 1 << 30);
+        failed.queue_family_index = None;
+        failed.surface_support_error = Some(vk::Result::ERROR_SURFACE_LOST_KHR);
+        let report = DeviceSelector::default().select(vec![
+            failed,
+            candidate(1, vk::PhysicalDeviceType::INTEGRATED_GPU, 1 << 30),
+        ]);
+        assert_eq!(
+            report.candidates[0].score.as_ref().unwrap_err(),
+            &[RejectionReason::SurfaceSupportQueryFailed(
+                vk::Result::ERROR_SURFACE_LOST_KHR
+            )]
+        );
+        assert_eq!(report.selected().unwrap().index, 1);
+    }
+}
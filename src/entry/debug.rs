@@ -1,4 +1,4 @@
-use anyhow::Result;
+use crate::error::Result;
 use std::{borrow::Cow, ffi::CStr};
 
 use ash::vk;
@@ -1,11 +1,14 @@
 mod debug;
 
-use crate::ApplicationInfo;
-use anyhow::Result;
+use crate::{error::Result, ApplicationInfo};
 use ash::vk;
 use debug::DebugUtils;
 use raw_window_handle::HasRawWindowHandle;
-use std::ffi::{CStr, CString};
+use std::{
+    ffi::{CStr, CString},
+    ops::Deref,
+    sync::Arc,
+};
 
 pub struct InstanceInfo {
     debug_layers: bool,
@@ -40,17 +43,64 @@ impl InstanceInfo {
     }
 }
 
-pub struct Entry {
+pub struct EntryInner {
     pub(crate) entry: ash::Entry,
     pub(crate) instance: ash::Instance,
+    // Highest Vulkan version the application asked for
+    pub(crate) api_version: u32,
     debug_utils: Option<DebugUtils>,
 }
 
+impl Drop for EntryInner {
+    fn drop(&mut self) {
+        unsafe {
+            if let Some(debug_utils) = &self.debug_utils {
+                debug_utils.clean();
+            }
+            self.instance.destroy_instance(None);
+        }
+    }
+}
+
+// Cheap to clone. The instance is destroyed once the last clone, and every device and surface
+// created from it, has been dropped.
+#[derive(Clone)]
+pub struct Entry {
+    inner: Arc<EntryInner>,
+}
+
+impl Deref for Entry {
+    type Target = EntryInner;
+
+    fn deref(&self) -> &Self::Target {
+        &self.inner
+    }
+}
+
 impl Entry {
     pub fn new(
         application_info: ApplicationInfo,
         instance_info: InstanceInfo,
         window_handle: &dyn HasRawWindowHandle,
+    ) -> Result<Self> {
+        Self::with_extensions(
+            application_info,
+            instance_info,
+            ash_window::enumerate_required_extensions(window_handle)?,
+        )
+    }
+
+    pub fn new_headless(
+        application_info: ApplicationInfo,
+        instance_info: InstanceInfo,
+    ) -> Result<Self> {
+        Self::with_extensions(application_info, instance_info, &[])
+    }
+
+    fn with_extensions(
+        application_info: ApplicationInfo,
+        instance_info: InstanceInfo,
+        window_extensions: &[*const std::os::raw::c_char],
     ) -> Result<Self> {
         let entry = ash::Entry::linked();
         let application_info: vk::ApplicationInfo = application_info.into();
@@ -66,7 +116,7 @@ impl Entry {
         let extensions_raw = extensions
             .iter()
             .map(|extension| extension.as_ptr())
-            .chain(ash_window::enumerate_required_extensions(window_handle)?.to_vec())
+            .chain(window_extensions.iter().copied())
             .collect::<Vec<*const std::os::raw::c_char>>();
 
         let create_info = vk::InstanceCreateInfo::builder()
@@ -83,16 +133,12 @@ impl Entry {
         };
 
         Ok(Self {
-            entry,
-            instance,
-            debug_utils,
+            inner: Arc::new(EntryInner {
+                entry,
+                instance,
+                api_version: application_info.api_version,
+                debug_utils,
+            }),
         })
     }
-
-    pub unsafe fn clean(&self) {
-        if let Some(debug_utils) = &self.debug_utils {
-            debug_utils.clean();
-        }
-        self.instance.destroy_instance(None);
-    }
 }
@@ -0,0 +1,86 @@
+use crate::{
+    descriptors::DescriptorError,
+    device::DeviceCreationError,
+    frame::FrameError,
+    pipeline::{PipelineCacheError, PipelineCreationError},
+    render_graph::RenderGraphError,
+    render_pass::{DynamicRenderingError, RenderPassError},
+    resources::{BufferCreationError, ImageCreationError, ImageReadbackError, MemoryCopyError},
+    shader::reflection::ReflectionError,
+    sync::TimelineSemaphoreError,
+};
+use ash::vk;
+use thiserror::Error;
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("vulkan call failed: {0}")]
+    Vulkan(#[from] vk::Result),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Image(#[from] image::ImageError),
+    #[error(transparent)]
+    Reflection(#[from] ReflectionError),
+    #[error(transparent)]
+    Descriptor(#[from] DescriptorError),
+    #[error(transparent)]
+    DeviceCreation(#[from] DeviceCreationError),
+    #[error(transparent)]
+    BufferCreation(#[from] BufferCreationError),
+    #[error(transparent)]
+    MemoryCopy(#[from] MemoryCopyError),
+    #[error(transparent)]
+    ImageCreation(#[from] ImageCreationError),
+    #[error(transparent)]
+    ImageReadback(#[from] ImageReadbackError),
+    #[error(transparent)]
+    PipelineCreation(#[from] PipelineCreationError),
+    #[error(transparent)]
+    PipelineCache(#[from] PipelineCacheError),
+    #[error(transparent)]
+    Frame(#[from] FrameError),
+    #[error(transparent)]
+    TimelineSemaphore(#[from] TimelineSemaphoreError),
+    #[error(transparent)]
+    RenderGraph(#[from] RenderGraphError),
+    #[error(transparent)]
+    DynamicRendering(#[from] DynamicRenderingError),
+    #[error(transparent)]
+    RenderPass(#[from] RenderPassError),
+    #[cfg(feature = "testing")]
+    #[error(transparent)]
+    GoldenImage(#[from] crate::testing::GoldenImageError),
+}
+
+impl Error {
+    // The Vulkan result behind this error, if it came from a Vulkan call
+    pub fn vk_result(&self) -> Option<vk::Result> {
+        match self {
+            Error::Vulkan(result) => Some(*result),
+            Error::PipelineCreation(PipelineCreationError::CouldNotCreatePipelines(result)) => {
+                Some(*result)
+            }
+            _ => None,
+        }
+    }
+
+    // The device can't be used anymore, everything created from it has to be recreated
+    pub fn is_device_lost(&self) -> bool {
+        self.vk_result() == Some(vk::Result::ERROR_DEVICE_LOST)
+    }
+
+    pub fn is_out_of_memory(&self) -> bool {
+        matches!(
+            self.vk_result(),
+            Some(
+                vk::Result::ERROR_OUT_OF_HOST_MEMORY
+                    | vk::Result::ERROR_OUT_OF_DEVICE_MEMORY
+                    | vk::Result::ERROR_OUT_OF_POOL_MEMORY
+                    | vk::Result::ERROR_FRAGMENTED_POOL
+            )
+        )
+    }
+}
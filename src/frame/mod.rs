@@ -0,0 +1,181 @@
+use crate::{
+    context::Context,
+    device::Device,
+    error::Result,
+    pipeline::PipelineStages,
+    swapchain::{Swapchain, SwapchainStatus},
+    sync::{Fence, Semaphore},
+};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum FrameError {
+    #[error("a frame manager needs at least one frame in flight")]
+    NoFramesInFlight,
+    #[error("begin_frame was called while a frame was already being recorded")]
+    FrameAlreadyBegun,
+    #[error("end_frame was called without a matching begin_frame")]
+    FrameNotBegun,
+}
+
+pub struct Frame {
+    pub context: Context,
+    pub fence: Fence,
+    pub image_available: Semaphore,
+    pub render_finished: Semaphore,
+}
+
+impl Frame {
+    pub fn new(device: &Device) -> Result<Self> {
+        Ok(Self {
+            context: Context::new(device)?,
+            fence: Fence::new(device)?,
+            image_available: Semaphore::new(device)?,
+            render_finished: Semaphore::new(device)?,
+        })
+    }
+}
+
+pub struct FrameManager {
+    frames: Vec<Frame>,
+    frame_index: usize,
+    // Swapchain image acquired by the frame currently being recorded
+    present_index: Option<u32>,
+    // Last status reported by the swapchain when acquiring or presenting
+    swapchain_status: SwapchainStatus,
+    device: Device,
+}
+
+impl FrameManager {
+    pub fn new(device: &Device, frames_in_flight: usize) -> Result<Self> {
+        if frames_in_flight == 0 {
+            return Err(FrameError::NoFramesInFlight.into());
+        }
+        let frames = (0..frames_in_flight)
+            .map(|_| Frame::new(device))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Self {
+            frames,
+            frame_index: 0,
+            present_index: None,
+            swapchain_status: SwapchainStatus::Ok,
+            device: device.clone(),
+        })
+    }
+
+    pub fn frames_in_flight(&self) -> usize {
+        self.frames.len()
+    }
+
+    // Index of the current frame in `0..frames_in_flight`, useful to pick per-frame resources
+    pub fn frame_index(&self) -> usize {
+        self.frame_index
+    }
+
+    pub fn frame(&self) -> &Frame {
+        &self.frames[self.frame_index]
+    }
+
+    pub fn context(&self) -> &Context {
+        &self.frame().context
+    }
+
+    pub fn swapchain_status(&self) -> SwapchainStatus {
+        self.swapchain_status
+    }
+
+    // Recreates the swapchain if auto recreation is enabled and the last status asked for it.
+    // Returns `false` if the swapchain is still unusable, e.g. the window is minimized.
+    fn recreate_swapchain_if_needed(
+        &mut self,
+        device: &Device,
+        swapchain: &mut Swapchain,
+    ) -> Result<bool> {
+        if !swapchain.auto_recreate() || !self.swapchain_status.needs_recreate() {
+            return Ok(true);
+        }
+        let frame = &self.frames[self.frame_index];
+        if swapchain.recreate(device, &frame.context, &frame.fence)? {
+            self.swapchain_status = SwapchainStatus::Ok;
+            Ok(true)
+        } else {
+            Ok(false)
+        }
+    }
+
+    // Waits until the GPU is done with this frame's previous submission, acquires the next
+    // swapchain image and starts recording. Returns the acquired present index, or `None` if
+    // the frame should be skipped; `swapchain_status` tells why. With auto recreation enabled on
+    // the swapchain, out of date and suboptimal swapchains are recreated here, and `None` is
+    // only returned while the window is minimized or the surface is lost.
+    pub fn begin_frame(
+        &mut self,
+        device: &Device,
+        swapchain: &mut Swapchain,
+    ) -> Result<Option<u32>> {
+        if self.present_index.is_some() {
+            return Err(FrameError::FrameAlreadyBegun.into());
+        }
+        // The fence is only reset once an image was acquired, so a failed acquire can't leave
+        // it unsignaled forever
+        self.frames[self.frame_index].fence.wait(device)?;
+        device.destroy_retired()?;
+        if !self.recreate_swapchain_if_needed(device, swapchain)? {
+            return Ok(None);
+        }
+
+        let (mut present_index, mut status) =
+            swapchain.acquire_next_image_index(&self.frames[self.frame_index].image_available)?;
+        if status == SwapchainStatus::OutOfDate && swapchain.auto_recreate() {
+            self.swapchain_status = status;
+            if !self.recreate_swapchain_if_needed(device, swapchain)? {
+                return Ok(None);
+            }
+            (present_index, status) = swapchain
+                .acquire_next_image_index(&self.frames[self.frame_index].image_available)?;
+        }
+        self.swapchain_status = status;
+        let present_index = match present_index {
+            Some(present_index) => present_index,
+            None => return Ok(None),
+        };
+
+        let frame = &self.frames[self.frame_index];
+        frame.context.begin(device, &frame.fence)?;
+        self.present_index = Some(present_index);
+        Ok(Some(present_index))
+    }
+
+    // Submits the recorded commands and presents the image acquired in `begin_frame`. An out of
+    // date or suboptimal swapchain is recreated at the next `begin_frame` in auto mode.
+    pub fn end_frame(&mut self, device: &Device, swapchain: &Swapchain) -> Result<SwapchainStatus> {
+        let present_index = self.present_index.take().ok_or(FrameError::FrameNotBegun)?;
+        let frame = &self.frames[self.frame_index];
+        frame.context.end(
+            device,
+            &[&frame.image_available],
+            &[&frame.render_finished],
+            &frame.fence,
+            &[PipelineStages::ColorAttachmentOutput],
+        )?;
+        self.swapchain_status =
+            swapchain.present(device, &[&frame.render_finished], &[present_index])?;
+        self.frame_index = (self.frame_index + 1) % self.frames.len();
+        Ok(self.swapchain_status)
+    }
+
+    // Blocks until every frame in flight has finished executing
+    pub fn wait_all(&self, device: &Device) -> Result<()> {
+        for frame in &self.frames {
+            frame.fence.wait(device)?;
+        }
+        Ok(())
+    }
+}
+
+// Frames still in flight may be using the semaphores and command buffers
+impl Drop for FrameManager {
+    fn drop(&mut self) {
+        self.wait_all(&self.device).ok();
+    }
+}
@@ -3,40 +3,73 @@ mod context;
 mod descriptors;
 mod device;
 mod entry;
+mod error;
+mod frame;
 mod mem;
 mod pipeline;
 mod push_constant;
+mod render_graph;
 mod render_pass;
 mod resources;
 mod shader;
 mod surface;
 mod swapchain;
 mod sync;
+#[cfg(feature = "testing")]
+pub mod testing;
 
 pub use {
     application::{ApiVersion, ApplicationInfo},
     context::Context,
     descriptors::{
-        new_descriptor_image_info, BindingDesc, DescriptorBufferInfo, DescriptorInfo,
-        DescriptorPool, DescriptorSet, DescriptorType, ShaderStage,
+        new_descriptor_image_info, new_descriptor_input_attachment_info,
+        new_descriptor_sampled_image_info, new_descriptor_sampler_info,
+        new_descriptor_storage_image_info, BindingDesc, DescriptorBufferInfo, DescriptorError,
+        DescriptorInfo, DescriptorPool, DescriptorSet, DescriptorType, ShaderStage,
+    },
+    device::{
+        CandidateReport, Device, DeviceCandidate, DeviceCreationError, DeviceFeatures,
+        DevicePreference, DeviceSelector, DeviceType, Queue, QueueType, RejectionReason, Retire,
+        RetiredResource, SelectionReport,
     },
-    device::Device,
     entry::{Entry, InstanceInfo},
-    mem::as_u8_slice,
+    error::{Error, Result},
+    frame::{Frame, FrameError, FrameManager},
+    mem::{as_u8_slice, Allocation, AllocationKind, HeapUsage, MemoryLocation},
     pipeline::{
-        graphics_pipeline::{GraphicsPipeline, VertexInputData},
-        PipelineStages,
+        graphics_pipeline::VertexInputData, BlendMode, CompareOp, ComputePipeline, CullMode,
+        FrontFace, GraphicsPipeline, GraphicsPipelineDesc, Pipeline, PipelineCache,
+        PipelineCacheError, PipelineCreationError, PipelineStages, PolygonMode, PrimitiveTopology,
     },
     push_constant::PushConstant,
-    render_pass::{ClearValue, RenderPass, RenderPassAttachment},
+    render_graph::{
+        BufferHandle, CompiledRenderGraph, ImageHandle, Pass, PassResources, RenderGraph,
+        RenderGraphError, TransientImageDesc,
+    },
+    render_pass::{
+        AttachmentDesc, AttachmentImage, AttachmentLoadOp, AttachmentStoreOp, ClearValue,
+        DynamicRenderingError, RenderPass, RenderPassAttachment, RenderPassDesc, RenderPassError,
+        RenderingAttachment, RenderingDesc, RenderingFormats, SubpassDependency, SubpassDesc,
+    },
     resources::{
-        Buffer, BufferType, Image, ImageLayout, ImageResolution, ImageType, Sampler, SamplerFilter,
-        SamplerWrapMode,
+        Buffer, BufferCreationError, BufferType, DepthFormat, Image, ImageCreationError,
+        ImageLayout, ImageReadbackError, ImageResolution, ImageType, MemoryCopyError, Sampler,
+        SamplerFilter, SamplerWrapMode,
+    },
+    shader::{
+        graphics_program::GraphicsProgram,
+        reflection::{
+            DescriptorBinding, EntryPoint, ReflectionError, ShaderReflection, VertexInput,
+        },
+        Shader,
     },
-    shader::{graphics_program::GraphicsProgram, Shader},
     surface::Surface,
-    swapchain::Swapchain,
-    sync::{AccessMask, Fence, ImageMemoryBarrier, Semaphore},
+    swapchain::{Swapchain, SwapchainStatus},
+    sync::{
+        AccessMask, BufferMemoryBarrier, DependencyFlags, Fence, ImageMemoryBarrier, MemoryBarrier,
+        PipelineBarrier, QueueOwnershipTransfer, ResourceTransitions, ResourceUsage, Semaphore,
+        TimelineSemaphore, TimelineSemaphoreError, TimelineValue,
+    },
 };
 
 pub use ash;
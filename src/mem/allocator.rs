@@ -0,0 +1,415 @@
+use crate::{error::Result, mem::MemoryMappablePointer};
+use ash::vk;
+
+const DEFAULT_BLOCK_SIZE: u64 = 64 * 1024 * 1024;
+
+// Buffers and linear images must not share a `bufferImageGranularity` page with optimal images
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum AllocationKind {
+    Linear,
+    Optimal,
+}
+
+#[derive(Debug)]
+pub struct Allocation {
+    pub memory: vk::DeviceMemory,
+    pub offset: u64,
+    pub size: u64,
+    memory_type_index: u32,
+    block_index: usize,
+    ptr: Option<MemoryMappablePointer>,
+}
+
+impl Allocation {
+    pub fn ptr(&self) -> Option<&MemoryMappablePointer> {
+        self.ptr.as_ref()
+    }
+
+    // Another handle to the same memory, for resources that alias it. Only the original should be
+    // freed.
+    pub(crate) fn alias(&self) -> Self {
+        Self {
+            memory: self.memory,
+            offset: self.offset,
+            size: self.size,
+            memory_type_index: self.memory_type_index,
+            block_index: self.block_index,
+            ptr: self.ptr.as_ref().map(|ptr| unsafe { ptr.add(0) }),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct HeapUsage {
+    pub heap_index: u32,
+    pub heap_size: u64,
+    pub block_count: usize,
+    pub allocation_count: usize,
+    // Bytes reserved from the driver
+    pub reserved_bytes: u64,
+    // Bytes handed out to resources, including alignment padding
+    pub used_bytes: u64,
+}
+
+#[derive(Debug, Copy, Clone)]
+struct Region {
+    offset: u64,
+    size: u64,
+    kind: AllocationKind,
+}
+
+struct MemoryBlock {
+    memory: vk::DeviceMemory,
+    size: u64,
+    ptr: Option<MemoryMappablePointer>,
+    // Sorted by offset
+    regions: Vec<Region>,
+}
+
+fn align_up(value: u64, alignment: u64) -> u64 {
+    if alignment <= 1 {
+        value
+    } else {
+        value.div_ceil(alignment) * alignment
+    }
+}
+
+fn same_page(end_of_first: u64, start_of_second: u64, granularity: u64) -> bool {
+    granularity > 1 && (end_of_first - 1) / granularity == start_of_second / granularity
+}
+
+impl MemoryBlock {
+    // First-fit search through the gaps between used regions
+    fn find_offset(
+        &self,
+        size: u64,
+        alignment: u64,
+        kind: AllocationKind,
+        granularity: u64,
+    ) -> Option<(usize, u64)> {
+        let mut cursor = 0;
+        let mut previous: Option<&Region> = None;
+        for index in 0..=self.regions.len() {
+            let next = self.regions.get(index);
+            let gap_end = next.map_or(self.size, |region| region.offset);
+
+            let mut offset = align_up(cursor, alignment);
+            if let Some(previous) = previous {
+                if previous.kind != kind
+                    && same_page(previous.offset + previous.size, offset, granularity)
+                {
+                    offset = align_up(offset, granularity);
+                }
+            }
+            let end = offset + size;
+            let conflicts_with_next = next
+                .is_some_and(|next| next.kind != kind && same_page(end, next.offset, granularity));
+            if end <= gap_end && !conflicts_with_next {
+                return Some((index, offset));
+            }
+
+            if let Some(next) = next {
+                cursor = next.offset + next.size;
+                previous = Some(next);
+            }
+        }
+        None
+    }
+
+    fn used_bytes(&self) -> u64 {
+        self.regions.iter().map(|region| region.size).sum()
+    }
+}
+
+pub struct Allocator {
+    memory_properties: vk::PhysicalDeviceMemoryProperties,
+    buffer_image_granularity: u64,
+    // Indexed by memory type, freed blocks leave a `None` behind so indices stay stable
+    blocks: Vec<Vec<Option<MemoryBlock>>>,
+}
+
+impl Allocator {
+    pub(crate) fn new(
+        memory_properties: vk::PhysicalDeviceMemoryProperties,
+        limits: &vk::PhysicalDeviceLimits,
+    ) -> Self {
+        Self {
+            memory_properties,
+            buffer_image_granularity: limits.buffer_image_granularity,
+            blocks: (0..memory_properties.memory_type_count)
+                .map(|_| Vec::new())
+                .collect(),
+        }
+    }
+
+    fn block_size(&self, memory_type_index: u32) -> u64 {
+        let heap_index = self.memory_properties.memory_types[memory_type_index as usize].heap_index;
+        let heap_size = self.memory_properties.memory_heaps[heap_index as usize].size;
+        // Small heaps, like the 256MB BAR heap, should not be eaten by a few blocks
+        DEFAULT_BLOCK_SIZE.min(heap_size / 8)
+    }
+
+    pub(crate) fn allocate(
+        &mut self,
+        device: &ash::Device,
+        requirements: &vk::MemoryRequirements,
+        memory_type_index: u32,
+        kind: AllocationKind,
+    ) -> Result<Allocation> {
+        let granularity = self.buffer_image_granularity;
+        let blocks = &mut self.blocks[memory_type_index as usize];
+        let found = blocks.iter().enumerate().find_map(|(block_index, block)| {
+            block.as_ref().and_then(|block| {
+                block
+                    .find_offset(requirements.size, requirements.alignment, kind, granularity)
+                    .map(|(region_index, offset)| (block_index, region_index, offset))
+            })
+        });
+
+        let (block_index, region_index, offset) = match found {
+            Some(found) => found,
+            None => {
+                // Requests larger than a block get a dedicated block of their own
+                let block_size = self.block_size(memory_type_index).max(requirements.size);
+                let block = self.allocate_block(device, block_size, memory_type_index)?;
+                let blocks = &mut self.blocks[memory_type_index as usize];
+                let block_index = match blocks.iter().position(|block| block.is_none()) {
+                    Some(free_index) => {
+                        blocks[free_index] = Some(block);
+                        free_index
+                    }
+                    None => {
+                        blocks.push(Some(block));
+                        blocks.len() - 1
+                    }
+                };
+                (block_index, 0, 0)
+            }
+        };
+
+        let block = self.blocks[memory_type_index as usize][block_index]
+            .as_mut()
+            .unwrap();
+        block.regions.insert(
+            region_index,
+            Region {
+                offset,
+                size: requirements.size,
+                kind,
+            },
+        );
+        let ptr = block
+            .ptr
+            .as_ref()
+            .map(|ptr| unsafe { ptr.add(offset as usize) });
+
+        Ok(Allocation {
+            memory: block.memory,
+            offset,
+            size: requirements.size,
+            memory_type_index,
+            block_index,
+            ptr,
+        })
+    }
+
+    fn allocate_block(
+        &self,
+        device: &ash::Device,
+        size: u64,
+        memory_type_index: u32,
+    ) -> Result<MemoryBlock> {
+        let allocate_info = vk::MemoryAllocateInfo {
+            allocation_size: size,
+            memory_type_index,
+            ..Default::default()
+        };
+        let memory = unsafe { device.allocate_memory(&allocate_info, None)? };
+
+        // Host visible blocks stay mapped for their whole lifetime, allocations get an offset into it
+        let property_flags =
+            self.memory_properties.memory_types[memory_type_index as usize].property_flags;
+        let ptr = if property_flags.contains(vk::MemoryPropertyFlags::HOST_VISIBLE) {
+            unsafe {
+                let ptr = device.map_memory(memory, 0, size, vk::MemoryMapFlags::empty())?;
+                Some(MemoryMappablePointer::from_raw_ptr(ptr))
+            }
+        } else {
+            None
+        };
+
+        Ok(MemoryBlock {
+            memory,
+            size,
+            ptr,
+            regions: Vec::new(),
+        })
+    }
+
+    // One empty block per memory type is kept around, so allocating and freeing in a loop doesn't
+    // go back to the driver every time. Dedicated blocks for large requests are always released.
+    pub(crate) unsafe fn free(&mut self, device: &ash::Device, allocation: &Allocation) {
+        let block_size = self.block_size(allocation.memory_type_index);
+        let blocks = &mut self.blocks[allocation.memory_type_index as usize];
+        let Some(block) = blocks[allocation.block_index].as_mut() else {
+            return;
+        };
+        block
+            .regions
+            .retain(|region| region.offset != allocation.offset);
+        if !block.regions.is_empty() {
+            return;
+        }
+
+        let dedicated = block.size > block_size;
+        let other_empty_block = blocks.iter().enumerate().any(|(index, block)| {
+            index != allocation.block_index
+                && block.as_ref().is_some_and(|block| block.regions.is_empty())
+        });
+        if dedicated || other_empty_block {
+            if let Some(block) = blocks[allocation.block_index].take() {
+                device.free_memory(block.memory, None);
+            }
+        }
+    }
+
+    pub fn usage(&self) -> Vec<HeapUsage> {
+        let mut usage = self.memory_properties.memory_heaps
+            [..self.memory_properties.memory_heap_count as usize]
+            .iter()
+            .enumerate()
+            .map(|(heap_index, heap)| HeapUsage {
+                heap_index: heap_index as u32,
+                heap_size: heap.size,
+                block_count: 0,
+                allocation_count: 0,
+                reserved_bytes: 0,
+                used_bytes: 0,
+            })
+            .collect::<Vec<_>>();
+
+        for (memory_type_index, blocks) in self.blocks.iter().enumerate() {
+            let heap_index = self.memory_properties.memory_types[memory_type_index].heap_index;
+            let heap_usage = &mut usage[heap_index as usize];
+            for block in blocks.iter().flatten() {
+                heap_usage.block_count += 1;
+                heap_usage.allocation_count += block.regions.len();
+                heap_usage.reserved_bytes += block.size;
+                heap_usage.used_bytes += block.used_bytes();
+            }
+        }
+        usage
+    }
+
+    pub(crate) unsafe fn clean(&mut self, device: &ash::Device) {
+        for block in self
+            .blocks
+            .iter_mut()
+            .flat_map(|blocks| blocks.drain(..))
+            .flatten()
+        {
+            device.free_memory(block.memory, None);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GRANULARITY: u64 = 1024;
+
+    fn block(size: u64, regions: &[(u64, u64, AllocationKind)]) -> MemoryBlock {
+        MemoryBlock {
+            memory: vk::DeviceMemory::null(),
+            size,
+            ptr: None,
+            regions: regions
+                .iter()
+                .map(|&(offset, size, kind)| Region { offset, size, kind })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn align_up_rounds_to_alignment() {
+        assert_eq!(align_up(0, 256), 0);
+        assert_eq!(align_up(1, 256), 256);
+        assert_eq!(align_up(256, 256), 256);
+        assert_eq!(align_up(257, 256), 512);
+        assert_eq!(align_up(13, 0), 13);
+        assert_eq!(align_up(13, 1), 13);
+    }
+
+    #[test]
+    fn same_page_uses_last_byte_of_first_region() {
+        assert!(same_page(100, 200, GRANULARITY));
+        assert!(!same_page(1024, 1024, GRANULARITY));
+        assert!(same_page(1025, 2047, GRANULARITY));
+        assert!(!same_page(1025, 2048, GRANULARITY));
+        assert!(!same_page(100, 200, 1));
+    }
+
+    #[test]
+    fn find_offset_is_first_fit() {
+        let block = block(
+            4096,
+            &[
+                (0, 256, AllocationKind::Linear),
+                (512, 256, AllocationKind::Linear),
+            ],
+        );
+        assert_eq!(
+            block.find_offset(256, 256, AllocationKind::Linear, GRANULARITY),
+            Some((1, 256))
+        );
+        assert_eq!(
+            block.find_offset(512, 256, AllocationKind::Linear, GRANULARITY),
+            Some((2, 768))
+        );
+        assert_eq!(
+            block.find_offset(4096, 256, AllocationKind::Linear, GRANULARITY),
+            None
+        );
+    }
+
+    #[test]
+    fn find_offset_aligns_within_gaps() {
+        let block = block(4096, &[(0, 100, AllocationKind::Linear)]);
+        assert_eq!(
+            block.find_offset(64, 64, AllocationKind::Linear, GRANULARITY),
+            Some((1, 128))
+        );
+    }
+
+    #[test]
+    fn find_offset_keeps_different_kinds_on_separate_pages() {
+        let block = block(
+            8192,
+            &[
+                (0, 256, AllocationKind::Linear),
+                (2048, 256, AllocationKind::Linear),
+            ],
+        );
+        // After the first linear region, an optimal region has to move to the next page
+        assert_eq!(
+            block.find_offset(256, 256, AllocationKind::Optimal, GRANULARITY),
+            Some((1, 1024))
+        );
+        // Doesn't fit before the second linear region without sharing its page
+        assert_eq!(
+            block.find_offset(1100, 256, AllocationKind::Optimal, GRANULARITY),
+            Some((2, 3072))
+        );
+        // The same kind can share pages
+        assert_eq!(
+            block.find_offset(256, 256, AllocationKind::Linear, GRANULARITY),
+            Some((1, 256))
+        );
+        // Without a granularity constraint kinds can be packed together
+        assert_eq!(
+            block.find_offset(256, 256, AllocationKind::Optimal, 1),
+            Some((1, 256))
+        );
+    }
+}
@@ -1,9 +1,27 @@
+mod allocator;
+
+pub use allocator::{Allocation, AllocationKind, Allocator, HeapUsage};
 use ash::vk;
 
 pub fn find_memory_type_index(
     memory_req: &vk::MemoryRequirements,
     memory_prop: &vk::PhysicalDeviceMemoryProperties,
     flags: vk::MemoryPropertyFlags,
+) -> Option<u32> {
+    find_memory_type_index_without(
+        memory_req,
+        memory_prop,
+        flags,
+        vk::MemoryPropertyFlags::empty(),
+    )
+}
+
+// Same as `find_memory_type_index`, skipping memory types that have any of `excluded`
+fn find_memory_type_index_without(
+    memory_req: &vk::MemoryRequirements,
+    memory_prop: &vk::PhysicalDeviceMemoryProperties,
+    flags: vk::MemoryPropertyFlags,
+    excluded: vk::MemoryPropertyFlags,
 ) -> Option<u32> {
     memory_prop.memory_types[..memory_prop.memory_type_count as _]
         .iter()
@@ -11,10 +29,75 @@ pub fn find_memory_type_index(
         .find(|(index, memory_type)| {
             (1 << index) & memory_req.memory_type_bits != 0
                 && memory_type.property_flags & flags == flags
+                && !memory_type.property_flags.intersects(excluded)
         })
         .map(|(index, _memory_type)| index as _)
 }
 
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum MemoryLocation {
+    // Fastest for the GPU, not visible to the host, filled through a staging buffer
+    GpuOnly,
+    // Host writes, GPU reads. Prefers the small device local host visible heap when there is one,
+    // for data updated every frame like uniforms
+    CpuToGpu,
+    // Host writes, only read by transfers. Used for staging, stays out of device local memory
+    CpuOnly,
+    // GPU writes, host reads. Used for readback
+    GpuToCpu,
+}
+
+impl MemoryLocation {
+    fn required_flags(self) -> vk::MemoryPropertyFlags {
+        match self {
+            MemoryLocation::GpuOnly => vk::MemoryPropertyFlags::DEVICE_LOCAL,
+            MemoryLocation::CpuToGpu | MemoryLocation::CpuOnly | MemoryLocation::GpuToCpu => {
+                vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT
+            }
+        }
+    }
+
+    fn preferred_flags(self) -> vk::MemoryPropertyFlags {
+        match self {
+            MemoryLocation::GpuOnly => vk::MemoryPropertyFlags::empty(),
+            MemoryLocation::CpuToGpu => vk::MemoryPropertyFlags::DEVICE_LOCAL,
+            MemoryLocation::CpuOnly => vk::MemoryPropertyFlags::empty(),
+            MemoryLocation::GpuToCpu => vk::MemoryPropertyFlags::HOST_CACHED,
+        }
+    }
+
+    // Only used when nothing else fits
+    fn avoided_flags(self) -> vk::MemoryPropertyFlags {
+        match self {
+            MemoryLocation::CpuOnly => vk::MemoryPropertyFlags::DEVICE_LOCAL,
+            MemoryLocation::GpuOnly | MemoryLocation::CpuToGpu | MemoryLocation::GpuToCpu => {
+                vk::MemoryPropertyFlags::empty()
+            }
+        }
+    }
+
+    pub fn is_host_visible(self) -> bool {
+        self.required_flags()
+            .contains(vk::MemoryPropertyFlags::HOST_VISIBLE)
+    }
+
+    pub(crate) fn find_memory_type_index(
+        self,
+        memory_req: &vk::MemoryRequirements,
+        memory_prop: &vk::PhysicalDeviceMemoryProperties,
+    ) -> Option<u32> {
+        let (required, preferred, avoided) = (
+            self.required_flags(),
+            self.preferred_flags(),
+            self.avoided_flags(),
+        );
+        find_memory_type_index_without(memory_req, memory_prop, required | preferred, avoided)
+            .or_else(|| find_memory_type_index_without(memory_req, memory_prop, required, avoided))
+            .or_else(|| find_memory_type_index(memory_req, memory_prop, required | preferred))
+            .or_else(|| find_memory_type_index(memory_req, memory_prop, required))
+    }
+}
+
 pub unsafe fn mem_copy<T: Copy>(ptr: *mut std::ffi::c_void, data: &[T]) {
     let elem_size = std::mem::size_of::<T>() as vk::DeviceSize;
     let size = data.len() as vk::DeviceSize * elem_size;
@@ -22,6 +105,7 @@ pub unsafe fn mem_copy<T: Copy>(ptr: *mut std::ffi::c_void, data: &[T]) {
     align.copy_from_slice(data);
 }
 
+#[derive(Debug)]
 pub struct MemoryMappablePointer(*mut std::ffi::c_void);
 unsafe impl Send for MemoryMappablePointer {}
 unsafe impl Sync for MemoryMappablePointer {}
@@ -31,9 +115,17 @@ impl MemoryMappablePointer {
         Self(ptr)
     }
 
+    pub unsafe fn add(&self, offset: usize) -> Self {
+        Self(self.0.add(offset))
+    }
+
     pub fn mem_copy<T: Copy>(&self, data: &[T]) {
         unsafe { mem_copy(self.0, data) };
     }
+
+    pub unsafe fn read_bytes(&self, size: usize) -> Vec<u8> {
+        std::slice::from_raw_parts(self.0 as *const u8, size).to_vec()
+    }
 }
 
 pub fn as_u8_slice<T: Sized>(p: &T) -> &[u8] {
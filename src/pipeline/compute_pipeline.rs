@@ -0,0 +1,122 @@
+use crate::{
+    context::Context,
+    descriptors::DescriptorSet,
+    device::Device,
+    error::Result,
+    pipeline::{create_pipeline_layout, Pipeline, PipelineCache},
+    push_constant::PushConstant,
+    shader::Shader,
+};
+use ash::vk;
+use std::ffi::CString;
+
+pub struct ComputePipeline {
+    pub layout: vk::PipelineLayout,
+    pub pipeline: vk::Pipeline,
+    device: Device,
+}
+
+impl ComputePipeline {
+    pub fn new(
+        device: &Device,
+        shader: &Shader,
+        descriptor_sets: &[&DescriptorSet],
+        push_constants: &[&PushConstant],
+        pipeline_cache: Option<&PipelineCache>,
+    ) -> Result<Self> {
+        let main_function_name = CString::new("main").unwrap();
+        let stage = vk::PipelineShaderStageCreateInfo::builder()
+            .stage(vk::ShaderStageFlags::COMPUTE)
+            .module(shader.module)
+            .name(&main_function_name)
+            .build();
+
+        // Shaders that can't be reflected are trusted to match the push constants
+        if let Ok(reflection) = shader.reflection() {
+            reflection.validate_push_constants(push_constants)?;
+        }
+        let layout = create_pipeline_layout(device, descriptor_sets, push_constants)?;
+
+        let pipeline_create_info = [vk::ComputePipelineCreateInfo::builder()
+            .stage(stage)
+            .layout(layout)
+            .build()];
+
+        let pipeline = match unsafe {
+            device.device.create_compute_pipelines(
+                pipeline_cache.map_or_else(vk::PipelineCache::null, |cache| cache.cache),
+                &pipeline_create_info,
+                None,
+            )
+        } {
+            Ok(pipelines) => pipelines[0],
+            Err((_, err)) => {
+                unsafe { device.device.destroy_pipeline_layout(layout, None) };
+                return Err(err.into());
+            }
+        };
+
+        Ok(Self {
+            layout,
+            pipeline,
+            device: device.clone(),
+        })
+    }
+
+    pub fn from_reflection(
+        device: &Device,
+        shader: &Shader,
+        descriptor_sets: &[&DescriptorSet],
+        pipeline_cache: Option<&PipelineCache>,
+    ) -> Result<Self> {
+        let reflection = shader.reflection()?;
+        reflection.validate_descriptor_sets(descriptor_sets)?;
+        let push_constants = reflection.push_constants()?;
+        let push_constants = push_constants.iter().collect::<Vec<_>>();
+        Self::new(
+            device,
+            shader,
+            descriptor_sets,
+            &push_constants,
+            pipeline_cache,
+        )
+    }
+
+    pub fn bind(&self, device: &Device, context: &Context) {
+        device.bind_pipeline(context, self);
+    }
+
+    pub fn bind_descriptor_set(
+        &self,
+        device: &Device,
+        context: &Context,
+        descriptor_set: &DescriptorSet,
+    ) {
+        device.bind_descriptor_sets(context, self, 0, &[descriptor_set]);
+    }
+}
+
+impl Drop for ComputePipeline {
+    fn drop(&mut self) {
+        unsafe {
+            self.device.device.destroy_pipeline(self.pipeline, None);
+            self.device
+                .device
+                .destroy_pipeline_layout(self.layout, None);
+        }
+    }
+}
+
+impl Pipeline for ComputePipeline {
+    fn raw(&self) -> vk::Pipeline {
+        self.pipeline
+    }
+
+    fn layout(&self) -> vk::PipelineLayout {
+        self.layout
+    }
+
+    fn bind_point(&self) -> vk::PipelineBindPoint {
+        vk::PipelineBindPoint::COMPUTE
+    }
+}
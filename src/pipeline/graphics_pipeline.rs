@@ -1,8 +1,13 @@
 use crate::{
-    context::Context, descriptors::DescriptorSet, device::Device, push_constant::PushConstant,
-    render_pass::RenderPass, shader::graphics_program::GraphicsProgram, swapchain::Swapchain,
+    context::Context,
+    descriptors::DescriptorSet,
+    device::Device,
+    error::Result,
+    pipeline::{create_pipeline_layout, GraphicsPipelineDesc, Pipeline, PipelineCache},
+    push_constant::PushConstant,
+    render_pass::{DynamicRenderingError, RenderPass, RenderingFormats},
+    shader::graphics_program::GraphicsProgram,
 };
-use anyhow::Result;
 use ash::vk;
 use std::ffi::{CStr, CString};
 use thiserror::Error;
@@ -23,26 +28,94 @@ impl VertexInputData {
 }
 
 #[derive(Debug, Error)]
-pub enum PipelineCrationError {
+pub enum PipelineCreationError {
     #[error("Could not create pipeline: {0}")]
     CouldNotCreatePipelines(vk::Result),
+    #[error("pipeline has {found} blend modes, but renders into {expected} color attachments")]
+    BlendModeCountMismatch { expected: usize, found: usize },
+    #[error("pipeline has {found:?} samples, but its subpass renders with {expected:?}")]
+    SampleCountMismatch {
+        expected: vk::SampleCountFlags,
+        found: vk::SampleCountFlags,
+    },
 }
 
 pub struct GraphicsPipeline {
     pub layout: vk::PipelineLayout,
     pub pipeline: vk::Pipeline,
+    device: Device,
+}
+
+// What a pipeline renders into
+#[derive(Copy, Clone)]
+enum RenderTarget<'a> {
+    RenderPass(&'a RenderPass),
+    // Dynamic rendering
+    Formats(&'a RenderingFormats),
 }
 
 impl GraphicsPipeline {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         device: &Device,
-        swapchain: &Swapchain,
         render_pass: &RenderPass,
         program: &GraphicsProgram,
         vertex_iput_state: Option<VertexInputData>,
         descriptor_sets: &[&DescriptorSet],
         push_constants: &[&PushConstant],
-        cull_back_faces: bool,
+        desc: &GraphicsPipelineDesc,
+        pipeline_cache: Option<&PipelineCache>,
+    ) -> Result<Self> {
+        Self::create(
+            device,
+            RenderTarget::RenderPass(render_pass),
+            program,
+            vertex_iput_state,
+            descriptor_sets,
+            push_constants,
+            desc,
+            pipeline_cache,
+        )
+    }
+
+    // For drawing between `Context::begin_rendering` and `Context::end_rendering`, into
+    // attachments of `formats`. Doesn't need to be recreated when the attachments are resized.
+    #[allow(clippy::too_many_arguments)]
+    pub fn with_rendering(
+        device: &Device,
+        formats: &RenderingFormats,
+        program: &GraphicsProgram,
+        vertex_iput_state: Option<VertexInputData>,
+        descriptor_sets: &[&DescriptorSet],
+        push_constants: &[&PushConstant],
+        desc: &GraphicsPipelineDesc,
+        pipeline_cache: Option<&PipelineCache>,
+    ) -> Result<Self> {
+        if !device.enabled_features().dynamic_rendering() {
+            return Err(DynamicRenderingError::NotEnabled.into());
+        }
+        Self::create(
+            device,
+            RenderTarget::Formats(formats),
+            program,
+            vertex_iput_state,
+            descriptor_sets,
+            push_constants,
+            desc,
+            pipeline_cache,
+        )
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn create(
+        device: &Device,
+        target: RenderTarget,
+        program: &GraphicsProgram,
+        vertex_iput_state: Option<VertexInputData>,
+        descriptor_sets: &[&DescriptorSet],
+        push_constants: &[&PushConstant],
+        desc: &GraphicsPipelineDesc,
+        pipeline_cache: Option<&PipelineCache>,
     ) -> Result<Self> {
         let main_function_name = CString::new("main").unwrap();
         let pipeline_shader_stages = [
@@ -57,80 +130,70 @@ impl GraphicsPipeline {
                 .name(&main_function_name)
                 .build(),
         ];
-        let viewports = [swapchain.viewport()];
-        let scissors = [swapchain.scissor()];
-
-        let input_assembly_info = vk::PipelineInputAssemblyStateCreateInfo::builder()
-            .topology(vk::PrimitiveTopology::TRIANGLE_LIST);
+        let input_assembly_info = desc.input_assembly_info();
+        // Viewport and scissor are dynamic state, only the counts matter here
         let viewport_info = vk::PipelineViewportStateCreateInfo::builder()
-            .scissors(&scissors)
-            .viewports(&viewports);
-        let rasterization_info = vk::PipelineRasterizationStateCreateInfo::builder()
-            .depth_clamp_enable(false)
-            .rasterizer_discard_enable(false)
-            .polygon_mode(vk::PolygonMode::FILL)
-            .cull_mode(if cull_back_faces {
-                vk::CullModeFlags::BACK
-            } else {
-                vk::CullModeFlags::NONE
-            })
-            .front_face(vk::FrontFace::COUNTER_CLOCKWISE)
-            .depth_bias_enable(false)
-            .line_width(1.0);
-        let stencil_op = vk::StencilOpState::builder()
-            .fail_op(vk::StencilOp::KEEP)
-            .pass_op(vk::StencilOp::KEEP)
-            .compare_op(vk::CompareOp::ALWAYS)
-            .build();
-        let depth_stencil_info = vk::PipelineDepthStencilStateCreateInfo::builder()
-            .depth_test_enable(false)
-            .depth_write_enable(false)
-            .depth_compare_op(vk::CompareOp::ALWAYS)
-            .depth_bounds_test_enable(false)
-            .stencil_test_enable(false)
-            .front(stencil_op)
-            .back(stencil_op);
-        let color_blend_attachments = [vk::PipelineColorBlendAttachmentState::builder()
-            .color_write_mask(
-                vk::ColorComponentFlags::R
-                    | vk::ColorComponentFlags::G
-                    | vk::ColorComponentFlags::B
-                    | vk::ColorComponentFlags::A,
-            )
-            .blend_enable(true)
-            .src_color_blend_factor(vk::BlendFactor::ONE)
-            .dst_color_blend_factor(vk::BlendFactor::ONE_MINUS_SRC_ALPHA)
-            .build()];
+            .scissor_count(1)
+            .viewport_count(1);
+        let rasterization_info = desc.rasterization_info();
+        let depth_stencil_info = desc.depth_stencil_info();
+        let color_attachment_count = match target {
+            RenderTarget::RenderPass(render_pass) => {
+                if let Some(expected) = render_pass.samples(desc.subpass()) {
+                    if expected != desc.samples() {
+                        return Err(PipelineCreationError::SampleCountMismatch {
+                            expected,
+                            found: desc.samples(),
+                        }
+                        .into());
+                    }
+                }
+                render_pass.color_attachment_count(desc.subpass())
+            }
+            RenderTarget::Formats(formats) => formats.color.len(),
+        };
+        let mut color_blend_attachments = desc.color_blend_attachments();
+        // A single blend mode applies to every color attachment
+        if color_blend_attachments.len() == 1 {
+            color_blend_attachments =
+                std::iter::repeat_n(color_blend_attachments[0], color_attachment_count).collect();
+        } else if color_blend_attachments.len() != color_attachment_count {
+            return Err(PipelineCreationError::BlendModeCountMismatch {
+                expected: color_attachment_count,
+                found: color_blend_attachments.len(),
+            }
+            .into());
+        }
         let color_blend_info =
             vk::PipelineColorBlendStateCreateInfo::builder().attachments(&color_blend_attachments);
-        let dynamic_state = [vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR];
         let dynamic_state_info =
-            vk::PipelineDynamicStateCreateInfo::builder().dynamic_states(&dynamic_state);
+            vk::PipelineDynamicStateCreateInfo::builder().dynamic_states(desc.dynamic_states());
         let vertex_input_state = vertex_iput_state.as_ref().map_or_else(
             || vk::PipelineVertexInputStateCreateInfo::builder().build(),
             |state| state.create_info(),
         );
-        let multisample_info = vk::PipelineMultisampleStateCreateInfo::builder()
-            .rasterization_samples(vk::SampleCountFlags::TYPE_1);
-
-        let descriptor_set_layouts = descriptor_sets
-            .iter()
-            .map(|set| set.layout)
-            .collect::<Vec<_>>();
-        let push_constant_ranges = push_constants
-            .iter()
-            .map(|pc| pc.to_raw())
-            .collect::<Vec<_>>();
-        let layout_create_info = vk::PipelineLayoutCreateInfo::builder()
-            .set_layouts(&descriptor_set_layouts)
-            .push_constant_ranges(&push_constant_ranges);
-        let layout = unsafe {
-            device
-                .device
-                .create_pipeline_layout(&layout_create_info, None)?
+        let multisample_info = desc.multisample_info();
+
+        // Programs that can't be reflected are trusted to match the push constants
+        if let Ok(reflection) = program.reflection() {
+            reflection.validate_push_constants(push_constants)?;
+        }
+        let layout = create_pipeline_layout(device, descriptor_sets, push_constants)?;
+
+        let mut rendering_info = vk::PipelineRenderingCreateInfo::builder();
+        let (render_pass, subpass, rendering_info) = match target {
+            RenderTarget::RenderPass(render_pass) => {
+                (render_pass.render_pass, desc.subpass(), None)
+            }
+            RenderTarget::Formats(formats) => {
+                rendering_info = rendering_info
+                    .color_attachment_formats(&formats.color)
+                    .depth_attachment_format(formats.depth.unwrap_or_default());
+                (vk::RenderPass::null(), 0, Some(&mut rendering_info))
+            }
         };
 
-        let pipeline_create_info = [vk::GraphicsPipelineCreateInfo::builder()
+        let mut pipeline_create_info = vk::GraphicsPipelineCreateInfo::builder()
             .stages(&pipeline_shader_stages)
             .vertex_input_state(&vertex_input_state)
             .input_assembly_state(&input_assembly_info)
@@ -141,20 +204,112 @@ impl GraphicsPipeline {
             .color_blend_state(&color_blend_info)
             .dynamic_state(&dynamic_state_info)
             .layout(layout)
-            .render_pass(render_pass.render_pass)
-            .subpass(0)
-            .build()];
+            .render_pass(render_pass)
+            .subpass(subpass);
+        if let Some(rendering_info) = rendering_info {
+            pipeline_create_info = pipeline_create_info.push_next(rendering_info);
+        }
+        let pipeline_create_info = [pipeline_create_info.build()];
 
-        let pipeline = unsafe {
+        let pipeline = match unsafe {
             device.device.create_graphics_pipelines(
-                vk::PipelineCache::null(),
+                pipeline_cache.map_or_else(vk::PipelineCache::null, |cache| cache.cache),
                 &pipeline_create_info,
                 None,
             )
+        } {
+            Ok(pipelines) => pipelines[0],
+            Err((_, err)) => {
+                unsafe { device.device.destroy_pipeline_layout(layout, None) };
+                return Err(PipelineCreationError::CouldNotCreatePipelines(err).into());
+            }
+        };
+
+        Ok(Self {
+            layout,
+            pipeline,
+            device: device.clone(),
+        })
+    }
+
+    // Push constants are taken from the shaders, as is the vertex input layout when
+    // `vertex_input_state` is `None`. Otherwise, `vertex_input_state` and `descriptor_sets` are
+    // validated against what the shaders expect.
+    pub fn from_reflection(
+        device: &Device,
+        render_pass: &RenderPass,
+        program: &GraphicsProgram,
+        vertex_input_state: Option<VertexInputData>,
+        descriptor_sets: &[&DescriptorSet],
+        desc: &GraphicsPipelineDesc,
+        pipeline_cache: Option<&PipelineCache>,
+    ) -> Result<Self> {
+        Self::create_from_reflection(
+            device,
+            RenderTarget::RenderPass(render_pass),
+            program,
+            vertex_input_state,
+            descriptor_sets,
+            desc,
+            pipeline_cache,
+        )
+    }
+
+    // Same as `from_reflection`, for dynamic rendering
+    pub fn from_reflection_with_rendering(
+        device: &Device,
+        formats: &RenderingFormats,
+        program: &GraphicsProgram,
+        vertex_input_state: Option<VertexInputData>,
+        descriptor_sets: &[&DescriptorSet],
+        desc: &GraphicsPipelineDesc,
+        pipeline_cache: Option<&PipelineCache>,
+    ) -> Result<Self> {
+        if !device.enabled_features().dynamic_rendering() {
+            return Err(DynamicRenderingError::NotEnabled.into());
         }
-        .expect("Failed to create graphics pipeline.")[0];
+        Self::create_from_reflection(
+            device,
+            RenderTarget::Formats(formats),
+            program,
+            vertex_input_state,
+            descriptor_sets,
+            desc,
+            pipeline_cache,
+        )
+    }
+
+    fn create_from_reflection(
+        device: &Device,
+        target: RenderTarget,
+        program: &GraphicsProgram,
+        vertex_input_state: Option<VertexInputData>,
+        descriptor_sets: &[&DescriptorSet],
+        desc: &GraphicsPipelineDesc,
+        pipeline_cache: Option<&PipelineCache>,
+    ) -> Result<Self> {
+        let reflection = program.reflection()?;
+        reflection.validate_descriptor_sets(descriptor_sets)?;
+        let vertex_input_state = match vertex_input_state {
+            Some(vertex_input_state) => {
+                reflection.validate_vertex_input(&vertex_input_state)?;
+                vertex_input_state
+            }
+            None => reflection.vertex_input_data(),
+        };
+        let push_constants = reflection.push_constants()?;
+        let push_constants = push_constants.iter().collect::<Vec<_>>();
 
-        Ok(Self { layout, pipeline })
+        Self::create(
+            device,
+            target,
+            program,
+            Some(vertex_input_state),
+            descriptor_sets,
+            &push_constants,
+            desc,
+            pipeline_cache,
+        )
     }
 
     pub fn bind(&self, device: &Device, context: &Context) {
@@ -184,9 +339,29 @@ impl GraphicsPipeline {
             );
         }
     }
+}
+
+impl Drop for GraphicsPipeline {
+    fn drop(&mut self) {
+        unsafe {
+            self.device.device.destroy_pipeline(self.pipeline, None);
+            self.device
+                .device
+                .destroy_pipeline_layout(self.layout, None);
+        }
+    }
+}
+
+impl Pipeline for GraphicsPipeline {
+    fn raw(&self) -> vk::Pipeline {
+        self.pipeline
+    }
+
+    fn layout(&self) -> vk::PipelineLayout {
+        self.layout
+    }
 
-    pub unsafe fn clean(&self, device: &Device) {
-        device.device.destroy_pipeline(self.pipeline, None);
-        device.device.destroy_pipeline_layout(self.layout, None);
+    fn bind_point(&self) -> vk::PipelineBindPoint {
+        vk::PipelineBindPoint::GRAPHICS
     }
 }
@@ -0,0 +1,305 @@
+use ash::vk;
+
+#[derive(Debug, Copy, Clone)]
+pub enum PrimitiveTopology {
+    PointList,
+    LineList,
+    LineStrip,
+    TriangleList,
+    TriangleStrip,
+    TriangleFan,
+}
+
+impl From<PrimitiveTopology> for vk::PrimitiveTopology {
+    fn from(topology: PrimitiveTopology) -> Self {
+        match topology {
+            PrimitiveTopology::PointList => vk::PrimitiveTopology::POINT_LIST,
+            PrimitiveTopology::LineList => vk::PrimitiveTopology::LINE_LIST,
+            PrimitiveTopology::LineStrip => vk::PrimitiveTopology::LINE_STRIP,
+            PrimitiveTopology::TriangleList => vk::PrimitiveTopology::TRIANGLE_LIST,
+            PrimitiveTopology::TriangleStrip => vk::PrimitiveTopology::TRIANGLE_STRIP,
+            PrimitiveTopology::TriangleFan => vk::PrimitiveTopology::TRIANGLE_FAN,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub enum PolygonMode {
+    Fill,
+    Line,
+    Point,
+}
+
+impl From<PolygonMode> for vk::PolygonMode {
+    fn from(mode: PolygonMode) -> Self {
+        match mode {
+            PolygonMode::Fill => vk::PolygonMode::FILL,
+            PolygonMode::Line => vk::PolygonMode::LINE,
+            PolygonMode::Point => vk::PolygonMode::POINT,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub enum CullMode {
+    None,
+    Front,
+    Back,
+    FrontAndBack,
+}
+
+impl From<CullMode> for vk::CullModeFlags {
+    fn from(mode: CullMode) -> Self {
+        match mode {
+            CullMode::None => vk::CullModeFlags::NONE,
+            CullMode::Front => vk::CullModeFlags::FRONT,
+            CullMode::Back => vk::CullModeFlags::BACK,
+            CullMode::FrontAndBack => vk::CullModeFlags::FRONT_AND_BACK,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub enum FrontFace {
+    CounterClockwise,
+    Clockwise,
+}
+
+impl From<FrontFace> for vk::FrontFace {
+    fn from(front_face: FrontFace) -> Self {
+        match front_face {
+            FrontFace::CounterClockwise => vk::FrontFace::COUNTER_CLOCKWISE,
+            FrontFace::Clockwise => vk::FrontFace::CLOCKWISE,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub enum CompareOp {
+    Never,
+    Less,
+    Equal,
+    LessOrEqual,
+    Greater,
+    NotEqual,
+    GreaterOrEqual,
+    Always,
+}
+
+impl From<CompareOp> for vk::CompareOp {
+    fn from(op: CompareOp) -> Self {
+        match op {
+            CompareOp::Never => vk::CompareOp::NEVER,
+            CompareOp::Less => vk::CompareOp::LESS,
+            CompareOp::Equal => vk::CompareOp::EQUAL,
+            CompareOp::LessOrEqual => vk::CompareOp::LESS_OR_EQUAL,
+            CompareOp::Greater => vk::CompareOp::GREATER,
+            CompareOp::NotEqual => vk::CompareOp::NOT_EQUAL,
+            CompareOp::GreaterOrEqual => vk::CompareOp::GREATER_OR_EQUAL,
+            CompareOp::Always => vk::CompareOp::ALWAYS,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub enum BlendMode {
+    Opaque,
+    Alpha,
+    Additive,
+    // Matches the blending pipelines have always used: only the color channels are blended,
+    // destination alpha is written as zero.
+    Premultiplied,
+}
+
+impl From<BlendMode> for vk::PipelineColorBlendAttachmentState {
+    fn from(mode: BlendMode) -> Self {
+        let state = vk::PipelineColorBlendAttachmentState::builder().color_write_mask(
+            vk::ColorComponentFlags::R
+                | vk::ColorComponentFlags::G
+                | vk::ColorComponentFlags::B
+                | vk::ColorComponentFlags::A,
+        );
+        match mode {
+            BlendMode::Opaque => state.blend_enable(false),
+            BlendMode::Alpha => state
+                .blend_enable(true)
+                .src_color_blend_factor(vk::BlendFactor::SRC_ALPHA)
+                .dst_color_blend_factor(vk::BlendFactor::ONE_MINUS_SRC_ALPHA)
+                .color_blend_op(vk::BlendOp::ADD)
+                .src_alpha_blend_factor(vk::BlendFactor::ONE)
+                .dst_alpha_blend_factor(vk::BlendFactor::ONE_MINUS_SRC_ALPHA)
+                .alpha_blend_op(vk::BlendOp::ADD),
+            BlendMode::Additive => state
+                .blend_enable(true)
+                .src_color_blend_factor(vk::BlendFactor::SRC_ALPHA)
+                .dst_color_blend_factor(vk::BlendFactor::ONE)
+                .color_blend_op(vk::BlendOp::ADD)
+                .src_alpha_blend_factor(vk::BlendFactor::ONE)
+                .dst_alpha_blend_factor(vk::BlendFactor::ONE)
+                .alpha_blend_op(vk::BlendOp::ADD),
+            BlendMode::Premultiplied => state
+                .blend_enable(true)
+                .src_color_blend_factor(vk::BlendFactor::ONE)
+                .dst_color_blend_factor(vk::BlendFactor::ONE_MINUS_SRC_ALPHA),
+        }
+        .build()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct GraphicsPipelineDesc {
+    topology: PrimitiveTopology,
+    polygon_mode: PolygonMode,
+    cull_mode: CullMode,
+    front_face: FrontFace,
+    depth_test: bool,
+    depth_write: bool,
+    depth_compare_op: CompareOp,
+    stencil: Option<(vk::StencilOpState, vk::StencilOpState)>,
+    blend_modes: Vec<BlendMode>,
+    samples: vk::SampleCountFlags,
+    dynamic_states: Vec<vk::DynamicState>,
+    subpass: u32,
+}
+
+impl Default for GraphicsPipelineDesc {
+    fn default() -> Self {
+        Self {
+            topology: PrimitiveTopology::TriangleList,
+            polygon_mode: PolygonMode::Fill,
+            cull_mode: CullMode::None,
+            front_face: FrontFace::CounterClockwise,
+            depth_test: false,
+            depth_write: false,
+            depth_compare_op: CompareOp::Always,
+            stencil: None,
+            blend_modes: vec![BlendMode::Premultiplied],
+            samples: vk::SampleCountFlags::TYPE_1,
+            dynamic_states: vec![vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR],
+            subpass: 0,
+        }
+    }
+}
+
+impl GraphicsPipelineDesc {
+    pub fn with_topology(mut self, topology: PrimitiveTopology) -> Self {
+        self.topology = topology;
+        self
+    }
+
+    pub fn with_polygon_mode(mut self, polygon_mode: PolygonMode) -> Self {
+        self.polygon_mode = polygon_mode;
+        self
+    }
+
+    pub fn with_cull_mode(mut self, cull_mode: CullMode) -> Self {
+        self.cull_mode = cull_mode;
+        self
+    }
+
+    pub fn with_front_face(mut self, front_face: FrontFace) -> Self {
+        self.front_face = front_face;
+        self
+    }
+
+    pub fn with_depth_test(mut self, compare_op: CompareOp, write: bool) -> Self {
+        self.depth_test = true;
+        self.depth_write = write;
+        self.depth_compare_op = compare_op;
+        self
+    }
+
+    pub fn with_stencil(mut self, front: vk::StencilOpState, back: vk::StencilOpState) -> Self {
+        self.stencil = Some((front, back));
+        self
+    }
+
+    // For pipelines with a single color attachment
+    pub fn with_blend_mode(mut self, blend_mode: BlendMode) -> Self {
+        self.blend_modes = vec![blend_mode];
+        self
+    }
+
+    // One blend mode per color attachment, in attachment order
+    pub fn with_blend_modes(mut self, blend_modes: &[BlendMode]) -> Self {
+        self.blend_modes = blend_modes.to_vec();
+        self
+    }
+
+    pub fn with_samples(mut self, samples: vk::SampleCountFlags) -> Self {
+        self.samples = samples;
+        self
+    }
+
+    // Viewport and scissor are always dynamic, these are added on top of them
+    pub fn with_dynamic_state(mut self, dynamic_state: vk::DynamicState) -> Self {
+        if !self.dynamic_states.contains(&dynamic_state) {
+            self.dynamic_states.push(dynamic_state);
+        }
+        self
+    }
+
+    // Subpass of the render pass the pipeline is used in, ignored with dynamic rendering
+    pub fn with_subpass(mut self, subpass: u32) -> Self {
+        self.subpass = subpass;
+        self
+    }
+
+    pub(crate) fn input_assembly_info(&self) -> vk::PipelineInputAssemblyStateCreateInfo {
+        vk::PipelineInputAssemblyStateCreateInfo::builder()
+            .topology(self.topology.into())
+            .build()
+    }
+
+    pub(crate) fn rasterization_info(&self) -> vk::PipelineRasterizationStateCreateInfo {
+        vk::PipelineRasterizationStateCreateInfo::builder()
+            .depth_clamp_enable(false)
+            .rasterizer_discard_enable(false)
+            .polygon_mode(self.polygon_mode.into())
+            .cull_mode(self.cull_mode.into())
+            .front_face(self.front_face.into())
+            .depth_bias_enable(false)
+            .line_width(1.0)
+            .build()
+    }
+
+    pub(crate) fn depth_stencil_info(&self) -> vk::PipelineDepthStencilStateCreateInfo {
+        let keep = vk::StencilOpState::builder()
+            .fail_op(vk::StencilOp::KEEP)
+            .pass_op(vk::StencilOp::KEEP)
+            .compare_op(vk::CompareOp::ALWAYS)
+            .build();
+        let (front, back) = self.stencil.unwrap_or((keep, keep));
+        vk::PipelineDepthStencilStateCreateInfo::builder()
+            .depth_test_enable(self.depth_test)
+            .depth_write_enable(self.depth_write)
+            .depth_compare_op(self.depth_compare_op.into())
+            .depth_bounds_test_enable(false)
+            .stencil_test_enable(self.stencil.is_some())
+            .front(front)
+            .back(back)
+            .build()
+    }
+
+    pub(crate) fn color_blend_attachments(&self) -> Vec<vk::PipelineColorBlendAttachmentState> {
+        self.blend_modes.iter().map(|&mode| mode.into()).collect()
+    }
+
+    pub(crate) fn multisample_info(&self) -> vk::PipelineMultisampleStateCreateInfo {
+        vk::PipelineMultisampleStateCreateInfo::builder()
+            .rasterization_samples(self.samples)
+            .build()
+    }
+
+    pub(crate) fn dynamic_states(&self) -> &[vk::DynamicState] {
+        &self.dynamic_states
+    }
+
+    pub(crate) fn subpass(&self) -> u32 {
+        self.subpass
+    }
+
+    pub(crate) fn samples(&self) -> vk::SampleCountFlags {
+        self.samples
+    }
+}
@@ -1,6 +1,49 @@
+pub mod compute_pipeline;
 pub mod graphics_pipeline;
+pub mod graphics_pipeline_desc;
+pub mod pipeline_cache;
 
-pub use graphics_pipeline::GraphicsPipeline;
+pub use compute_pipeline::ComputePipeline;
+pub use graphics_pipeline::{GraphicsPipeline, PipelineCreationError};
+pub use graphics_pipeline_desc::{
+    BlendMode, CompareOp, CullMode, FrontFace, GraphicsPipelineDesc, PolygonMode, PrimitiveTopology,
+};
+pub use pipeline_cache::{PipelineCache, PipelineCacheError};
+
+use crate::{
+    descriptors::DescriptorSet, device::Device, error::Result, push_constant::PushConstant,
+};
+use ash::vk;
+
+pub trait Pipeline {
+    fn raw(&self) -> vk::Pipeline;
+    fn layout(&self) -> vk::PipelineLayout;
+    fn bind_point(&self) -> vk::PipelineBindPoint;
+}
+
+pub(crate) fn create_pipeline_layout(
+    device: &Device,
+    descriptor_sets: &[&DescriptorSet],
+    push_constants: &[&PushConstant],
+) -> Result<vk::PipelineLayout> {
+    let descriptor_set_layouts = descriptor_sets
+        .iter()
+        .map(|set| set.layout)
+        .collect::<Vec<_>>();
+    let push_constant_ranges = push_constants
+        .iter()
+        .map(|pc| pc.to_raw())
+        .collect::<Vec<_>>();
+    let layout_create_info = vk::PipelineLayoutCreateInfo::builder()
+        .set_layouts(&descriptor_set_layouts)
+        .push_constant_ranges(&push_constant_ranges);
+    let layout = unsafe {
+        device
+            .device
+            .create_pipeline_layout(&layout_create_info, None)?
+    };
+    Ok(layout)
+}
 
 #[derive(Debug, Copy, Clone)]
 pub enum PipelineStages {
@@ -0,0 +1,237 @@
+use crate::{
+    device::Device,
+    error::{Error, Result},
+};
+use ash::vk;
+use std::path::Path;
+use thiserror::Error;
+
+// Size of `VkPipelineCacheHeaderVersionOne`
+const HEADER_SIZE: usize = 16 + vk::UUID_SIZE;
+
+#[derive(Debug, Error)]
+pub enum PipelineCacheError {
+    #[error("pipeline cache data is too short to hold a header")]
+    TruncatedHeader,
+    #[error("unsupported pipeline cache header version {0}")]
+    UnsupportedVersion(u32),
+    #[error("pipeline cache was created by vendor {found:#x}, expected {expected:#x}")]
+    VendorMismatch { expected: u32, found: u32 },
+    #[error("pipeline cache was created by device {found:#x}, expected {expected:#x}")]
+    DeviceMismatch { expected: u32, found: u32 },
+    #[error("pipeline cache UUID does not match the device")]
+    UuidMismatch,
+}
+
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+fn validate_header(
+    properties: &vk::PhysicalDeviceProperties,
+    data: &[u8],
+) -> Result<(), PipelineCacheError> {
+    if data.len() < HEADER_SIZE || (read_u32(data, 0) as usize) < HEADER_SIZE {
+        return Err(PipelineCacheError::TruncatedHeader);
+    }
+    let version = read_u32(data, 4);
+    if version != vk::PipelineCacheHeaderVersion::ONE.as_raw() as u32 {
+        return Err(PipelineCacheError::UnsupportedVersion(version));
+    }
+    let vendor_id = read_u32(data, 8);
+    if vendor_id != properties.vendor_id {
+        return Err(PipelineCacheError::VendorMismatch {
+            expected: properties.vendor_id,
+            found: vendor_id,
+        });
+    }
+    let device_id = read_u32(data, 12);
+    if device_id != properties.device_id {
+        return Err(PipelineCacheError::DeviceMismatch {
+            expected: properties.device_id,
+            found: device_id,
+        });
+    }
+    if data[16..HEADER_SIZE] != properties.pipeline_cache_uuid {
+        return Err(PipelineCacheError::UuidMismatch);
+    }
+    Ok(())
+}
+
+pub struct PipelineCache {
+    pub cache: vk::PipelineCache,
+    device: Device,
+}
+
+impl PipelineCache {
+    pub fn new(device: &Device) -> Result<Self> {
+        Self::from_initial_data(device, &[])
+    }
+
+    fn from_initial_data(device: &Device, data: &[u8]) -> Result<Self> {
+        let create_info = vk::PipelineCacheCreateInfo::builder().initial_data(data);
+        let cache = unsafe { device.device.create_pipeline_cache(&create_info, None)? };
+        Ok(Self {
+            cache,
+            device: device.clone(),
+        })
+    }
+
+    pub fn from_data(device: &Device, data: &[u8]) -> Result<Self> {
+        validate_header(&device.properties, data)?;
+        Self::from_initial_data(device, data)
+    }
+
+    pub fn from_file<P: AsRef<Path>>(device: &Device, path: P) -> Result<Self> {
+        let data = std::fs::read(path)?;
+        Self::from_data(device, &data)
+    }
+
+    // Starts from an empty cache when the file is missing or was written by a different
+    // device or driver, so a stale cache never fails startup. Other errors are still returned.
+    pub fn from_file_or_new<P: AsRef<Path>>(device: &Device, path: P) -> Result<Self> {
+        match Self::from_file(device, path) {
+            Ok(cache) => Ok(cache),
+            Err(Error::PipelineCache(_)) => Self::new(device),
+            Err(Error::Io(err)) if err.kind() == std::io::ErrorKind::NotFound => Self::new(device),
+            Err(err) => Err(err),
+        }
+    }
+
+    pub fn data(&self, device: &Device) -> Result<Vec<u8>> {
+        let data = unsafe { device.device.get_pipeline_cache_data(self.cache)? };
+        Ok(data)
+    }
+
+    // Writes to a temporary file first so an interrupted save can't leave a torn cache behind
+    pub fn save_to_file<P: AsRef<Path>>(&self, device: &Device, path: P) -> Result<()> {
+        let path = path.as_ref();
+        let data = self.data(device)?;
+        let tmp_path = path.with_extension("tmp");
+        std::fs::write(&tmp_path, data)?;
+        std::fs::rename(&tmp_path, path)?;
+        Ok(())
+    }
+
+    // Pipeline caches can be used from several threads at once, but merging into one needs
+    // exclusive access. A common pattern is one cache per worker thread, merged at the end.
+    pub fn merge(&mut self, device: &Device, caches: &[&PipelineCache]) -> Result<()> {
+        let src_caches = caches.iter().map(|cache| cache.cache).collect::<Vec<_>>();
+        unsafe {
+            device
+                .device
+                .merge_pipeline_caches(self.cache, &src_caches)?
+        };
+        Ok(())
+    }
+}
+
+impl Drop for PipelineCache {
+    fn drop(&mut self) {
+        unsafe { self.device.device.destroy_pipeline_cache(self.cache, None) };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn properties() -> vk::PhysicalDeviceProperties {
+        vk::PhysicalDeviceProperties {
+            vendor_id: 0x10de,
+            device_id: 0x2204,
+            pipeline_cache_uuid: [7; vk::UUID_SIZE],
+            ..Default::default()
+        }
+    }
+
+    fn header(properties: &vk::PhysicalDeviceProperties) -> Vec<u8> {
+        let mut data = Vec::new();
+        data.extend((HEADER_SIZE as u32).to_le_bytes());
+        data.extend((vk::PipelineCacheHeaderVersion::ONE.as_raw() as u32).to_le_bytes());
+        data.extend(properties.vendor_id.to_le_bytes());
+        data.extend(properties.device_id.to_le_bytes());
+        data.extend(properties.pipeline_cache_uuid);
+        // Driver specific data follows the header
+        data.extend([1, 2, 3, 4]);
+        data
+    }
+
+    #[test]
+    fn valid_header() {
+        let properties = properties();
+        assert!(validate_header(&properties, &header(&properties)).is_ok());
+    }
+
+    #[test]
+    fn truncated_header() {
+        let properties = properties();
+        let data = header(&properties);
+        assert!(matches!(
+            validate_header(&properties, &data[..HEADER_SIZE - 1]),
+            Err(PipelineCacheError::TruncatedHeader)
+        ));
+        assert!(matches!(
+            validate_header(&properties, &[]),
+            Err(PipelineCacheError::TruncatedHeader)
+        ));
+
+        // The header length field can't be smaller than the header itself
+        let mut data = header(&properties);
+        data[0..4].copy_from_slice(&8u32.to_le_bytes());
+        assert!(matches!(
+            validate_header(&properties, &data),
+            Err(PipelineCacheError::TruncatedHeader)
+        ));
+    }
+
+    #[test]
+    fn unsupported_version() {
+        let properties = properties();
+        let mut data = header(&properties);
+        data[4..8].copy_from_slice(&2u32.to_le_bytes());
+        assert!(matches!(
+            validate_header(&properties, &data),
+            Err(PipelineCacheError::UnsupportedVersion(2))
+        ));
+    }
+
+    #[test]
+    fn different_device() {
+        let properties = properties();
+        let data = header(&properties);
+
+        let other_vendor = vk::PhysicalDeviceProperties {
+            vendor_id: 0x1002,
+            ..properties
+        };
+        assert!(matches!(
+            validate_header(&other_vendor, &data),
+            Err(PipelineCacheError::VendorMismatch {
+                expected: 0x1002,
+                found: 0x10de
+            })
+        ));
+
+        let other_device = vk::PhysicalDeviceProperties {
+            device_id: 0x2206,
+            ..properties
+        };
+        assert!(matches!(
+            validate_header(&other_device, &data),
+            Err(PipelineCacheError::DeviceMismatch {
+                expected: 0x2206,
+                found: 0x2204
+            })
+        ));
+
+        let other_driver = vk::PhysicalDeviceProperties {
+            pipeline_cache_uuid: [8; vk::UUID_SIZE],
+            ..properties
+        };
+        assert!(matches!(
+            validate_header(&other_driver, &data),
+            Err(PipelineCacheError::UuidMismatch)
+        ));
+    }
+}
@@ -0,0 +1,90 @@
+use super::{GraphImage, Plan, RenderGraph};
+use std::fmt::Write;
+
+fn escape(name: &str) -> String {
+    name.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+// `slot_of` is empty for graphs that haven't been compiled
+pub(super) fn write(graph: &RenderGraph, plan: &Plan, slot_of: &[Option<usize>]) -> String {
+    let mut dot = String::from("digraph render_graph {\n    rankdir=LR;\n");
+
+    for (index, pass) in graph.passes.iter().enumerate() {
+        let name = escape(&pass.name);
+        let _ = match plan.order.iter().position(|&ordered| ordered == index) {
+            Some(position) => writeln!(
+                dot,
+                "    pass_{index} [shape=box, label=\"{position}: {name}\"];"
+            ),
+            None if plan.cyclic.contains(&index) => writeln!(
+                dot,
+                "    pass_{index} [shape=box, color=red, label=\"{name} (cycle)\"];"
+            ),
+            None => writeln!(
+                dot,
+                "    pass_{index} [shape=box, style=dashed, color=gray, label=\"{name} (culled)\"];"
+            ),
+        };
+    }
+
+    for (index, (name, image)) in graph.images.iter().enumerate() {
+        let kind = match image {
+            GraphImage::Imported(_) => "imported".to_string(),
+            GraphImage::Transient(desc) => {
+                let mut kind = format!(
+                    "transient {}x{} {:?}",
+                    desc.resolution.width, desc.resolution.height, desc.format
+                );
+                if let Some(Some(slot)) = slot_of.get(index) {
+                    let _ = write!(kind, ", memory {slot}");
+                }
+                kind
+            }
+        };
+        let _ = writeln!(
+            dot,
+            "    image_{index} [shape=ellipse, label=\"{}\\n{kind}\"];",
+            escape(name)
+        );
+    }
+    for (index, (name, _)) in graph.buffers.iter().enumerate() {
+        let _ = writeln!(
+            dot,
+            "    buffer_{index} [shape=ellipse, label=\"{}\\nimported\"];",
+            escape(name)
+        );
+    }
+
+    for (index, pass) in graph.passes.iter().enumerate() {
+        let style = if plan.kept[index] {
+            ""
+        } else {
+            ", style=dashed"
+        };
+        let accesses = pass
+            .images
+            .iter()
+            .map(|(image, usage)| (format!("image_{}", image.0), usage))
+            .chain(
+                pass.buffers
+                    .iter()
+                    .map(|(buffer, usage)| (format!("buffer_{}", buffer.0), usage)),
+            );
+        for (resource, usage) in accesses {
+            let _ = if usage.is_write() {
+                writeln!(
+                    dot,
+                    "    pass_{index} -> {resource} [label=\"{usage:?}\"{style}];"
+                )
+            } else {
+                writeln!(
+                    dot,
+                    "    {resource} -> pass_{index} [label=\"{usage:?}\"{style}];"
+                )
+            };
+        }
+    }
+
+    dot.push_str("}\n");
+    dot
+}
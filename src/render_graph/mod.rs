@@ -0,0 +1,775 @@
+mod dot;
+
+use crate::{
+    context::Context,
+    device::{take_fields, Device, Retire, RetiredResource},
+    error::Result,
+    mem::{self, Allocation, AllocationKind},
+    resources::{
+        ash_image::{Image, ImageCreationError, ImageResolution, ImageType},
+        buffer::Buffer,
+    },
+    sync::{ResourceTransitions, ResourceUsage},
+};
+use ash::vk;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum RenderGraphError {
+    #[error("passes depend on each other: {0:?}")]
+    Cycle(Vec<String>),
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct ImageHandle(usize);
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct BufferHandle(usize);
+
+// An image created by the graph, that only lives for the duration of the passes using it
+#[derive(Debug, Copy, Clone)]
+pub struct TransientImageDesc {
+    pub resolution: ImageResolution,
+    pub ty: ImageType,
+    pub format: vk::Format,
+    pub samples: vk::SampleCountFlags,
+}
+
+impl TransientImageDesc {
+    pub fn new(resolution: ImageResolution, ty: ImageType) -> Self {
+        Self {
+            resolution,
+            ty,
+            format: ty.format(),
+            samples: vk::SampleCountFlags::TYPE_1,
+        }
+    }
+
+    pub fn with_format(mut self, format: vk::Format) -> Self {
+        self.format = format;
+        self
+    }
+
+    pub fn with_samples(mut self, samples: vk::SampleCountFlags) -> Self {
+        self.samples = samples;
+        self
+    }
+}
+
+enum GraphImage<'a> {
+    Imported(&'a Image),
+    Transient(TransientImageDesc),
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum GraphResource {
+    Image(usize),
+    Buffer(usize),
+}
+
+type RecordPass<'a> = Box<dyn FnMut(&Device, &Context, &PassResources) + 'a>;
+
+// A node of the graph. Whether a resource is read or written follows from its usage, and the
+// graph moves every resource to its usage before `record` is called.
+pub struct Pass<'a> {
+    name: String,
+    images: Vec<(ImageHandle, ResourceUsage)>,
+    buffers: Vec<(BufferHandle, ResourceUsage)>,
+    side_effects: bool,
+    record: RecordPass<'a>,
+}
+
+impl<'a> Pass<'a> {
+    pub fn new(
+        name: impl Into<String>,
+        record: impl FnMut(&Device, &Context, &PassResources) + 'a,
+    ) -> Self {
+        Self {
+            name: name.into(),
+            images: Vec::new(),
+            buffers: Vec::new(),
+            side_effects: false,
+            record: Box::new(record),
+        }
+    }
+
+    pub fn with_image(mut self, image: ImageHandle, usage: ResourceUsage) -> Self {
+        self.images.push((image, usage));
+        self
+    }
+
+    pub fn with_buffer(mut self, buffer: BufferHandle, usage: ResourceUsage) -> Self {
+        self.buffers.push((buffer, usage));
+        self
+    }
+
+    // Never culled, for passes that do something the graph can't see, like rendering to the
+    // swapchain
+    pub fn with_side_effects(mut self) -> Self {
+        self.side_effects = true;
+        self
+    }
+
+    fn accesses(&self) -> impl Iterator<Item = (GraphResource, ResourceUsage)> + '_ {
+        self.images
+            .iter()
+            .map(|(image, usage)| (GraphResource::Image(image.0), *usage))
+            .chain(
+                self.buffers
+                    .iter()
+                    .map(|(buffer, usage)| (GraphResource::Buffer(buffer.0), *usage)),
+            )
+    }
+
+    fn uses(&self, resource: GraphResource) -> bool {
+        self.accesses().any(|(accessed, _)| accessed == resource)
+    }
+
+    fn writes(&self, resource: GraphResource) -> bool {
+        self.accesses()
+            .any(|(accessed, usage)| accessed == resource && usage.is_write())
+    }
+}
+
+// Resources of the graph, as seen by a pass while it is recorded
+pub struct PassResources<'r> {
+    images: Vec<Option<&'r Image>>,
+    buffers: Vec<&'r Buffer>,
+}
+
+impl PassResources<'_> {
+    // Panics for transient images not used by any pass that was kept
+    pub fn image(&self, image: ImageHandle) -> &Image {
+        self.images[image.0].expect("transient image is not used by any executed pass")
+    }
+
+    pub fn buffer(&self, buffer: BufferHandle) -> &Buffer {
+        self.buffers[buffer.0]
+    }
+}
+
+// Which passes run, and in which order
+struct Plan {
+    // Indexed by pass
+    kept: Vec<bool>,
+    order: Vec<usize>,
+    // Kept passes that could not be ordered
+    cyclic: Vec<usize>,
+}
+
+// Passes declare the resources they use, and the graph figures out the rest. Passes are added in
+// the order they would be submitted in: a pass reading a resource sees what the last pass added
+// before it wrote, and a pass writing a resource runs after the previous writer and the passes
+// reading in between. Passes are culled unless they have side effects, write an imported resource
+// or an output, or are needed by a pass that is kept, through what it reads or overwrites.
+#[derive(Default)]
+pub struct RenderGraph<'a> {
+    images: Vec<(String, GraphImage<'a>)>,
+    buffers: Vec<(String, &'a Buffer)>,
+    passes: Vec<Pass<'a>>,
+    outputs: Vec<ImageHandle>,
+}
+
+impl<'a> RenderGraph<'a> {
+    pub fn import_image(&mut self, name: impl Into<String>, image: &'a Image) -> ImageHandle {
+        self.images.push((name.into(), GraphImage::Imported(image)));
+        ImageHandle(self.images.len() - 1)
+    }
+
+    pub fn create_image(
+        &mut self,
+        name: impl Into<String>,
+        desc: TransientImageDesc,
+    ) -> ImageHandle {
+        self.images.push((name.into(), GraphImage::Transient(desc)));
+        ImageHandle(self.images.len() - 1)
+    }
+
+    pub fn import_buffer(&mut self, name: impl Into<String>, buffer: &'a Buffer) -> BufferHandle {
+        self.buffers.push((name.into(), buffer));
+        BufferHandle(self.buffers.len() - 1)
+    }
+
+    pub fn add_pass(&mut self, pass: Pass<'a>) {
+        self.passes.push(pass);
+    }
+
+    // Keeps the passes writing `image` alive, for transient images read back after execution
+    pub fn mark_output(&mut self, image: ImageHandle) {
+        self.outputs.push(image);
+    }
+
+    fn is_output(&self, resource: GraphResource) -> bool {
+        match resource {
+            GraphResource::Image(index) => {
+                matches!(self.images[index].1, GraphImage::Imported(_))
+                    || self.outputs.contains(&ImageHandle(index))
+            }
+            GraphResource::Buffer(_) => true,
+        }
+    }
+
+    // For every pass, the passes it needs, which are kept along with it, and the passes it has to
+    // run after, which also includes readers that a write must not overtake
+    fn dependencies(&self) -> (Vec<Vec<usize>>, Vec<Vec<usize>>) {
+        let mut needs = vec![Vec::new(); self.passes.len()];
+        let mut after = vec![Vec::new(); self.passes.len()];
+        for (index, pass) in self.passes.iter().enumerate() {
+            for (resource, _) in pass.accesses() {
+                let previous_writer =
+                    (0..index).rfind(|&other| self.passes[other].writes(resource));
+                if let Some(writer) = previous_writer {
+                    if !needs[index].contains(&writer) {
+                        needs[index].push(writer);
+                        after[index].push(writer);
+                    }
+                }
+                if pass.writes(resource) {
+                    let readers = previous_writer.map_or(0, |writer| writer + 1)..index;
+                    for reader in readers {
+                        if self.passes[reader].uses(resource) && !after[index].contains(&reader) {
+                            after[index].push(reader);
+                        }
+                    }
+                }
+            }
+        }
+        (needs, after)
+    }
+
+    fn plan(&self) -> Plan {
+        let (needs, after) = self.dependencies();
+
+        let mut kept = vec![false; self.passes.len()];
+        let mut stack = self
+            .passes
+            .iter()
+            .enumerate()
+            .filter(|(_, pass)| {
+                pass.side_effects
+                    || pass
+                        .accesses()
+                        .any(|(resource, usage)| usage.is_write() && self.is_output(resource))
+            })
+            .map(|(index, _)| index)
+            .collect::<Vec<_>>();
+        while let Some(index) = stack.pop() {
+            if !kept[index] {
+                kept[index] = true;
+                stack.extend(&needs[index]);
+            }
+        }
+
+        // Kahn's algorithm, picking the earliest added pass when several are ready. Culled passes
+        // don't hold anything back.
+        let mut order = Vec::new();
+        let mut placed = vec![false; self.passes.len()];
+        while let Some(next) = (0..self.passes.len()).find(|&index| {
+            kept[index]
+                && !placed[index]
+                && after[index]
+                    .iter()
+                    .all(|&dependency| placed[dependency] || !kept[dependency])
+        }) {
+            placed[next] = true;
+            order.push(next);
+        }
+        let cyclic = (0..self.passes.len())
+            .filter(|&index| kept[index] && !placed[index])
+            .collect();
+
+        Plan {
+            kept,
+            order,
+            cyclic,
+        }
+    }
+
+    // First and last position in `order` of every transient image. Outputs are read after the
+    // graph has executed, so their memory is not reused by anything later in the order.
+    fn lifetimes(&self, plan: &Plan) -> Vec<Option<(usize, usize)>> {
+        let mut lifetimes = vec![None; self.images.len()];
+        for (position, &pass_index) in plan.order.iter().enumerate() {
+            for (image, _) in &self.passes[pass_index].images {
+                if let GraphImage::Transient(_) = self.images[image.0].1 {
+                    let lifetime = lifetimes[image.0].get_or_insert((position, position));
+                    lifetime.1 = position;
+                }
+            }
+        }
+        for output in &self.outputs {
+            if let Some(lifetime) = &mut lifetimes[output.0] {
+                lifetime.1 = plan.order.len() - 1;
+            }
+        }
+        lifetimes
+    }
+
+    // Graphviz graph of the passes and resources, with culled passes dashed
+    pub fn to_dot(&self) -> String {
+        dot::write(self, &self.plan(), &[])
+    }
+
+    // Orders the passes and creates the transient images. Transient images whose lifetimes don't
+    // overlap share memory.
+    pub fn compile(self, device: &Device) -> Result<CompiledRenderGraph<'a>> {
+        let plan = self.plan();
+        if !plan.cyclic.is_empty() {
+            return Err(RenderGraphError::Cycle(
+                plan.cyclic
+                    .iter()
+                    .map(|&index| self.passes[index].name.clone())
+                    .collect(),
+            )
+            .into());
+        }
+
+        let lifetimes = self.lifetimes(&plan);
+
+        let mut raw_images = Vec::new();
+        for (index, (_, image)) in self.images.iter().enumerate() {
+            if let (GraphImage::Transient(desc), Some(_)) = (image, lifetimes[index]) {
+                let image = match Image::create_raw(
+                    device,
+                    desc.resolution,
+                    desc.ty,
+                    desc.format,
+                    desc.samples,
+                ) {
+                    Ok(image) => image,
+                    Err(error) => {
+                        unsafe { free_unbound(device, raw_images.iter(), &[]) };
+                        return Err(error);
+                    }
+                };
+                let requirements = unsafe { device.device.get_image_memory_requirements(image) };
+                raw_images.push((index, image, requirements));
+            }
+        }
+
+        let (mut slots, slot_of) = assign_slots(&raw_images, &lifetimes);
+
+        let mut memory = Vec::new();
+        for (requirements, _) in &slots {
+            let allocation = mem::find_memory_type_index(
+                requirements,
+                &device.memory_properties,
+                vk::MemoryPropertyFlags::DEVICE_LOCAL,
+            )
+            .ok_or_else(|| ImageCreationError::CouldNotFindMemoryIndex.into())
+            .and_then(|memory_index| {
+                device.allocate(requirements, memory_index, AllocationKind::Optimal)
+            });
+            match allocation {
+                Ok(allocation) => memory.push(allocation),
+                Err(error) => {
+                    unsafe { free_unbound(device, raw_images.iter(), &memory) };
+                    return Err(error);
+                }
+            }
+        }
+
+        let mut transient_images = (0..self.images.len()).map(|_| None).collect::<Vec<_>>();
+        let mut unbound = raw_images.iter();
+        while let Some(&(index, image, _)) = unbound.next() {
+            if let GraphImage::Transient(desc) = self.images[index].1 {
+                let allocation = memory[slot_of[index].unwrap()].alias();
+                match Image::bind(
+                    device,
+                    image,
+                    allocation,
+                    false,
+                    desc.resolution,
+                    desc.ty,
+                    desc.format,
+                    desc.samples,
+                ) {
+                    Ok(image) => transient_images[index] = Some(image),
+                    Err(error) => {
+                        // Bound images only destroy themselves, the memory is freed with the rest
+                        transient_images.clear();
+                        unsafe { free_unbound(device, unbound, &memory) };
+                        return Err(error);
+                    }
+                }
+            }
+        }
+
+        // The previous image in the same memory, whose accesses the first use of an image has to
+        // wait on. The first image of a slot follows the last one of the previous execution.
+        let mut first_uses = vec![Vec::new(); plan.order.len()];
+        for (_, occupants) in &mut slots {
+            occupants.sort_by_key(|&occupant| lifetimes[occupant].unwrap().0);
+            for (position, &occupant) in occupants.iter().enumerate() {
+                let previous = occupants[(position + occupants.len() - 1) % occupants.len()];
+                first_uses[lifetimes[occupant].unwrap().0].push((occupant, previous));
+            }
+        }
+
+        Ok(CompiledRenderGraph {
+            graph: self,
+            plan,
+            transient_images,
+            first_uses,
+            slot_of,
+            memory,
+            device: device.clone(),
+        })
+    }
+}
+
+type Slot = (vk::MemoryRequirements, Vec<usize>);
+
+// Largest images first, each one goes in the first slot it fits in without overlapping the
+// lifetime of another image in it. Returns the slots and the slot of every image.
+fn assign_slots(
+    raw_images: &[(usize, vk::Image, vk::MemoryRequirements)],
+    lifetimes: &[Option<(usize, usize)>],
+) -> (Vec<Slot>, Vec<Option<usize>>) {
+    let mut by_size = raw_images
+        .iter()
+        .map(|&(index, _, requirements)| (index, requirements))
+        .collect::<Vec<_>>();
+    by_size.sort_by_key(|(_, requirements)| std::cmp::Reverse(requirements.size));
+
+    let mut slots: Vec<Slot> = Vec::new();
+    let mut slot_of = vec![None; lifetimes.len()];
+    for (index, requirements) in by_size {
+        let (first, last) = lifetimes[index].unwrap();
+        let fits = |(slot_requirements, occupants): &Slot| {
+            slot_requirements.memory_type_bits & requirements.memory_type_bits != 0
+                && occupants.iter().all(|&occupant| {
+                    let (other_first, other_last) = lifetimes[occupant].unwrap();
+                    last < other_first || other_last < first
+                })
+        };
+        let slot = match slots.iter().position(fits) {
+            Some(slot) => {
+                let (slot_requirements, occupants) = &mut slots[slot];
+                slot_requirements.size = slot_requirements.size.max(requirements.size);
+                slot_requirements.alignment =
+                    slot_requirements.alignment.max(requirements.alignment);
+                slot_requirements.memory_type_bits &= requirements.memory_type_bits;
+                occupants.push(index);
+                slot
+            }
+            None => {
+                slots.push((requirements, vec![index]));
+                slots.len() - 1
+            }
+        };
+        slot_of[index] = Some(slot);
+    }
+    (slots, slot_of)
+}
+
+// Cleans up after `compile` failed, destroying images that weren't bound to memory yet and
+// freeing the memory of the slots
+unsafe fn free_unbound<'i>(
+    device: &Device,
+    images: impl Iterator<Item = &'i (usize, vk::Image, vk::MemoryRequirements)>,
+    memory: &[Allocation],
+) {
+    for (_, image, _) in images {
+        device.device.destroy_image(*image, None);
+    }
+    for allocation in memory {
+        device.free(allocation);
+    }
+}
+
+// A graph ready to be executed. Transient images are kept between executions, so the same
+// compiled graph can be executed every frame. Once done with it, hand it to `Device::retire` with
+// the fence of its last submission, so its images and memory are freed after that work.
+pub struct CompiledRenderGraph<'a> {
+    graph: RenderGraph<'a>,
+    plan: Plan,
+    // Indexed by image, `None` for imported images and transient images that are never used
+    transient_images: Vec<Option<Image>>,
+    // Indexed by position in the execution order, images first used there and the previous
+    // image in their memory
+    first_uses: Vec<Vec<(usize, usize)>>,
+    slot_of: Vec<Option<usize>>,
+    memory: Vec<Allocation>,
+    device: Device,
+}
+
+impl<'a> CompiledRenderGraph<'a> {
+    pub fn image(&self, image: ImageHandle) -> Option<&Image> {
+        match self.graph.images[image.0].1 {
+            GraphImage::Imported(imported) => Some(imported),
+            GraphImage::Transient(_) => self.transient_images[image.0].as_ref(),
+        }
+    }
+
+    // Names of the passes that are executed, in order
+    pub fn pass_order(&self) -> Vec<&str> {
+        self.plan
+            .order
+            .iter()
+            .map(|&index| self.graph.passes[index].name.as_str())
+            .collect()
+    }
+
+    // Same as `RenderGraph::to_dot`, with the memory transient images were assigned to
+    pub fn to_dot(&self) -> String {
+        dot::write(&self.graph, &self.plan, &self.slot_of)
+    }
+
+    // Records every pass into `context`, with the barriers between them
+    pub fn execute(&mut self, device: &Device, context: &Context) {
+        let resources = PassResources {
+            images: self
+                .graph
+                .images
+                .iter()
+                .zip(&self.transient_images)
+                .map(|((_, image), transient)| match image {
+                    GraphImage::Imported(image) => Some(*image),
+                    GraphImage::Transient(_) => transient.as_ref(),
+                })
+                .collect(),
+            buffers: self
+                .graph
+                .buffers
+                .iter()
+                .map(|(_, buffer)| *buffer)
+                .collect(),
+        };
+
+        for (position, &pass_index) in self.plan.order.iter().enumerate() {
+            for &(image, previous) in &self.first_uses[position] {
+                let previous = resources
+                    .image(ImageHandle(previous))
+                    .state
+                    .lock()
+                    .unwrap()
+                    .clone();
+                resources
+                    .image(ImageHandle(image))
+                    .state
+                    .lock()
+                    .unwrap()
+                    .alias(&previous);
+            }
+
+            let pass = &mut self.graph.passes[pass_index];
+            let mut transitions = ResourceTransitions::default();
+            for &(image, usage) in &pass.images {
+                transitions = transitions.with_image(resources.image(image), usage);
+            }
+            for &(buffer, usage) in &pass.buffers {
+                transitions = transitions.with_buffer(resources.buffer(buffer), usage);
+            }
+            transitions.record(device, context);
+
+            (pass.record)(device, context, &resources);
+        }
+    }
+}
+
+// Transient images are destroyed before the memory they alias
+impl Retire for CompiledRenderGraph<'_> {
+    fn into_retired(self) -> RetiredResource {
+        take_fields!(let CompiledRenderGraph {
+            graph,
+            plan,
+            transient_images,
+            first_uses,
+            slot_of,
+            memory,
+            device,
+        } = self);
+        RetiredResource::Group(
+            transient_images
+                .into_iter()
+                .flatten()
+                .map(Retire::into_retired)
+                .chain(memory.into_iter().map(RetiredResource::Memory))
+                .collect(),
+        )
+    }
+}
+
+// Waits for the device to be idle, since the images may still be in use. Retiring the graph with
+// `Device::retire` avoids the wait.
+impl Drop for CompiledRenderGraph<'_> {
+    fn drop(&mut self) {
+        self.device.wait_idle().ok();
+        // Aliasing images don't free their memory
+        self.transient_images.clear();
+        for allocation in &self.memory {
+            unsafe { self.device.free(allocation) };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::descriptors::ShaderStage;
+
+    const SAMPLED: ResourceUsage = ResourceUsage::Sampled(ShaderStage::Fragment);
+    const WRITE: ResourceUsage = ResourceUsage::ColorAttachment;
+
+    fn image(graph: &mut RenderGraph, name: &str) -> ImageHandle {
+        graph.create_image(
+            name,
+            TransientImageDesc::new(
+                ImageResolution::from_width_height(4, 4),
+                ImageType::ColorTarget,
+            ),
+        )
+    }
+
+    fn pass(name: &str) -> Pass<'static> {
+        Pass::new(name, |_, _, _| {})
+    }
+
+    fn order(graph: &RenderGraph) -> Vec<String> {
+        graph
+            .plan()
+            .order
+            .iter()
+            .map(|&index| graph.passes[index].name.clone())
+            .collect()
+    }
+
+    #[test]
+    fn culls_passes_nothing_needs() {
+        let mut graph = RenderGraph::default();
+        let shadow = image(&mut graph, "shadow");
+        let gbuffer = image(&mut graph, "gbuffer");
+        let hdr = image(&mut graph, "hdr");
+        let debug = image(&mut graph, "debug");
+
+        graph.add_pass(pass("shadow").with_image(shadow, WRITE));
+        graph.add_pass(pass("gbuffer").with_image(gbuffer, WRITE));
+        graph.add_pass(pass("debug").with_image(debug, WRITE));
+        graph.add_pass(
+            pass("lighting")
+                .with_image(shadow, SAMPLED)
+                .with_image(gbuffer, SAMPLED)
+                .with_image(hdr, WRITE),
+        );
+        graph.add_pass(pass("present").with_image(hdr, SAMPLED).with_side_effects());
+
+        assert_eq!(order(&graph), ["shadow", "gbuffer", "lighting", "present"]);
+        assert!(!graph.plan().kept[2]);
+        assert!(graph.plan().cyclic.is_empty());
+    }
+
+    #[test]
+    fn reader_sees_the_last_earlier_writer() {
+        let mut graph = RenderGraph::default();
+        let target = image(&mut graph, "target");
+        graph.mark_output(target);
+
+        graph.add_pass(pass("first write").with_image(target, WRITE));
+        graph.add_pass(pass("read").with_image(target, SAMPLED).with_side_effects());
+        graph.add_pass(pass("second write").with_image(target, WRITE));
+
+        let (needs, after) = graph.dependencies();
+        assert_eq!(needs[1], [0]);
+        // The second write waits on the read instead of being read
+        assert_eq!(needs[2], [0]);
+        assert_eq!(after[2], [0, 1]);
+        assert_eq!(order(&graph), ["first write", "read", "second write"]);
+    }
+
+    #[test]
+    fn write_after_read_keeps_only_writers() {
+        let mut graph = RenderGraph::default();
+        let target = image(&mut graph, "target");
+        let unused = image(&mut graph, "unused");
+        graph.mark_output(target);
+
+        graph.add_pass(pass("first write").with_image(target, WRITE));
+        graph.add_pass(
+            pass("read")
+                .with_image(target, SAMPLED)
+                .with_image(unused, WRITE),
+        );
+        graph.add_pass(pass("second write").with_image(target, WRITE));
+
+        assert_eq!(order(&graph), ["first write", "second write"]);
+    }
+
+    #[test]
+    fn reader_before_any_writer_does_not_wait() {
+        let mut graph = RenderGraph::default();
+        let target = image(&mut graph, "target");
+        graph.mark_output(target);
+
+        graph.add_pass(pass("read").with_image(target, SAMPLED).with_side_effects());
+        graph.add_pass(pass("write").with_image(target, WRITE));
+
+        let (needs, after) = graph.dependencies();
+        assert!(needs[0].is_empty());
+        assert!(needs[1].is_empty());
+        assert_eq!(after[1], [0]);
+        assert_eq!(order(&graph), ["read", "write"]);
+    }
+
+    fn requirements(size: u64) -> vk::MemoryRequirements {
+        vk::MemoryRequirements {
+            size,
+            alignment: 256,
+            memory_type_bits: 1,
+        }
+    }
+
+    // Three images each written by one pass and read by the next
+    fn chain(graph: &mut RenderGraph) -> [ImageHandle; 3] {
+        let images = [image(graph, "a"), image(graph, "b"), image(graph, "c")];
+        graph.add_pass(pass("write a").with_image(images[0], WRITE));
+        graph.add_pass(
+            pass("a to b")
+                .with_image(images[0], SAMPLED)
+                .with_image(images[1], WRITE),
+        );
+        graph.add_pass(
+            pass("b to c")
+                .with_image(images[1], SAMPLED)
+                .with_image(images[2], WRITE),
+        );
+        graph.add_pass(
+            pass("read c")
+                .with_image(images[2], SAMPLED)
+                .with_side_effects(),
+        );
+        images
+    }
+
+    fn slots(graph: &RenderGraph) -> Vec<Option<usize>> {
+        let lifetimes = graph.lifetimes(&graph.plan());
+        let raw_images = [(0, 1024), (1, 2048), (2, 512)]
+            .map(|(index, size)| (index, vk::Image::null(), requirements(size)));
+        let (slots, slot_of) = assign_slots(&raw_images, &lifetimes);
+        assert_eq!(slots[0].0.size, 2048);
+        slot_of
+    }
+
+    #[test]
+    fn images_with_disjoint_lifetimes_share_memory() {
+        let mut graph = RenderGraph::default();
+        chain(&mut graph);
+
+        assert_eq!(
+            graph.lifetimes(&graph.plan()),
+            [Some((0, 1)), Some((1, 2)), Some((2, 3))]
+        );
+        // `b` overlaps both others, which can share memory
+        assert_eq!(slots(&graph), [Some(1), Some(0), Some(1)]);
+    }
+
+    #[test]
+    fn outputs_live_until_the_end() {
+        let mut graph = RenderGraph::default();
+        let [a, ..] = chain(&mut graph);
+        graph.mark_output(a);
+
+        assert_eq!(graph.lifetimes(&graph.plan())[0], Some((0, 3)));
+        assert_eq!(slots(&graph), [Some(1), Some(0), Some(2)]);
+    }
+}
@@ -0,0 +1,332 @@
+use crate::{
+    error::Result,
+    render_pass::ClearValue,
+    resources::ash_image::{Image, ImageLayout},
+    swapchain::{flipped_viewport, Swapchain},
+    sync::{ImageMemoryBarrier, PipelineBarrier, ResourceTransitions, ResourceUsage},
+    Context, Device,
+};
+use ash::vk;
+use math::vec::Vec4;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum DynamicRenderingError {
+    #[error("dynamic rendering is not enabled, see `DeviceFeatures::with_dynamic_rendering`")]
+    NotEnabled,
+    #[error("rendering needs at least one attachment")]
+    NoAttachments,
+}
+
+#[derive(Debug, Copy, Clone)]
+pub enum AttachmentLoadOp {
+    Load,
+    Clear(ClearValue),
+    DontCare,
+}
+
+#[derive(Debug, Copy, Clone)]
+pub enum AttachmentStoreOp {
+    Store,
+    DontCare,
+}
+
+impl From<AttachmentStoreOp> for vk::AttachmentStoreOp {
+    fn from(op: AttachmentStoreOp) -> Self {
+        match op {
+            AttachmentStoreOp::Store => vk::AttachmentStoreOp::STORE,
+            AttachmentStoreOp::DontCare => vk::AttachmentStoreOp::DONT_CARE,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub enum AttachmentImage<'a> {
+    // Moved to its attachment layout through its tracked state
+    Image(&'a Image),
+    // A swapchain image, which isn't tracked. It starts out undefined, and is moved to
+    // `PRESENT_SRC_KHR` by `Context::end_rendering`.
+    Present {
+        image: vk::Image,
+        view: vk::ImageView,
+        format: vk::Format,
+        resolution: vk::Extent2D,
+    },
+}
+
+impl AttachmentImage<'_> {
+    fn view(&self) -> vk::ImageView {
+        match self {
+            AttachmentImage::Image(image) => image.view,
+            AttachmentImage::Present { view, .. } => *view,
+        }
+    }
+
+    fn format(&self) -> vk::Format {
+        match self {
+            AttachmentImage::Image(image) => image.format,
+            AttachmentImage::Present { format, .. } => *format,
+        }
+    }
+
+    fn resolution(&self) -> vk::Extent2D {
+        match self {
+            AttachmentImage::Image(image) => vk::Extent2D {
+                width: image.resolution.width,
+                height: image.resolution.height,
+            },
+            AttachmentImage::Present { resolution, .. } => *resolution,
+        }
+    }
+}
+
+// Loads and stores by default
+#[derive(Debug, Copy, Clone)]
+pub struct RenderingAttachment<'a> {
+    pub image: AttachmentImage<'a>,
+    pub load_op: AttachmentLoadOp,
+    pub store_op: AttachmentStoreOp,
+}
+
+impl<'a> RenderingAttachment<'a> {
+    pub fn new(image: &'a Image) -> Self {
+        Self {
+            image: AttachmentImage::Image(image),
+            load_op: AttachmentLoadOp::Load,
+            store_op: AttachmentStoreOp::Store,
+        }
+    }
+
+    // The swapchain image at `present_index`, cleared to black by default since its previous
+    // contents are undefined
+    pub fn present(swapchain: &Swapchain, present_index: u32) -> Self {
+        Self {
+            image: AttachmentImage::Present {
+                image: swapchain.present_images[present_index as usize],
+                view: swapchain.present_image_views[present_index as usize],
+                format: swapchain.surface_data.format.format,
+                resolution: swapchain.surface_data.resolution,
+            },
+            load_op: AttachmentLoadOp::Clear(ClearValue::Color(Vec4::new(0.0, 0.0, 0.0, 1.0))),
+            store_op: AttachmentStoreOp::Store,
+        }
+    }
+
+    pub fn with_clear(self, value: ClearValue) -> Self {
+        self.with_load_op(AttachmentLoadOp::Clear(value))
+    }
+
+    pub fn with_load_op(mut self, load_op: AttachmentLoadOp) -> Self {
+        self.load_op = load_op;
+        self
+    }
+
+    pub fn with_store_op(mut self, store_op: AttachmentStoreOp) -> Self {
+        self.store_op = store_op;
+        self
+    }
+
+    fn to_vk(self, layout: vk::ImageLayout) -> vk::RenderingAttachmentInfo {
+        let (load_op, clear_value) = match self.load_op {
+            AttachmentLoadOp::Load => (vk::AttachmentLoadOp::LOAD, Default::default()),
+            AttachmentLoadOp::Clear(value) => (vk::AttachmentLoadOp::CLEAR, value.into()),
+            AttachmentLoadOp::DontCare => (vk::AttachmentLoadOp::DONT_CARE, Default::default()),
+        };
+        vk::RenderingAttachmentInfo::builder()
+            .image_view(self.image.view())
+            .image_layout(layout)
+            .load_op(load_op)
+            .store_op(self.store_op.into())
+            .clear_value(clear_value)
+            .build()
+    }
+}
+
+// Attachment formats a pipeline used with dynamic rendering is created for
+#[derive(Debug, Clone, Default)]
+pub struct RenderingFormats {
+    pub color: Vec<vk::Format>,
+    pub depth: Option<vk::Format>,
+}
+
+impl RenderingFormats {
+    pub fn with_color(mut self, format: vk::Format) -> Self {
+        self.color.push(format);
+        self
+    }
+
+    pub fn with_depth(mut self, format: vk::Format) -> Self {
+        self.depth = Some(format);
+        self
+    }
+}
+
+// Attachments for `Context::begin_rendering`. The render area covers the first attachment unless
+// set with `with_render_area`.
+#[derive(Debug, Clone, Default)]
+pub struct RenderingDesc<'a> {
+    color_attachments: Vec<RenderingAttachment<'a>>,
+    depth_attachment: Option<RenderingAttachment<'a>>,
+    render_area: Option<vk::Rect2D>,
+}
+
+impl<'a> RenderingDesc<'a> {
+    pub fn with_color_attachment(mut self, attachment: RenderingAttachment<'a>) -> Self {
+        self.color_attachments.push(attachment);
+        self
+    }
+
+    pub fn with_depth_attachment(mut self, attachment: RenderingAttachment<'a>) -> Self {
+        self.depth_attachment = Some(attachment);
+        self
+    }
+
+    pub fn with_render_area(mut self, render_area: vk::Rect2D) -> Self {
+        self.render_area = Some(render_area);
+        self
+    }
+
+    pub fn formats(&self) -> RenderingFormats {
+        RenderingFormats {
+            color: self
+                .color_attachments
+                .iter()
+                .map(|attachment| attachment.image.format())
+                .collect(),
+            depth: self
+                .depth_attachment
+                .map(|attachment| attachment.image.format()),
+        }
+    }
+
+    pub fn render_area(&self) -> vk::Rect2D {
+        self.render_area.unwrap_or_else(|| {
+            self.color_attachments
+                .iter()
+                .chain(&self.depth_attachment)
+                .next()
+                .map_or_else(Default::default, |attachment| {
+                    attachment.image.resolution().into()
+                })
+        })
+    }
+
+    pub fn viewport(&self) -> vk::Viewport {
+        flipped_viewport(self.render_area().extent)
+    }
+
+    pub fn scissor(&self) -> vk::Rect2D {
+        self.render_area()
+    }
+
+    // Swapchain images among the color attachments
+    fn present_images(&self) -> impl Iterator<Item = vk::Image> + '_ {
+        self.color_attachments
+            .iter()
+            .filter_map(|attachment| match attachment.image {
+                AttachmentImage::Present { image, .. } => Some(image),
+                AttachmentImage::Image(_) => None,
+            })
+    }
+
+    // Barrier moving every swapchain image between `old_layout` and `new_layout`, `None` without
+    // swapchain images
+    fn present_barrier(
+        &self,
+        (src_stage, dst_stage): (vk::PipelineStageFlags, vk::PipelineStageFlags),
+        (src_access, dst_access): (vk::AccessFlags, vk::AccessFlags),
+        (old_layout, new_layout): (ImageLayout, ImageLayout),
+    ) -> Option<PipelineBarrier> {
+        let mut images = self.present_images().peekable();
+        images.peek()?;
+        Some(images.fold(
+            PipelineBarrier::new(src_stage, dst_stage),
+            |barrier, image| {
+                barrier.with_image_barrier(
+                    ImageMemoryBarrier::from_raw(
+                        image,
+                        vk::ImageAspectFlags::COLOR,
+                        dst_access,
+                        old_layout,
+                        new_layout,
+                    )
+                    .with_src_access(src_access),
+                )
+            },
+        ))
+    }
+
+    // Moves the attachments to their layouts, then begins rendering. Swapchain images wait on
+    // the color attachment output stage, which is where frames wait on the acquire semaphore.
+    pub(crate) fn begin(&self, device: &Device, context: &Context) -> Result<()> {
+        if !device.enabled_features().dynamic_rendering() {
+            return Err(DynamicRenderingError::NotEnabled.into());
+        }
+        if self.color_attachments.is_empty() && self.depth_attachment.is_none() {
+            return Err(DynamicRenderingError::NoAttachments.into());
+        }
+
+        let mut transitions = ResourceTransitions::default();
+        for attachment in &self.color_attachments {
+            if let AttachmentImage::Image(image) = attachment.image {
+                transitions = transitions.with_image(image, ResourceUsage::ColorAttachment);
+            }
+        }
+        if let Some(attachment) = &self.depth_attachment {
+            if let AttachmentImage::Image(image) = attachment.image {
+                transitions = transitions.with_image(image, ResourceUsage::DepthStencilAttachment);
+            }
+        }
+        transitions.record(device, context);
+        if let Some(barrier) = self.present_barrier(
+            (
+                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+            ),
+            (
+                vk::AccessFlags::empty(),
+                ResourceUsage::ColorAttachment.access(),
+            ),
+            (ImageLayout::Undefined, ImageLayout::ColorAttachment),
+        ) {
+            device.pipeline_barrier(context, &barrier);
+        }
+
+        let color_attachments = self
+            .color_attachments
+            .iter()
+            .map(|attachment| attachment.to_vk(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL))
+            .collect::<Vec<_>>();
+        let depth_attachment = self
+            .depth_attachment
+            .map(|attachment| attachment.to_vk(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL));
+
+        let mut rendering_info = vk::RenderingInfo::builder()
+            .render_area(self.render_area())
+            .layer_count(1)
+            .color_attachments(&color_attachments);
+        if let Some(depth_attachment) = &depth_attachment {
+            rendering_info = rendering_info.depth_attachment(depth_attachment);
+        }
+        device.cmd_begin_rendering(context, &rendering_info);
+        Ok(())
+    }
+
+    // Ends rendering, then moves swapchain images to `PRESENT_SRC_KHR`
+    pub(crate) fn end(&self, device: &Device, context: &Context) {
+        device.cmd_end_rendering(context);
+        if let Some(barrier) = self.present_barrier(
+            (
+                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+                vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+            ),
+            (
+                vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+                vk::AccessFlags::empty(),
+            ),
+            (ImageLayout::ColorAttachment, ImageLayout::PresentSrc),
+        ) {
+            device.pipeline_barrier(context, &barrier);
+        }
+    }
+}
@@ -1,7 +1,23 @@
-use crate::{context::Context, device::Device, swapchain::Swapchain, Image};
-use anyhow::Result;
+use crate::{
+    context::Context,
+    descriptors::ShaderStage,
+    device::Device,
+    error::Result,
+    resources::ash_image::{has_stencil, DepthFormat, ImageLayout},
+    swapchain::{flipped_viewport, Swapchain},
+    sync::{ResourceTransitions, ResourceUsage, TrackedImage},
+    Image,
+};
 use ash::vk;
 use math::vec::Vec4;
+use thiserror::Error;
+
+mod dynamic_rendering;
+
+pub use dynamic_rendering::{
+    AttachmentImage, AttachmentLoadOp, AttachmentStoreOp, DynamicRenderingError,
+    RenderingAttachment, RenderingDesc, RenderingFormats,
+};
 
 #[derive(Debug, Copy, Clone)]
 pub enum ClearValue {
@@ -32,9 +48,16 @@ pub enum RenderPassAttachment {
     DepthLoad,
 }
 
+// Where the attachment images end up after the render pass
+#[derive(Debug, Copy, Clone)]
+enum AttachmentTarget {
+    Present,
+    Sampled,
+}
+
 impl RenderPassAttachment {
-    fn to_vk(self, format: vk::Format) -> vk::AttachmentDescription {
-        match self {
+    fn to_vk(self, format: vk::Format, target: AttachmentTarget) -> vk::AttachmentDescription {
+        let description = match self {
             RenderPassAttachment::ColorClear => vk::AttachmentDescription {
                 format,
                 samples: vk::SampleCountFlags::TYPE_1,
@@ -53,7 +76,7 @@ impl RenderPassAttachment {
                 ..Default::default()
             },
             RenderPassAttachment::DepthClear => vk::AttachmentDescription {
-                format: vk::Format::D16_UNORM,
+                format,
                 samples: vk::SampleCountFlags::TYPE_1,
                 load_op: vk::AttachmentLoadOp::CLEAR,
                 store_op: vk::AttachmentStoreOp::STORE,
@@ -62,7 +85,7 @@ impl RenderPassAttachment {
                 ..Default::default()
             },
             RenderPassAttachment::DepthLoad => vk::AttachmentDescription {
-                format: vk::Format::D16_UNORM,
+                format,
                 samples: vk::SampleCountFlags::TYPE_1,
                 load_op: vk::AttachmentLoadOp::LOAD,
                 store_op: vk::AttachmentStoreOp::STORE,
@@ -70,6 +93,703 @@ impl RenderPassAttachment {
                 final_layout: vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
                 ..Default::default()
             },
+        };
+        match target {
+            AttachmentTarget::Present => description,
+            // Offscreen targets are left ready to be sampled, and come back from being sampled
+            AttachmentTarget::Sampled => vk::AttachmentDescription {
+                initial_layout: match self {
+                    RenderPassAttachment::ColorClear | RenderPassAttachment::DepthClear => {
+                        vk::ImageLayout::UNDEFINED
+                    }
+                    RenderPassAttachment::ColorLoad | RenderPassAttachment::DepthLoad => {
+                        vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL
+                    }
+                },
+                final_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                ..description
+            },
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum RenderPassError {
+    #[error("render pass has {expected} color attachments, but {found} images were given")]
+    ColorAttachmentCountMismatch { expected: usize, found: usize },
+    #[error("render pass depth attachment and depth image don't match")]
+    DepthAttachmentMismatch,
+    #[error("image for attachment {attachment} is missing usage {missing:?}")]
+    MissingImageUsage {
+        attachment: usize,
+        missing: vk::ImageUsageFlags,
+    },
+    #[error("image for attachment {attachment} is {found:?}, but the attachment is {expected:?}")]
+    FormatMismatch {
+        attachment: usize,
+        expected: vk::Format,
+        found: vk::Format,
+    },
+    #[error("depth format {0:?} can't be used as a depth attachment on this device")]
+    UnsupportedDepthFormat(vk::Format),
+    #[error("subpass {subpass} uses attachment {attachment}, which the render pass doesn't have")]
+    InvalidSubpassAttachment { subpass: usize, attachment: u32 },
+    #[error("subpass {subpass} uses attachment {attachment} as both color and input attachment")]
+    ColorAttachmentAlsoInput { subpass: usize, attachment: u32 },
+    #[error("attachment {attachment} has {expected:?} samples, but the image has {found:?}")]
+    SampleCountMismatch {
+        attachment: usize,
+        expected: vk::SampleCountFlags,
+        found: vk::SampleCountFlags,
+    },
+    #[error("the color and depth attachments of subpass {subpass} have different sample counts")]
+    SubpassSampleCountMismatch { subpass: usize },
+    #[error(
+        "subpass {subpass} can't resolve into attachment {attachment}, it has to be single \
+         sampled, of the format of a multisampled color attachment and not otherwise used"
+    )]
+    InvalidResolveAttachment { subpass: usize, attachment: u32 },
+}
+
+// One attachment of a `RenderPassDesc`. By default the previous contents are discarded, and the
+// image is stored and left ready to be sampled. Attachments are single sampled unless changed, and
+// need images with the same sample count.
+#[derive(Debug, Copy, Clone)]
+pub struct AttachmentDesc {
+    pub format: vk::Format,
+    pub samples: vk::SampleCountFlags,
+    pub load_op: AttachmentLoadOp,
+    pub store_op: AttachmentStoreOp,
+    pub initial_layout: ImageLayout,
+    pub final_layout: ImageLayout,
+}
+
+impl AttachmentDesc {
+    pub fn new(format: vk::Format) -> Self {
+        Self {
+            format,
+            samples: vk::SampleCountFlags::TYPE_1,
+            load_op: AttachmentLoadOp::DontCare,
+            store_op: AttachmentStoreOp::Store,
+            initial_layout: ImageLayout::Undefined,
+            final_layout: ImageLayout::ShaderReadOnly,
+        }
+    }
+
+    pub fn depth(format: DepthFormat) -> Self {
+        Self::new(format.into())
+    }
+
+    // Multisampled color attachments are usually resolved with
+    // `SubpassDesc::with_resolved_color_attachment`
+    pub fn with_samples(mut self, samples: vk::SampleCountFlags) -> Self {
+        self.samples = samples;
+        self
+    }
+
+    pub fn with_clear(self, value: ClearValue) -> Self {
+        self.with_load_op(AttachmentLoadOp::Clear(value))
+    }
+
+    // `initial_layout` has to be set to the layout the image is in for `AttachmentLoadOp::Load`
+    pub fn with_load_op(mut self, load_op: AttachmentLoadOp) -> Self {
+        self.load_op = load_op;
+        self
+    }
+
+    pub fn with_store_op(mut self, store_op: AttachmentStoreOp) -> Self {
+        self.store_op = store_op;
+        self
+    }
+
+    pub fn with_layouts(mut self, initial_layout: ImageLayout, final_layout: ImageLayout) -> Self {
+        self.initial_layout = initial_layout;
+        self.final_layout = final_layout;
+        self
+    }
+
+    fn clear_value(&self) -> vk::ClearValue {
+        match self.load_op {
+            AttachmentLoadOp::Clear(value) => value.into(),
+            AttachmentLoadOp::Load | AttachmentLoadOp::DontCare => Default::default(),
+        }
+    }
+
+    // Stencil is loaded and stored like depth for formats that have it
+    fn to_vk(self) -> vk::AttachmentDescription {
+        let load_op = match self.load_op {
+            AttachmentLoadOp::Load => vk::AttachmentLoadOp::LOAD,
+            AttachmentLoadOp::Clear(_) => vk::AttachmentLoadOp::CLEAR,
+            AttachmentLoadOp::DontCare => vk::AttachmentLoadOp::DONT_CARE,
+        };
+        let store_op = self.store_op.into();
+        let (stencil_load_op, stencil_store_op) = if has_stencil(self.format) {
+            (load_op, store_op)
+        } else {
+            (
+                vk::AttachmentLoadOp::DONT_CARE,
+                vk::AttachmentStoreOp::DONT_CARE,
+            )
+        };
+        vk::AttachmentDescription {
+            format: self.format,
+            samples: self.samples,
+            load_op,
+            store_op,
+            stencil_load_op,
+            stencil_store_op,
+            initial_layout: self.initial_layout.into(),
+            final_layout: self.final_layout.into(),
+            ..Default::default()
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum SubpassDepth {
+    None,
+    ReadWrite,
+    // Depth testing without writes, so depth can also be an input attachment
+    ReadOnly,
+}
+
+// Attachments used by a subpass, as indices into the render pass attachments: color attachments in
+// the order they were added, then depth
+#[derive(Debug, Clone)]
+pub struct SubpassDesc {
+    color_attachments: Vec<u32>,
+    // One per color attachment
+    resolve_attachments: Vec<Option<u32>>,
+    input_attachments: Vec<u32>,
+    depth: SubpassDepth,
+}
+
+impl Default for SubpassDesc {
+    fn default() -> Self {
+        Self {
+            color_attachments: Vec::new(),
+            resolve_attachments: Vec::new(),
+            input_attachments: Vec::new(),
+            depth: SubpassDepth::None,
+        }
+    }
+}
+
+impl SubpassDesc {
+    pub fn with_color_attachment(mut self, attachment: u32) -> Self {
+        self.color_attachments.push(attachment);
+        self.resolve_attachments.push(None);
+        self
+    }
+
+    // Multisampled `attachment` is resolved into single sampled `resolve` at the end of the
+    // subpass
+    pub fn with_resolved_color_attachment(mut self, attachment: u32, resolve: u32) -> Self {
+        self.color_attachments.push(attachment);
+        self.resolve_attachments.push(Some(resolve));
+        self
+    }
+
+    // Color attachments written by the subpass, including resolves
+    fn writes(&self) -> impl Iterator<Item = u32> + '_ {
+        self.color_attachments
+            .iter()
+            .copied()
+            .chain(self.resolve_attachments.iter().flatten().copied())
+    }
+
+    // Read in the fragment shader with `subpassLoad`, through a `DescriptorType::InputAttachment`
+    // binding
+    pub fn with_input_attachment(mut self, attachment: u32) -> Self {
+        self.input_attachments.push(attachment);
+        self
+    }
+
+    pub fn with_depth_attachment(mut self) -> Self {
+        self.depth = SubpassDepth::ReadWrite;
+        self
+    }
+
+    pub fn with_read_only_depth_attachment(mut self) -> Self {
+        self.depth = SubpassDepth::ReadOnly;
+        self
+    }
+}
+
+// Attachment references of a subpass, kept alive while the render pass is created
+struct SubpassReferences {
+    color: Vec<vk::AttachmentReference>,
+    // Empty when nothing is resolved
+    resolve: Vec<vk::AttachmentReference>,
+    input: Vec<vk::AttachmentReference>,
+    depth: Option<vk::AttachmentReference>,
+}
+
+impl SubpassReferences {
+    fn new(subpass: &SubpassDesc, depth_index: u32) -> Self {
+        let depth_read_only = subpass.depth == SubpassDepth::ReadOnly
+            || subpass.input_attachments.contains(&depth_index);
+        let depth_layout = if depth_read_only {
+            vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL
+        } else {
+            vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL
+        };
+        Self {
+            color: subpass
+                .color_attachments
+                .iter()
+                .map(|&attachment| vk::AttachmentReference {
+                    attachment,
+                    layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+                })
+                .collect(),
+            resolve: if subpass.resolve_attachments.iter().any(Option::is_some) {
+                subpass
+                    .resolve_attachments
+                    .iter()
+                    .map(|resolve| vk::AttachmentReference {
+                        attachment: resolve.unwrap_or(vk::ATTACHMENT_UNUSED),
+                        layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+                    })
+                    .collect()
+            } else {
+                Vec::new()
+            },
+            input: subpass
+                .input_attachments
+                .iter()
+                .map(|&attachment| vk::AttachmentReference {
+                    attachment,
+                    layout: if attachment == depth_index {
+                        depth_layout
+                    } else {
+                        vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL
+                    },
+                })
+                .collect(),
+            depth: (subpass.depth != SubpassDepth::None).then_some(vk::AttachmentReference {
+                attachment: depth_index,
+                layout: depth_layout,
+            }),
+        }
+    }
+
+    fn to_vk(&self) -> vk::SubpassDescription {
+        let mut subpass = vk::SubpassDescription::builder()
+            .color_attachments(&self.color)
+            .input_attachments(&self.input)
+            .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS);
+        if !self.resolve.is_empty() {
+            subpass = subpass.resolve_attachments(&self.resolve);
+        }
+        if let Some(depth) = &self.depth {
+            subpass = subpass.depth_stencil_attachment(depth);
+        }
+        subpass.build()
+    }
+}
+
+// Execution and memory dependency between two subpasses, or between a subpass and commands
+// outside the render pass with `vk::SUBPASS_EXTERNAL`. By region unless changed.
+#[derive(Debug, Copy, Clone)]
+pub struct SubpassDependency {
+    raw: vk::SubpassDependency,
+}
+
+impl SubpassDependency {
+    pub fn new(src_subpass: u32, dst_subpass: u32) -> Self {
+        Self {
+            raw: vk::SubpassDependency {
+                src_subpass,
+                dst_subpass,
+                dependency_flags: vk::DependencyFlags::BY_REGION,
+                ..Default::default()
+            },
+        }
+    }
+
+    // Attachments written by `src_subpass` are read as input attachments by `dst_subpass`
+    pub fn input_attachment(src_subpass: u32, dst_subpass: u32) -> Self {
+        Self::new(src_subpass, dst_subpass)
+            .with_src(
+                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+                    | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS,
+                vk::AccessFlags::COLOR_ATTACHMENT_WRITE
+                    | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+            )
+            .with_dst(
+                vk::PipelineStageFlags::FRAGMENT_SHADER,
+                vk::AccessFlags::INPUT_ATTACHMENT_READ,
+            )
+    }
+
+    pub fn with_src(
+        mut self,
+        stage: impl Into<vk::PipelineStageFlags>,
+        access: impl Into<vk::AccessFlags>,
+    ) -> Self {
+        self.raw.src_stage_mask = stage.into();
+        self.raw.src_access_mask = access.into();
+        self
+    }
+
+    pub fn with_dst(
+        mut self,
+        stage: impl Into<vk::PipelineStageFlags>,
+        access: impl Into<vk::AccessFlags>,
+    ) -> Self {
+        self.raw.dst_stage_mask = stage.into();
+        self.raw.dst_access_mask = access.into();
+        self
+    }
+
+    pub fn with_dependency_flags(mut self, flags: impl Into<vk::DependencyFlags>) -> Self {
+        self.raw.dependency_flags = flags.into();
+        self
+    }
+}
+
+// Any number of color attachments and an optional depth/stencil attachment. Without subpasses,
+// there is a single one using every attachment. The first subpass waits on earlier sampling of
+// the attachments, later sampling waits on the last subpass, and subpasses reading input
+// attachments wait on the earlier subpasses writing them. Dependencies added with
+// `with_dependency` come on top of those.
+#[derive(Debug, Clone, Default)]
+pub struct RenderPassDesc {
+    color_attachments: Vec<AttachmentDesc>,
+    depth_attachment: Option<AttachmentDesc>,
+    subpasses: Vec<SubpassDesc>,
+    dependencies: Vec<SubpassDependency>,
+}
+
+impl RenderPassDesc {
+    pub fn with_color_attachment(mut self, attachment: AttachmentDesc) -> Self {
+        self.color_attachments.push(attachment);
+        self
+    }
+
+    pub fn with_depth_attachment(mut self, attachment: AttachmentDesc) -> Self {
+        self.depth_attachment = Some(attachment);
+        self
+    }
+
+    // Subpasses run in the order they are added, `Context::next_subpass` moves to the next one
+    pub fn with_subpass(mut self, subpass: SubpassDesc) -> Self {
+        self.subpasses.push(subpass);
+        self
+    }
+
+    pub fn with_dependency(mut self, dependency: SubpassDependency) -> Self {
+        self.dependencies.push(dependency);
+        self
+    }
+
+    pub fn color_attachments(&self) -> &[AttachmentDesc] {
+        &self.color_attachments
+    }
+
+    pub fn depth_attachment(&self) -> Option<&AttachmentDesc> {
+        self.depth_attachment.as_ref()
+    }
+
+    // Index of the depth attachment in subpasses
+    pub fn depth_attachment_index(&self) -> u32 {
+        self.color_attachments.len() as u32
+    }
+
+    fn check_subpasses(&self) -> Result<()> {
+        let color_count = self.color_attachments.len() as u32;
+        let depth_index = self.depth_attachment.is_some().then_some(color_count);
+        for (index, subpass) in self.subpasses.iter().enumerate() {
+            let invalid = |attachment| RenderPassError::InvalidSubpassAttachment {
+                subpass: index,
+                attachment,
+            };
+            if let Some(&attachment) = subpass
+                .color_attachments
+                .iter()
+                .find(|&&attachment| attachment >= color_count)
+            {
+                return Err(invalid(attachment).into());
+            }
+            if let Some(&attachment) = subpass
+                .input_attachments
+                .iter()
+                .find(|&&attachment| attachment >= color_count && Some(attachment) != depth_index)
+            {
+                return Err(invalid(attachment).into());
+            }
+            if subpass.depth != SubpassDepth::None && depth_index.is_none() {
+                return Err(invalid(color_count).into());
+            }
+            // Would be a feedback loop, which needs the `GENERAL` layout and barriers inside the
+            // subpass
+            if let Some(&attachment) = subpass
+                .color_attachments
+                .iter()
+                .find(|attachment| subpass.input_attachments.contains(attachment))
+            {
+                return Err(RenderPassError::ColorAttachmentAlsoInput {
+                    subpass: index,
+                    attachment,
+                }
+                .into());
+            }
+            let resolves = subpass
+                .color_attachments
+                .iter()
+                .zip(&subpass.resolve_attachments)
+                .filter_map(|(&attachment, resolve)| resolve.map(|resolve| (attachment, resolve)));
+            for (attachment, resolve) in resolves {
+                if resolve >= color_count {
+                    return Err(invalid(resolve).into());
+                }
+                let source = &self.color_attachments[attachment as usize];
+                let target = &self.color_attachments[resolve as usize];
+                let used_elsewhere = subpass.color_attachments.contains(&resolve)
+                    || subpass.input_attachments.contains(&resolve)
+                    || subpass
+                        .resolve_attachments
+                        .iter()
+                        .filter(|&&other| other == Some(resolve))
+                        .count()
+                        > 1;
+                if source.samples == vk::SampleCountFlags::TYPE_1
+                    || target.samples != vk::SampleCountFlags::TYPE_1
+                    || source.format != target.format
+                    || used_elsewhere
+                {
+                    return Err(RenderPassError::InvalidResolveAttachment {
+                        subpass: index,
+                        attachment: resolve,
+                    }
+                    .into());
+                }
+            }
+            let depth_samples = self
+                .depth_attachment
+                .filter(|_| subpass.depth != SubpassDepth::None)
+                .map(|depth| depth.samples);
+            let mut samples = subpass
+                .color_attachments
+                .iter()
+                .map(|&attachment| self.color_attachments[attachment as usize].samples)
+                .chain(depth_samples);
+            if let Some(first) = samples.next() {
+                if samples.any(|samples| samples != first) {
+                    return Err(
+                        RenderPassError::SubpassSampleCountMismatch { subpass: index }.into(),
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Every subpass reading an input attachment waits on the earlier subpasses writing it
+    fn input_dependencies(&self) -> Vec<SubpassDependency> {
+        let subpasses = self.subpasses();
+        let depth_index = self.depth_attachment_index();
+        let mut dependencies = Vec::new();
+        for (dst, subpass) in subpasses.iter().enumerate() {
+            for (src, earlier) in subpasses[..dst].iter().enumerate() {
+                let writes = |attachment: &u32| {
+                    earlier.writes().any(|written| written == *attachment)
+                        || (*attachment == depth_index && earlier.depth == SubpassDepth::ReadWrite)
+                };
+                if subpass.input_attachments.iter().any(writes) {
+                    dependencies.push(SubpassDependency::input_attachment(src as u32, dst as u32));
+                }
+            }
+        }
+        dependencies
+    }
+
+    // Color attachments first, then depth
+    fn attachments(&self) -> impl Iterator<Item = &AttachmentDesc> {
+        self.color_attachments.iter().chain(&self.depth_attachment)
+    }
+
+    fn subpasses(&self) -> Vec<SubpassDesc> {
+        if !self.subpasses.is_empty() {
+            return self.subpasses.clone();
+        }
+        let mut subpass = SubpassDesc::default();
+        for attachment in 0..self.color_attachments.len() {
+            subpass = subpass.with_color_attachment(attachment as u32);
+        }
+        if self.depth_attachment.is_some() {
+            subpass = subpass.with_depth_attachment();
+        }
+        vec![subpass]
+    }
+}
+
+// The attachments a render pass was created with
+#[derive(Debug, Clone)]
+enum PassAttachments {
+    // A color and a depth attachment, in the formats of the framebuffer images
+    Single {
+        color: RenderPassAttachment,
+        depth: RenderPassAttachment,
+    },
+    Desc(RenderPassDesc),
+}
+
+impl PassAttachments {
+    fn color_count(&self, subpass: u32) -> usize {
+        match self {
+            PassAttachments::Single { .. } => 1,
+            PassAttachments::Desc(desc) => desc
+                .subpasses()
+                .get(subpass as usize)
+                .map_or(0, |subpass| subpass.color_attachments.len()),
+        }
+    }
+
+    // Sample count of the attachments the subpass renders into, if it has any
+    fn samples(&self, subpass: u32) -> Option<vk::SampleCountFlags> {
+        match self {
+            PassAttachments::Single { .. } => Some(vk::SampleCountFlags::TYPE_1),
+            PassAttachments::Desc(desc) => {
+                let subpass = desc.subpasses().get(subpass as usize)?.clone();
+                subpass
+                    .color_attachments
+                    .first()
+                    .map(|&attachment| desc.color_attachments[attachment as usize].samples)
+                    .or_else(|| {
+                        desc.depth_attachment
+                            .filter(|_| subpass.depth != SubpassDepth::None)
+                            .map(|depth| depth.samples)
+                    })
+            }
+        }
+    }
+
+    fn subpasses(&self) -> Vec<SubpassDesc> {
+        match self {
+            PassAttachments::Single { .. } => vec![SubpassDesc::default()
+                .with_color_attachment(0)
+                .with_depth_attachment()],
+            PassAttachments::Desc(desc) => desc.subpasses(),
+        }
+    }
+
+    fn depth_index(&self) -> u32 {
+        match self {
+            PassAttachments::Single { .. } => 1,
+            PassAttachments::Desc(desc) => desc.depth_attachment_index(),
+        }
+    }
+
+    fn descriptions(&self, targets: &FramebufferTargets) -> Vec<vk::AttachmentDescription> {
+        match self {
+            PassAttachments::Single { color, depth } => vec![
+                color.to_vk(targets.formats[0], targets.target),
+                depth.to_vk(targets.formats[1], targets.target),
+            ],
+            PassAttachments::Desc(desc) => desc
+                .attachments()
+                .map(|attachment| attachment.to_vk())
+                .collect(),
+        }
+    }
+
+    // Dependencies between subpasses for input attachments, then the explicit ones of the desc
+    fn dependencies(&self) -> Vec<vk::SubpassDependency> {
+        match self {
+            PassAttachments::Single { .. } => Vec::new(),
+            PassAttachments::Desc(desc) => desc
+                .input_dependencies()
+                .iter()
+                .chain(&desc.dependencies)
+                .map(|dependency| dependency.raw)
+                .collect(),
+        }
+    }
+}
+
+// Usage an image needs to be moved to before a render pass that expects it in `layout`
+fn layout_usage(layout: vk::ImageLayout) -> Option<ResourceUsage> {
+    match layout {
+        vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL => Some(ResourceUsage::ColorAttachment),
+        vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL => {
+            Some(ResourceUsage::DepthStencilAttachment)
+        }
+        vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL => {
+            Some(ResourceUsage::DepthStencilReadOnly(ShaderStage::Fragment))
+        }
+        vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL => {
+            Some(ResourceUsage::Sampled(ShaderStage::Fragment))
+        }
+        vk::ImageLayout::GENERAL => Some(ResourceUsage::StorageWrite(ShaderStage::Fragment)),
+        vk::ImageLayout::TRANSFER_SRC_OPTIMAL => Some(ResourceUsage::TransferSrc),
+        vk::ImageLayout::TRANSFER_DST_OPTIMAL => Some(ResourceUsage::TransferDst),
+        _ => None,
+    }
+}
+
+// Offscreen attachment, moved to its initial layout when the render pass begins and tracked in
+// its final layout once it ends
+#[derive(Debug, Clone)]
+struct TrackedAttachment {
+    image: TrackedImage,
+    initial_layout: vk::ImageLayout,
+    final_layout: ImageLayout,
+}
+
+struct FramebufferTargets {
+    // One per attachment, color attachments first
+    formats: Vec<vk::Format>,
+    // Offscreen images, one per attachment. Swapchain images are not tracked.
+    tracked: Vec<TrackedImage>,
+    // One set of views per framebuffer
+    attachments: Vec<Vec<vk::ImageView>>,
+    resolution: vk::Extent2D,
+    target: AttachmentTarget,
+    swapchain_generation: Option<u64>,
+}
+
+impl FramebufferTargets {
+    fn from_swapchain(swapchain: &Swapchain) -> Self {
+        Self {
+            formats: vec![
+                swapchain.surface_data.format.format,
+                swapchain.depth_image.format,
+            ],
+            tracked: Vec::new(),
+            attachments: swapchain
+                .present_image_views
+                .iter()
+                .map(|&present_image_view| vec![present_image_view, swapchain.depth_image.view])
+                .collect(),
+            resolution: swapchain.surface_data.resolution,
+            target: AttachmentTarget::Present,
+            swapchain_generation: Some(swapchain.generation()),
+        }
+    }
+
+    fn from_images(color_images: &[&Image], depth_image: Option<&Image>) -> Self {
+        let images = color_images
+            .iter()
+            .copied()
+            .chain(depth_image)
+            .collect::<Vec<_>>();
+        Self {
+            formats: images.iter().map(|image| image.format).collect(),
+            tracked: images
+                .iter()
+                .map(|image| TrackedImage::new(image))
+                .collect(),
+            attachments: vec![images.iter().map(|image| image.view).collect()],
+            resolution: images
+                .first()
+                .map_or_else(Default::default, |image| vk::Extent2D {
+                    width: image.resolution.width,
+                    height: image.resolution.height,
+                }),
+            target: AttachmentTarget::Sampled,
+            swapchain_generation: None,
         }
     }
 }
@@ -77,41 +797,68 @@ impl RenderPassAttachment {
 pub struct RenderPass {
     pub render_pass: vk::RenderPass,
     pub framebuffers: Vec<vk::Framebuffer>,
-    color_attachment: RenderPassAttachment,
-    depth_attachment: RenderPassAttachment,
+    attachments: PassAttachments,
     clear_values: Vec<vk::ClearValue>,
     render_area: vk::Rect2D,
+    tracked_attachments: Vec<TrackedAttachment>,
+    // Generation of the swapchain the framebuffers were created from, `None` for offscreen passes
+    swapchain_generation: Option<u64>,
+    device: Device,
 }
 
 impl RenderPass {
     fn create_render_pass_structures(
         device: &Device,
-        swapchain: &Swapchain,
-        color_attachment: RenderPassAttachment,
-        depth_attachment: RenderPassAttachment,
+        targets: &FramebufferTargets,
+        attachments: &PassAttachments,
     ) -> Result<(vk::RenderPass, Vec<vk::Framebuffer>, vk::Rect2D)> {
-        // TODO: hard-coded for now
-        let renderpass_attachments = [
-            color_attachment.to_vk(swapchain.surface_data.format.format),
-            depth_attachment.to_vk(swapchain.surface_data.format.format),
-        ];
-        let color_attachment_refs = [vk::AttachmentReference {
-            attachment: 0,
-            layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
-        }];
-        let depth_attachment_ref = vk::AttachmentReference {
-            attachment: 1,
-            layout: vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
-        };
+        let renderpass_attachments = attachments.descriptions(targets);
+        let subpass_references = attachments
+            .subpasses()
+            .iter()
+            .map(|subpass| SubpassReferences::new(subpass, attachments.depth_index()))
+            .collect::<Vec<_>>();
+        let subpasses = subpass_references
+            .iter()
+            .map(SubpassReferences::to_vk)
+            .collect::<Vec<_>>();
+        let last_subpass = subpasses.len() as u32 - 1;
 
-        let subpass = vk::SubpassDescription::builder()
-            .color_attachments(&color_attachment_refs)
-            .depth_stencil_attachment(&depth_attachment_ref)
-            .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS);
+        // Offscreen targets are sampled by later passes, so writes need to be made visible
+        let attachment_stages = vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+            | vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS
+            | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS;
+        let attachment_writes = vk::AccessFlags::COLOR_ATTACHMENT_WRITE
+            | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE;
+        let mut dependencies = match targets.target {
+            AttachmentTarget::Present => vec![],
+            AttachmentTarget::Sampled => vec![
+                vk::SubpassDependency {
+                    src_subpass: vk::SUBPASS_EXTERNAL,
+                    dst_subpass: 0,
+                    src_stage_mask: vk::PipelineStageFlags::FRAGMENT_SHADER,
+                    dst_stage_mask: attachment_stages,
+                    src_access_mask: vk::AccessFlags::SHADER_READ,
+                    dst_access_mask: attachment_writes,
+                    dependency_flags: vk::DependencyFlags::BY_REGION,
+                },
+                vk::SubpassDependency {
+                    src_subpass: last_subpass,
+                    dst_subpass: vk::SUBPASS_EXTERNAL,
+                    src_stage_mask: attachment_stages,
+                    dst_stage_mask: vk::PipelineStageFlags::FRAGMENT_SHADER,
+                    src_access_mask: attachment_writes,
+                    dst_access_mask: vk::AccessFlags::SHADER_READ,
+                    dependency_flags: vk::DependencyFlags::BY_REGION,
+                },
+            ],
+        };
+        dependencies.extend(attachments.dependencies());
 
         let renderpass_create_info = vk::RenderPassCreateInfo::builder()
             .attachments(&renderpass_attachments)
-            .subpasses(std::slice::from_ref(&subpass));
+            .subpasses(&subpasses)
+            .dependencies(&dependencies);
 
         let render_pass = unsafe {
             device
@@ -119,16 +866,15 @@ impl RenderPass {
                 .create_render_pass(&renderpass_create_info, None)?
         };
 
-        let framebuffers = swapchain
-            .present_image_views
+        let framebuffers = targets
+            .attachments
             .iter()
-            .map(|&present_image_view| {
-                let framebuffer_attachments = [present_image_view, swapchain.depth_image.view];
+            .map(|framebuffer_attachments| {
                 let frame_buffer_create_info = vk::FramebufferCreateInfo::builder()
                     .render_pass(render_pass)
-                    .attachments(&framebuffer_attachments)
-                    .width(swapchain.surface_data.resolution.width)
-                    .height(swapchain.surface_data.resolution.height)
+                    .attachments(framebuffer_attachments)
+                    .width(targets.resolution.width)
+                    .height(targets.resolution.height)
                     .layers(1);
 
                 unsafe {
@@ -140,59 +886,314 @@ impl RenderPass {
             .collect::<Result<Vec<_>, _>>()?;
 
         // TODO: Make this configurable
-        let render_area = swapchain.surface_data.resolution.into();
+        let render_area = targets.resolution.into();
 
         Ok((render_pass, framebuffers, render_area))
     }
 
-    pub fn new(
+    fn from_targets(
         device: &Device,
-        swapchain: &Swapchain,
-        color_attachment: RenderPassAttachment,
-        depth_attachment: RenderPassAttachment,
-        clear_values: &[ClearValue],
+        targets: &FramebufferTargets,
+        attachments: PassAttachments,
+        clear_values: Vec<vk::ClearValue>,
     ) -> Result<Self> {
-        let clear_values = clear_values
-            .iter()
-            .map(|val| {
-                let ret: vk::ClearValue = (*val).into();
-                ret
-            })
-            .collect::<Vec<_>>();
-        let (render_pass, framebuffers, render_area) = Self::create_render_pass_structures(
-            device,
-            swapchain,
-            color_attachment,
-            depth_attachment,
-        )?;
+        let (render_pass, framebuffers, render_area) =
+            Self::create_render_pass_structures(device, targets, &attachments)?;
 
         Ok(Self {
             render_pass,
             framebuffers,
-            color_attachment,
-            depth_attachment,
+            tracked_attachments: Self::track_attachments(targets, &attachments),
+            attachments,
             clear_values,
             render_area,
+            swapchain_generation: targets.swapchain_generation,
+            device: device.clone(),
         })
     }
 
-    pub fn resize(&mut self, device: &Device, swapchain: &Swapchain) -> Result<()> {
-        unsafe {
-            self.clean(device);
+    fn track_attachments(
+        targets: &FramebufferTargets,
+        attachments: &PassAttachments,
+    ) -> Vec<TrackedAttachment> {
+        targets
+            .tracked
+            .iter()
+            .zip(attachments.descriptions(targets))
+            .map(|(image, description)| TrackedAttachment {
+                image: image.clone(),
+                initial_layout: description.initial_layout,
+                // Attachment descriptions are built from `ImageLayout`s
+                final_layout: description
+                    .final_layout
+                    .try_into()
+                    .unwrap_or(ImageLayout::Undefined),
+            })
+            .collect()
+    }
+
+    // Attachments have to be usable as attachments, and sampled if that is how the render pass
+    // leaves them
+    fn check_usage(images: &[&Image], final_layouts: &[vk::ImageLayout]) -> Result<()> {
+        for (attachment, (image, &final_layout)) in images.iter().zip(final_layouts).enumerate() {
+            let mut required = if image.aspect_mask().contains(vk::ImageAspectFlags::COLOR) {
+                vk::ImageUsageFlags::COLOR_ATTACHMENT
+            } else {
+                vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT
+            };
+            if final_layout == vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL {
+                required |= vk::ImageUsageFlags::SAMPLED;
+            }
+            let missing = required & !image.ty.usage();
+            if !missing.is_empty() {
+                return Err(RenderPassError::MissingImageUsage {
+                    attachment,
+                    missing,
+                }
+                .into());
+            }
         }
-        let (render_pass, framebuffers, render_area) = Self::create_render_pass_structures(
+        Ok(())
+    }
+
+    fn to_clear_values(clear_values: &[ClearValue]) -> Vec<vk::ClearValue> {
+        clear_values
+            .iter()
+            .map(|val| {
+                let ret: vk::ClearValue = (*val).into();
+                ret
+            })
+            .collect::<Vec<_>>()
+    }
+
+    pub fn new(
+        device: &Device,
+        swapchain: &Swapchain,
+        color_attachment: RenderPassAttachment,
+        depth_attachment: RenderPassAttachment,
+        clear_values: &[ClearValue],
+    ) -> Result<Self> {
+        Self::from_targets(
             device,
-            swapchain,
-            self.color_attachment,
-            self.depth_attachment,
+            &FramebufferTargets::from_swapchain(swapchain),
+            PassAttachments::Single {
+                color: color_attachment,
+                depth: depth_attachment,
+            },
+            Self::to_clear_values(clear_values),
+        )
+    }
+
+    // Renders into `color_image` and `depth_image` instead of the swapchain. Both images are
+    // left in `SHADER_READ_ONLY_OPTIMAL`, ready to be sampled with `new_descriptor_image_info`, so
+    // they need sampled usage like `ImageType::ColorTarget` and `ImageType::DepthTarget`.
+    // There is a single framebuffer, so `begin` should be called with index 0.
+    pub fn new_offscreen(
+        device: &Device,
+        color_image: &Image,
+        depth_image: &Image,
+        color_attachment: RenderPassAttachment,
+        depth_attachment: RenderPassAttachment,
+        clear_values: &[ClearValue],
+    ) -> Result<Self> {
+        Self::check_usage(
+            &[color_image, depth_image],
+            &[vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL; 2],
         )?;
+        Self::from_targets(
+            device,
+            &FramebufferTargets::from_images(&[color_image], Some(depth_image)),
+            PassAttachments::Single {
+                color: color_attachment,
+                depth: depth_attachment,
+            },
+            Self::to_clear_values(clear_values),
+        )
+    }
+
+    // Renders into `color_images` and `depth_image`, one per attachment of `desc` in the same
+    // order, for multiple render targets like G-buffers. Clear values come from the attachments'
+    // load ops. There is a single framebuffer, so `begin` should be called with index 0.
+    pub fn from_desc(
+        device: &Device,
+        desc: &RenderPassDesc,
+        color_images: &[&Image],
+        depth_image: Option<&Image>,
+    ) -> Result<Self> {
+        desc.check_subpasses()?;
+        Self::check_images(device, desc, color_images, depth_image)?;
+        Self::from_targets(
+            device,
+            &FramebufferTargets::from_images(color_images, depth_image),
+            PassAttachments::Desc(desc.clone()),
+            desc.attachments()
+                .map(|attachment| attachment.clear_value())
+                .collect(),
+        )
+    }
+
+    // Images have to match the attachments they are used for, and the depth format has to be
+    // supported by the device
+    fn check_images(
+        device: &Device,
+        desc: &RenderPassDesc,
+        color_images: &[&Image],
+        depth_image: Option<&Image>,
+    ) -> Result<()> {
+        if desc.color_attachments.len() != color_images.len() {
+            return Err(RenderPassError::ColorAttachmentCountMismatch {
+                expected: desc.color_attachments.len(),
+                found: color_images.len(),
+            }
+            .into());
+        }
+        if desc.depth_attachment.is_some() != depth_image.is_some() {
+            return Err(RenderPassError::DepthAttachmentMismatch.into());
+        }
+        if let Some(attachment) = &desc.depth_attachment {
+            if !device.supports_depth_format(attachment.format) {
+                return Err(RenderPassError::UnsupportedDepthFormat(attachment.format).into());
+            }
+        }
+        let images = color_images
+            .iter()
+            .copied()
+            .chain(depth_image)
+            .collect::<Vec<_>>();
+        for (index, (attachment, image)) in desc.attachments().zip(&images).enumerate() {
+            if attachment.format != image.format {
+                return Err(RenderPassError::FormatMismatch {
+                    attachment: index,
+                    expected: attachment.format,
+                    found: image.format,
+                }
+                .into());
+            }
+            if attachment.samples != image.samples {
+                return Err(RenderPassError::SampleCountMismatch {
+                    attachment: index,
+                    expected: attachment.samples,
+                    found: image.samples,
+                }
+                .into());
+            }
+        }
+        Self::check_usage(
+            &images,
+            &desc
+                .attachments()
+                .map(|attachment| attachment.final_layout.into())
+                .collect::<Vec<_>>(),
+        )
+    }
+
+    pub fn color_attachment_count(&self, subpass: u32) -> usize {
+        self.attachments.color_count(subpass)
+    }
+
+    // Pipelines used in the subpass need the same sample count, `None` if it has no color or depth
+    // attachments
+    pub fn samples(&self, subpass: u32) -> Option<vk::SampleCountFlags> {
+        self.attachments.samples(subpass)
+    }
+
+    pub fn subpass_count(&self) -> usize {
+        self.attachments.subpasses().len()
+    }
+
+    fn recreate(&mut self, device: &Device, targets: &FramebufferTargets) -> Result<()> {
+        unsafe {
+            self.destroy_structures();
+        }
+        let (render_pass, framebuffers, render_area) =
+            Self::create_render_pass_structures(device, targets, &self.attachments)?;
         self.render_pass = render_pass;
         self.framebuffers = framebuffers;
+        self.tracked_attachments = Self::track_attachments(targets, &self.attachments);
         self.render_area = render_area;
+        self.swapchain_generation = targets.swapchain_generation;
         Ok(())
     }
 
+    pub fn resize(&mut self, device: &Device, swapchain: &Swapchain) -> Result<()> {
+        self.recreate(device, &FramebufferTargets::from_swapchain(swapchain))
+    }
+
+    // Rebuilds the framebuffers only if `swapchain` was recreated since they were created, so
+    // it is cheap to call every frame. Does nothing for offscreen passes.
+    pub fn sync_with_swapchain(&mut self, device: &Device, swapchain: &Swapchain) -> Result<()> {
+        match self.swapchain_generation {
+            Some(generation) if generation != swapchain.generation() => {
+                self.resize(device, swapchain)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    // Same as `begin`, after picking up a recreated swapchain
+    pub fn begin_with_swapchain(
+        &mut self,
+        device: &Device,
+        context: &Context,
+        swapchain: &Swapchain,
+        present_index: u32,
+    ) -> Result<()> {
+        self.sync_with_swapchain(device, swapchain)?;
+        self.begin(device, context, present_index);
+        Ok(())
+    }
+
+    pub fn resize_offscreen(
+        &mut self,
+        device: &Device,
+        color_image: &Image,
+        depth_image: &Image,
+    ) -> Result<()> {
+        Self::check_usage(
+            &[color_image, depth_image],
+            &[vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL; 2],
+        )?;
+        self.recreate(
+            device,
+            &FramebufferTargets::from_images(&[color_image], Some(depth_image)),
+        )
+    }
+
+    // Same as `resize_offscreen`, for passes created with `from_desc`
+    pub fn resize_attachments(
+        &mut self,
+        device: &Device,
+        color_images: &[&Image],
+        depth_image: Option<&Image>,
+    ) -> Result<()> {
+        if let PassAttachments::Desc(desc) = &self.attachments {
+            Self::check_images(device, desc, color_images, depth_image)?;
+        }
+        self.recreate(
+            device,
+            &FramebufferTargets::from_images(color_images, depth_image),
+        )
+    }
+
+    pub fn viewport(&self) -> vk::Viewport {
+        flipped_viewport(self.render_area.extent)
+    }
+
+    pub fn scissor(&self) -> vk::Rect2D {
+        self.render_area
+    }
+
+    // Offscreen attachments are moved from their tracked layout to the layout the render pass
+    // expects them in first, which also covers images that were never rendered to
     pub fn begin(&self, device: &Device, context: &Context, present_index: u32) {
+        let mut transitions = ResourceTransitions::default();
+        for attachment in &self.tracked_attachments {
+            if let Some(usage) = layout_usage(attachment.initial_layout) {
+                transitions = transitions.with_tracked_image(&attachment.image, usage);
+            }
+        }
+        transitions.record(device, context);
+
         let render_pass_begin_info = vk::RenderPassBeginInfo::builder()
             .render_pass(self.render_pass)
             .framebuffer(self.framebuffers[present_index as usize])
@@ -208,18 +1209,215 @@ impl RenderPass {
         };
     }
 
+    // Offscreen attachments are tracked as written by the render pass and left in their final
+    // layout, so later `ResourceTransitions` start from there
     pub fn end(&self, device: &Device, context: &Context) {
         unsafe { device.device.cmd_end_render_pass(context.command_buffer) };
+
+        for attachment in &self.tracked_attachments {
+            let usage = if attachment
+                .image
+                .aspect_mask
+                .contains(vk::ImageAspectFlags::COLOR)
+            {
+                ResourceUsage::ColorAttachment
+            } else {
+                ResourceUsage::DepthStencilAttachment
+            };
+            let mut state = attachment.image.state.lock().unwrap();
+            for mip_level in 0..state.mip_levels() {
+                for array_layer in 0..state.array_layers() {
+                    state
+                        .subresource(mip_level, array_layer)
+                        .assume_in_layout(usage, attachment.final_layout);
+                }
+            }
+        }
     }
 
-    unsafe fn clean_framebuffers(&self, device: &Device) {
+    unsafe fn destroy_structures(&self) {
         for framebuffer in &self.framebuffers {
-            device.device.destroy_framebuffer(*framebuffer, None);
+            self.device.device.destroy_framebuffer(*framebuffer, None);
         }
+        self.device
+            .device
+            .destroy_render_pass(self.render_pass, None);
+    }
+}
+
+impl Drop for RenderPass {
+    fn drop(&mut self) {
+        unsafe { self.destroy_structures() };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gbuffer_desc() -> RenderPassDesc {
+        RenderPassDesc::default()
+            .with_color_attachment(AttachmentDesc::new(vk::Format::R8G8B8A8_UNORM))
+            .with_color_attachment(AttachmentDesc::new(vk::Format::R16G16B16A16_SFLOAT))
+            .with_depth_attachment(AttachmentDesc::depth(DepthFormat::D32))
+    }
+
+    fn desc_depth_index() -> u32 {
+        gbuffer_desc().depth_attachment_index()
+    }
+
+    #[test]
+    fn input_attachments_wait_on_earlier_writes() {
+        let desc = gbuffer_desc()
+            .with_subpass(
+                SubpassDesc::default()
+                    .with_color_attachment(0)
+                    .with_color_attachment(1)
+                    .with_depth_attachment(),
+            )
+            .with_subpass(
+                SubpassDesc::default()
+                    .with_input_attachment(0)
+                    .with_color_attachment(1),
+            )
+            .with_subpass(
+                SubpassDesc::default()
+                    .with_input_attachment(1)
+                    .with_input_attachment(desc_depth_index()),
+            );
+        let dependencies = desc
+            .input_dependencies()
+            .iter()
+            .map(|dependency| (dependency.raw.src_subpass, dependency.raw.dst_subpass))
+            .collect::<Vec<_>>();
+        assert_eq!(dependencies, [(0, 1), (0, 2), (1, 2)]);
+        assert!(desc.check_subpasses().is_ok());
+    }
+
+    #[test]
+    fn explicit_dependencies_come_after_input_dependencies() {
+        let desc = gbuffer_desc()
+            .with_subpass(SubpassDesc::default().with_color_attachment(0))
+            .with_subpass(
+                SubpassDesc::default()
+                    .with_input_attachment(0)
+                    .with_color_attachment(1),
+            )
+            .with_dependency(SubpassDependency::new(vk::SUBPASS_EXTERNAL, 1));
+        let dependencies = PassAttachments::Desc(desc)
+            .dependencies()
+            .iter()
+            .map(|dependency| (dependency.src_subpass, dependency.dst_subpass))
+            .collect::<Vec<_>>();
+        assert_eq!(dependencies, [(0, 1), (vk::SUBPASS_EXTERNAL, 1)]);
+    }
+
+    #[test]
+    fn read_only_depth_is_not_a_write() {
+        let desc = gbuffer_desc()
+            .with_subpass(SubpassDesc::default().with_read_only_depth_attachment())
+            .with_subpass(SubpassDesc::default().with_input_attachment(desc_depth_index()));
+        assert!(desc.input_dependencies().is_empty());
+    }
+
+    #[test]
+    fn color_attachment_cannot_be_input() {
+        let desc = gbuffer_desc().with_subpass(
+            SubpassDesc::default()
+                .with_color_attachment(0)
+                .with_input_attachment(0),
+        );
+        assert!(matches!(
+            desc.check_subpasses(),
+            Err(crate::Error::RenderPass(
+                RenderPassError::ColorAttachmentAlsoInput {
+                    subpass: 0,
+                    attachment: 0
+                }
+            ))
+        ));
+    }
+
+    #[test]
+    fn subpass_attachments_must_exist() {
+        let desc = RenderPassDesc::default()
+            .with_color_attachment(AttachmentDesc::new(vk::Format::R8G8B8A8_UNORM))
+            .with_subpass(SubpassDesc::default().with_depth_attachment());
+        assert!(matches!(
+            desc.check_subpasses(),
+            Err(crate::Error::RenderPass(
+                RenderPassError::InvalidSubpassAttachment {
+                    subpass: 0,
+                    attachment: 1
+                }
+            ))
+        ));
+    }
+
+    fn multisampled_desc() -> RenderPassDesc {
+        let samples = vk::SampleCountFlags::TYPE_4;
+        RenderPassDesc::default()
+            .with_color_attachment(
+                AttachmentDesc::new(vk::Format::R8G8B8A8_UNORM).with_samples(samples),
+            )
+            .with_color_attachment(AttachmentDesc::new(vk::Format::R8G8B8A8_UNORM))
+            .with_depth_attachment(AttachmentDesc::depth(DepthFormat::D32).with_samples(samples))
+    }
+
+    #[test]
+    fn multisampled_attachment_is_resolved() {
+        let desc = multisampled_desc()
+            .with_subpass(
+                SubpassDesc::default()
+                    .with_resolved_color_attachment(0, 1)
+                    .with_depth_attachment(),
+            )
+            .with_subpass(SubpassDesc::default().with_input_attachment(1));
+        assert!(desc.check_subpasses().is_ok());
+        let references =
+            SubpassReferences::new(&desc.subpasses()[0], desc.depth_attachment_index());
+        assert_eq!(references.resolve.len(), 1);
+        assert_eq!(references.resolve[0].attachment, 1);
+        // The resolve is a write the input attachment waits on
+        let dependencies = desc
+            .input_dependencies()
+            .iter()
+            .map(|dependency| (dependency.raw.src_subpass, dependency.raw.dst_subpass))
+            .collect::<Vec<_>>();
+        assert_eq!(dependencies, [(0, 1)]);
+        assert_eq!(
+            PassAttachments::Desc(desc).samples(0),
+            Some(vk::SampleCountFlags::TYPE_4)
+        );
+    }
+
+    #[test]
+    fn resolve_needs_single_sampled_target() {
+        let desc = multisampled_desc()
+            .with_subpass(SubpassDesc::default().with_resolved_color_attachment(1, 0));
+        assert!(matches!(
+            desc.check_subpasses(),
+            Err(crate::Error::RenderPass(
+                RenderPassError::InvalidResolveAttachment {
+                    subpass: 0,
+                    attachment: 0
+                }
+            ))
+        ));
     }
 
-    pub unsafe fn clean(&self, device: &Device) {
-        self.clean_framebuffers(device);
-        device.device.destroy_render_pass(self.render_pass, None);
+    #[test]
+    fn subpass_attachments_share_sample_count() {
+        let desc = multisampled_desc().with_subpass(
+            SubpassDesc::default()
+                .with_color_attachment(1)
+                .with_depth_attachment(),
+        );
+        assert!(matches!(
+            desc.check_subpasses(),
+            Err(crate::Error::RenderPass(
+                RenderPassError::SubpassSampleCountMismatch { subpass: 0 }
+            ))
+        ));
     }
 }
@@ -1,39 +1,121 @@
 use crate::{
     context::Context,
-    device::Device,
-    mem,
-    pipeline::PipelineStages,
-    resources::buffer::{Buffer, BufferType},
-    sync::{AccessMask, Fence, ImageMemoryBarrier},
+    descriptors::ShaderStage,
+    device::{take_fields, Device, Retire, RetiredResource},
+    error::Result,
+    mem::{self, Allocation, AllocationKind},
+    resources::{
+        buffer::{Buffer, BufferType},
+        readback,
+    },
+    sync::{Fence, ImageState, ResourceTransitions, ResourceUsage},
 };
-use anyhow::Result;
 use ash::vk;
 use bytes::Bytes;
-use image::GenericImageView;
-use std::{fs::File, io::BufReader, path::Path};
+use image::{GenericImageView, RgbaImage};
+use std::{
+    fs::File,
+    io::BufReader,
+    path::Path,
+    sync::{Arc, Mutex},
+};
 use thiserror::Error;
 
 #[derive(Debug, Error)]
 pub enum ImageCreationError {
     #[error("could not find memory index for image")]
     CouldNotFindMemoryIndex,
+    #[error("{0:?} samples are not supported for this image type")]
+    UnsupportedSampleCount(vk::SampleCountFlags),
 }
 
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum ImageLayout {
     Undefined,
+    General,
+    Preinitialized,
     DepthStencil,
+    DepthStencilReadOnly,
+    DepthReadOnlyStencilAttachment,
+    DepthAttachmentStencilReadOnly,
+    DepthAttachment,
+    DepthReadOnly,
+    StencilAttachment,
+    StencilReadOnly,
     TransferDest,
+    TransferSrc,
+    ColorAttachment,
+    ShaderReadOnly,
+    // Generic attachment and read-only layouts from Vulkan 1.3 / synchronization2
+    Attachment,
+    ReadOnly,
+    PresentSrc,
+    SharedPresent,
 }
 
 impl From<ImageLayout> for vk::ImageLayout {
     fn from(layout: ImageLayout) -> Self {
         match layout {
             ImageLayout::Undefined => vk::ImageLayout::UNDEFINED,
+            ImageLayout::General => vk::ImageLayout::GENERAL,
+            ImageLayout::Preinitialized => vk::ImageLayout::PREINITIALIZED,
             ImageLayout::DepthStencil => vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+            ImageLayout::DepthStencilReadOnly => vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL,
+            ImageLayout::DepthReadOnlyStencilAttachment => {
+                vk::ImageLayout::DEPTH_READ_ONLY_STENCIL_ATTACHMENT_OPTIMAL
+            }
+            ImageLayout::DepthAttachmentStencilReadOnly => {
+                vk::ImageLayout::DEPTH_ATTACHMENT_STENCIL_READ_ONLY_OPTIMAL
+            }
+            ImageLayout::DepthAttachment => vk::ImageLayout::DEPTH_ATTACHMENT_OPTIMAL,
+            ImageLayout::DepthReadOnly => vk::ImageLayout::DEPTH_READ_ONLY_OPTIMAL,
+            ImageLayout::StencilAttachment => vk::ImageLayout::STENCIL_ATTACHMENT_OPTIMAL,
+            ImageLayout::StencilReadOnly => vk::ImageLayout::STENCIL_READ_ONLY_OPTIMAL,
             ImageLayout::TransferDest => vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            ImageLayout::TransferSrc => vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            ImageLayout::ColorAttachment => vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+            ImageLayout::ShaderReadOnly => vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            ImageLayout::Attachment => vk::ImageLayout::ATTACHMENT_OPTIMAL,
+            ImageLayout::ReadOnly => vk::ImageLayout::READ_ONLY_OPTIMAL,
+            ImageLayout::PresentSrc => vk::ImageLayout::PRESENT_SRC_KHR,
+            ImageLayout::SharedPresent => vk::ImageLayout::SHARED_PRESENT_KHR,
         }
     }
 }
+
+// Fails with layouts that have no `ImageLayout`
+impl TryFrom<vk::ImageLayout> for ImageLayout {
+    type Error = vk::ImageLayout;
+
+    fn try_from(layout: vk::ImageLayout) -> Result<Self, Self::Error> {
+        Ok(match layout {
+            vk::ImageLayout::UNDEFINED => ImageLayout::Undefined,
+            vk::ImageLayout::GENERAL => ImageLayout::General,
+            vk::ImageLayout::PREINITIALIZED => ImageLayout::Preinitialized,
+            vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL => ImageLayout::DepthStencil,
+            vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL => ImageLayout::DepthStencilReadOnly,
+            vk::ImageLayout::DEPTH_READ_ONLY_STENCIL_ATTACHMENT_OPTIMAL => {
+                ImageLayout::DepthReadOnlyStencilAttachment
+            }
+            vk::ImageLayout::DEPTH_ATTACHMENT_STENCIL_READ_ONLY_OPTIMAL => {
+                ImageLayout::DepthAttachmentStencilReadOnly
+            }
+            vk::ImageLayout::DEPTH_ATTACHMENT_OPTIMAL => ImageLayout::DepthAttachment,
+            vk::ImageLayout::DEPTH_READ_ONLY_OPTIMAL => ImageLayout::DepthReadOnly,
+            vk::ImageLayout::STENCIL_ATTACHMENT_OPTIMAL => ImageLayout::StencilAttachment,
+            vk::ImageLayout::STENCIL_READ_ONLY_OPTIMAL => ImageLayout::StencilReadOnly,
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL => ImageLayout::TransferDest,
+            vk::ImageLayout::TRANSFER_SRC_OPTIMAL => ImageLayout::TransferSrc,
+            vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL => ImageLayout::ColorAttachment,
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL => ImageLayout::ShaderReadOnly,
+            vk::ImageLayout::ATTACHMENT_OPTIMAL => ImageLayout::Attachment,
+            vk::ImageLayout::READ_ONLY_OPTIMAL => ImageLayout::ReadOnly,
+            vk::ImageLayout::PRESENT_SRC_KHR => ImageLayout::PresentSrc,
+            vk::ImageLayout::SHARED_PRESENT_KHR => ImageLayout::SharedPresent,
+            layout => return Err(layout),
+        })
+    }
+}
 #[derive(Debug, Copy, Clone)]
 pub struct ImageResolution {
     pub width: u32,
@@ -71,17 +153,48 @@ impl From<vk::Extent2D> for ImageResolution {
     }
 }
 
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DepthFormat {
+    D16,
+    D32,
+    D24S8,
+    D32S8,
+}
+
+impl From<DepthFormat> for vk::Format {
+    fn from(format: DepthFormat) -> Self {
+        match format {
+            DepthFormat::D16 => vk::Format::D16_UNORM,
+            DepthFormat::D32 => vk::Format::D32_SFLOAT,
+            DepthFormat::D24S8 => vk::Format::D24_UNORM_S8_UINT,
+            DepthFormat::D32S8 => vk::Format::D32_SFLOAT_S8_UINT,
+        }
+    }
+}
+
+pub(crate) fn has_stencil(format: vk::Format) -> bool {
+    matches!(
+        format,
+        vk::Format::S8_UINT
+            | vk::Format::D16_UNORM_S8_UINT
+            | vk::Format::D24_UNORM_S8_UINT
+            | vk::Format::D32_SFLOAT_S8_UINT
+    )
+}
+
 #[derive(Debug, Copy, Clone)]
 pub enum ImageType {
     Color,
     Depth,
+    ColorTarget,
+    DepthTarget,
 }
 
 impl ImageType {
     pub fn format(&self) -> vk::Format {
         match self {
-            ImageType::Color => vk::Format::R8G8B8A8_UNORM,
-            ImageType::Depth => vk::Format::D16_UNORM,
+            ImageType::Color | ImageType::ColorTarget => vk::Format::R8G8B8A8_UNORM,
+            ImageType::Depth | ImageType::DepthTarget => vk::Format::D16_UNORM,
         }
     }
 
@@ -90,77 +203,265 @@ impl ImageType {
             // TODO: Should not be like this for all color textures, probably
             ImageType::Color => vk::ImageUsageFlags::TRANSFER_DST | vk::ImageUsageFlags::SAMPLED,
             ImageType::Depth => vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT,
+            ImageType::ColorTarget => {
+                vk::ImageUsageFlags::COLOR_ATTACHMENT
+                    | vk::ImageUsageFlags::SAMPLED
+                    | vk::ImageUsageFlags::INPUT_ATTACHMENT
+                    | vk::ImageUsageFlags::TRANSFER_SRC
+            }
+            ImageType::DepthTarget => {
+                vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT
+                    | vk::ImageUsageFlags::SAMPLED
+                    | vk::ImageUsageFlags::INPUT_ATTACHMENT
+            }
         }
     }
 
     pub fn aspect_mask(&self) -> vk::ImageAspectFlags {
         match self {
-            ImageType::Color => vk::ImageAspectFlags::COLOR,
-            ImageType::Depth => vk::ImageAspectFlags::DEPTH,
+            ImageType::Color | ImageType::ColorTarget => vk::ImageAspectFlags::COLOR,
+            ImageType::Depth | ImageType::DepthTarget => vk::ImageAspectFlags::DEPTH,
+        }
+    }
+
+    // Depth images with a stencil format include the stencil aspect
+    pub fn aspect_mask_for(&self, format: vk::Format) -> vk::ImageAspectFlags {
+        let aspect_mask = self.aspect_mask();
+        if aspect_mask == vk::ImageAspectFlags::DEPTH && has_stencil(format) {
+            aspect_mask | vk::ImageAspectFlags::STENCIL
+        } else {
+            aspect_mask
+        }
+    }
+
+    // Sample counts every usage of the type supports on `device`
+    pub fn sample_counts(&self, device: &Device) -> vk::SampleCountFlags {
+        let limits = &device.properties.limits;
+        let usage = self.usage();
+        let depth = self.aspect_mask() == vk::ImageAspectFlags::DEPTH;
+        let mut counts = vk::SampleCountFlags::from_raw(vk::Flags::MAX);
+        if usage.contains(vk::ImageUsageFlags::COLOR_ATTACHMENT) {
+            counts &= limits.framebuffer_color_sample_counts;
+        }
+        if usage.contains(vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT) {
+            counts &= limits.framebuffer_depth_sample_counts;
         }
+        if usage.contains(vk::ImageUsageFlags::SAMPLED) {
+            counts &= if depth {
+                limits.sampled_image_depth_sample_counts
+            } else {
+                limits.sampled_image_color_sample_counts
+            };
+        }
+        counts
     }
 }
 
 #[derive(Debug)]
 pub struct Image {
     pub image: vk::Image,
-    pub memory: vk::DeviceMemory,
+    pub allocation: Allocation,
     pub view: vk::ImageView,
+    // Depth-only view of depth/stencil images
+    depth_view: Option<vk::ImageView>,
     pub ty: ImageType,
+    pub format: vk::Format,
+    pub resolution: ImageResolution,
+    pub samples: vk::SampleCountFlags,
+    // Shared with render passes that use the image as an attachment
+    pub(crate) state: Arc<Mutex<ImageState>>,
+    owns_memory: bool,
+    device: Device,
 }
 
 impl Image {
     pub fn new(device: &Device, resolution: ImageResolution, ty: ImageType) -> Result<Self> {
-        let image_create_info = vk::ImageCreateInfo::builder()
-            .image_type(vk::ImageType::TYPE_2D)
-            .format(ty.format())
-            .extent(resolution.into())
-            .mip_levels(1)
-            .array_layers(1)
-            .samples(vk::SampleCountFlags::TYPE_1)
-            .tiling(vk::ImageTiling::OPTIMAL)
-            .usage(ty.usage())
-            .sharing_mode(vk::SharingMode::EXCLUSIVE);
+        Self::with_format(device, resolution, ty, ty.format())
+    }
 
-        let image = unsafe { device.device.create_image(&image_create_info, None)? };
+    pub fn with_format(
+        device: &Device,
+        resolution: ImageResolution,
+        ty: ImageType,
+        format: vk::Format,
+    ) -> Result<Self> {
+        Self::multisampled(device, resolution, ty, format, vk::SampleCountFlags::TYPE_1)
+    }
+
+    // Multisampled images are rendered into and resolved, they can't be copied from or uploaded to
+    pub fn multisampled(
+        device: &Device,
+        resolution: ImageResolution,
+        ty: ImageType,
+        format: vk::Format,
+        samples: vk::SampleCountFlags,
+    ) -> Result<Self> {
+        let image = Self::create_raw(device, resolution, ty, format, samples)?;
         let image_memory_req = unsafe { device.device.get_image_memory_requirements(image) };
-        let image_memory_index = mem::find_memory_type_index(
+        let allocation = mem::find_memory_type_index(
             &image_memory_req,
             &device.memory_properties,
             vk::MemoryPropertyFlags::DEVICE_LOCAL,
         )
-        .ok_or(ImageCreationError::CouldNotFindMemoryIndex)?;
+        .ok_or_else(|| ImageCreationError::CouldNotFindMemoryIndex.into())
+        .and_then(|image_memory_index| {
+            device.allocate(
+                &image_memory_req,
+                image_memory_index,
+                AllocationKind::Optimal,
+            )
+        });
+        let allocation = match allocation {
+            Ok(allocation) => allocation,
+            Err(error) => {
+                unsafe { device.device.destroy_image(image, None) };
+                return Err(error);
+            }
+        };
 
-        let image_allocate_info = vk::MemoryAllocateInfo::builder()
-            .allocation_size(image_memory_req.size)
-            .memory_type_index(image_memory_index);
+        Self::bind(
+            device, image, allocation, true, resolution, ty, format, samples,
+        )
+    }
 
-        let memory = unsafe { device.device.allocate_memory(&image_allocate_info, None)? };
+    pub(crate) fn create_raw(
+        device: &Device,
+        resolution: ImageResolution,
+        ty: ImageType,
+        format: vk::Format,
+        samples: vk::SampleCountFlags,
+    ) -> Result<vk::Image> {
+        if samples != vk::SampleCountFlags::TYPE_1 && !ty.sample_counts(device).contains(samples) {
+            return Err(ImageCreationError::UnsupportedSampleCount(samples).into());
+        }
+        let image_create_info = vk::ImageCreateInfo::builder()
+            .image_type(vk::ImageType::TYPE_2D)
+            .format(format)
+            .extent(resolution.into())
+            .mip_levels(1)
+            .array_layers(1)
+            .samples(samples)
+            .tiling(vk::ImageTiling::OPTIMAL)
+            .usage(ty.usage())
+            .sharing_mode(vk::SharingMode::EXCLUSIVE);
 
-        unsafe { device.device.bind_image_memory(image, memory, 0)? };
+        Ok(unsafe { device.device.create_image(&image_create_info, None)? })
+    }
 
-        let image_view_info = vk::ImageViewCreateInfo::builder()
-            .subresource_range(
-                vk::ImageSubresourceRange::builder()
-                    .aspect_mask(ty.aspect_mask())
-                    .level_count(1)
-                    .layer_count(1)
-                    .build(),
-            )
-            .image(image)
-            .format(image_create_info.format)
-            .view_type(vk::ImageViewType::TYPE_2D);
+    // Binds `image`, created with `create_raw`, to memory and creates its views. Images that don't
+    // own `allocation` alias memory owned by someone else, and leave freeing it to them. On
+    // failure, `image` is destroyed and `allocation` freed if it is owned.
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn bind(
+        device: &Device,
+        image: vk::Image,
+        allocation: Allocation,
+        owns_memory: bool,
+        resolution: ImageResolution,
+        ty: ImageType,
+        format: vk::Format,
+        samples: vk::SampleCountFlags,
+    ) -> Result<Self> {
+        let create_view = |aspect_mask| {
+            let image_view_info = vk::ImageViewCreateInfo::builder()
+                .subresource_range(
+                    vk::ImageSubresourceRange::builder()
+                        .aspect_mask(aspect_mask)
+                        .level_count(1)
+                        .layer_count(1)
+                        .build(),
+                )
+                .image(image)
+                .format(format)
+                .view_type(vk::ImageViewType::TYPE_2D);
+            unsafe { device.device.create_image_view(&image_view_info, None) }
+        };
 
-        let view = unsafe { device.device.create_image_view(&image_view_info, None)? };
+        let aspect_mask = ty.aspect_mask_for(format);
+        let view = unsafe {
+            device
+                .device
+                .bind_image_memory(image, allocation.memory, allocation.offset)
+        }
+        .and_then(|()| create_view(aspect_mask));
+        // Descriptors can only view a single aspect
+        let depth_view = view.and_then(|view| {
+            if aspect_mask.contains(vk::ImageAspectFlags::STENCIL) {
+                match create_view(vk::ImageAspectFlags::DEPTH) {
+                    Ok(depth_view) => Ok((view, Some(depth_view))),
+                    Err(error) => {
+                        unsafe { device.device.destroy_image_view(view, None) };
+                        Err(error)
+                    }
+                }
+            } else {
+                Ok((view, None))
+            }
+        });
+        let (view, depth_view) = match depth_view {
+            Ok(views) => views,
+            Err(error) => {
+                unsafe {
+                    device.device.destroy_image(image, None);
+                    if owns_memory {
+                        device.free(&allocation);
+                    }
+                }
+                return Err(error.into());
+            }
+        };
 
         Ok(Self {
             image,
-            memory,
+            allocation,
             view,
+            depth_view,
             ty,
+            format,
+            resolution,
+            samples,
+            state: Arc::new(Mutex::new(ImageState::new(1, 1))),
+            owns_memory,
+            device: device.clone(),
         })
     }
 
+    pub fn aspect_mask(&self) -> vk::ImageAspectFlags {
+        self.ty.aspect_mask_for(self.format)
+    }
+
+    // View for descriptors, which only sees depth for depth/stencil formats
+    pub fn descriptor_view(&self) -> vk::ImageView {
+        self.depth_view.unwrap_or(self.view)
+    }
+
+    // Number of mip levels and array layers with tracked state
+    pub fn subresource_counts(&self) -> (u32, u32) {
+        let state = self.state.lock().unwrap();
+        (state.mip_levels(), state.array_layers())
+    }
+
+    // Last layout recorded through `ResourceTransitions`, or set with `assume_usage`
+    pub fn tracked_layout(&self, mip_level: u32, array_layer: u32) -> ImageLayout {
+        self.state
+            .lock()
+            .unwrap()
+            .subresource(mip_level, array_layer)
+            .layout()
+    }
+
+    // For layout changes that happen outside of `ResourceTransitions`, like render pass final
+    // layouts. Every subresource is marked as last used by `usage`.
+    pub fn assume_usage(&self, usage: ResourceUsage) {
+        let mut state = self.state.lock().unwrap();
+        let (mip_levels, array_layers) = (state.mip_levels(), state.array_layers());
+        for mip_level in 0..mip_levels {
+            for array_layer in 0..array_layers {
+                state.subresource(mip_level, array_layer).assume(usage);
+            }
+        }
+    }
+
     fn image_create_commands(
         device: &Device,
         context: &Context,
@@ -169,18 +470,9 @@ impl Image {
         image_buffer: &Buffer,
         image_extent: vk::Extent2D,
     ) {
-        let layout_transition_barrier = ImageMemoryBarrier::new(
-            &image,
-            AccessMask::Transfer,
-            ImageLayout::Undefined,
-            ImageLayout::TransferDest,
-        );
-        device.pipeline_image_barrier(
-            context,
-            PipelineStages::BottomOfPipe,
-            PipelineStages::Transfer,
-            &[layout_transition_barrier],
-        );
+        ResourceTransitions::default()
+            .with_image(image, ResourceUsage::TransferDst)
+            .record(device, context);
         // TODO: Abstract better later
         let buffer_copy_regions = vk::BufferImageCopy::builder()
             .image_subresource(
@@ -201,31 +493,9 @@ impl Image {
                 &[buffer_copy_regions],
             );
         }
-        let texture_barrier_end = vk::ImageMemoryBarrier {
-            src_access_mask: vk::AccessFlags::TRANSFER_WRITE,
-            dst_access_mask: vk::AccessFlags::SHADER_READ,
-            old_layout: vk::ImageLayout::TRANSFER_DST_OPTIMAL,
-            new_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
-            image: image.image,
-            subresource_range: vk::ImageSubresourceRange {
-                aspect_mask: vk::ImageAspectFlags::COLOR,
-                level_count: 1,
-                layer_count: 1,
-                ..Default::default()
-            },
-            ..Default::default()
-        };
-        unsafe {
-            device.device.cmd_pipeline_barrier(
-                context.command_buffer,
-                vk::PipelineStageFlags::TRANSFER,
-                vk::PipelineStageFlags::FRAGMENT_SHADER,
-                vk::DependencyFlags::empty(),
-                &[],
-                &[],
-                &[texture_barrier_end],
-            );
-        }
+        ResourceTransitions::default()
+            .with_image(image, ResourceUsage::Sampled(ShaderStage::Fragment))
+            .record(device, context);
     }
 
     pub fn from_data_and_dims(
@@ -291,9 +561,65 @@ impl Image {
         Image::from_data_and_dims(device, context, fence, width, height, &image_data, record)
     }
 
-    pub unsafe fn clean(&self, device: &Device) {
-        device.device.destroy_image(self.image, None);
-        device.device.destroy_image_view(self.view, None);
-        device.device.free_memory(self.memory, None);
+    // Waits for the copy, after which the image is tracked as a transfer source
+    pub fn read_rgba(
+        &self,
+        device: &Device,
+        context: &Context,
+        fence: &Fence,
+    ) -> Result<RgbaImage> {
+        readback::read_image(device, context, fence, self)
+    }
+
+    pub fn save_to_file(
+        &self,
+        device: &Device,
+        context: &Context,
+        fence: &Fence,
+        path: impl AsRef<Path>,
+        format: image::ImageFormat,
+    ) -> Result<()> {
+        let pixels = self.read_rgba(device, context, fence)?;
+        pixels.save_with_format(path, format)?;
+        Ok(())
+    }
+}
+
+impl Retire for Image {
+    fn into_retired(self) -> RetiredResource {
+        take_fields!(let Image {
+            image,
+            allocation,
+            view,
+            depth_view,
+            ty,
+            format,
+            resolution,
+            samples,
+            state,
+            owns_memory,
+            device,
+        } = self);
+        RetiredResource::Image {
+            image,
+            view,
+            depth_view,
+            allocation: owns_memory.then_some(allocation),
+        }
+    }
+}
+
+impl Drop for Image {
+    fn drop(&mut self) {
+        unsafe {
+            self.device.device.destroy_image_view(self.view, None);
+            if let Some(depth_view) = self.depth_view {
+                self.device.device.destroy_image_view(depth_view, None);
+            }
+            self.device.device.destroy_image(self.image, None);
+            if self.owns_memory {
+                self.device.free(&self.allocation);
+            }
+        }
     }
 }
@@ -1,29 +1,40 @@
 use crate::{
-    device::Device,
-    mem::{self, MemoryMappablePointer},
+    context::Context,
+    descriptors::ShaderStage,
+    device::{take_fields, Device, Retire, RetiredResource},
+    error::Result,
+    mem::{Allocation, AllocationKind, MemoryLocation},
+    sync::{Fence, ResourceState, ResourceTransitions, ResourceUsage},
 };
-use anyhow::Result;
 use ash::vk;
+use std::sync::Mutex;
 use thiserror::Error;
 
 #[derive(Debug, Error)]
 pub enum BufferCreationError {
     #[error("could not find memory index for buffer")]
     CouldNotFindMemoryIndex,
+    #[error("buffers can't be empty")]
+    ZeroSize,
 }
 
 #[derive(Debug, Error)]
 pub enum MemoryCopyError {
     #[error("buffer does not allow CPU -> GPU transfer")]
     CannotTransferFromCPU,
+    #[error("buffer does not allow GPU -> CPU transfer")]
+    CannotTransferToCPU,
 }
 
+#[derive(Debug, Copy, Clone)]
 pub enum BufferType {
     Index,
     Vertex,
     Storage,
     Uniform,
     Staging,
+    Readback,
+    Indirect,
 }
 
 impl BufferType {
@@ -34,6 +45,36 @@ impl BufferType {
             BufferType::Storage => vk::BufferUsageFlags::STORAGE_BUFFER,
             BufferType::Staging => vk::BufferUsageFlags::TRANSFER_SRC,
             BufferType::Uniform => vk::BufferUsageFlags::UNIFORM_BUFFER,
+            BufferType::Readback => vk::BufferUsageFlags::TRANSFER_DST,
+            BufferType::Indirect => vk::BufferUsageFlags::INDIRECT_BUFFER,
+        }
+    }
+
+    fn default_location(&self) -> MemoryLocation {
+        match self {
+            BufferType::Readback => MemoryLocation::GpuToCpu,
+            BufferType::Staging => MemoryLocation::CpuOnly,
+            _ => MemoryLocation::CpuToGpu,
+        }
+    }
+
+    // Where the buffer is first used after an upload. Shader resources can be read from both
+    // graphics and compute.
+    fn consumers(&self) -> &'static [ResourceUsage] {
+        match self {
+            BufferType::Index => &[ResourceUsage::IndexBuffer],
+            BufferType::Vertex => &[ResourceUsage::VertexBuffer],
+            BufferType::Storage => &[
+                ResourceUsage::StorageRead(ShaderStage::AllGraphics),
+                ResourceUsage::StorageRead(ShaderStage::Compute),
+            ],
+            BufferType::Uniform => &[
+                ResourceUsage::UniformBuffer(ShaderStage::AllGraphics),
+                ResourceUsage::UniformBuffer(ShaderStage::Compute),
+            ],
+            BufferType::Staging => &[ResourceUsage::TransferSrc],
+            BufferType::Readback => &[ResourceUsage::HostRead],
+            BufferType::Indirect => &[ResourceUsage::IndirectBuffer],
         }
     }
 }
@@ -41,55 +82,71 @@ impl BufferType {
 pub struct Buffer {
     pub buffer: vk::Buffer,
     pub size: u64,
-    memory: vk::DeviceMemory,
-    ptr: Option<MemoryMappablePointer>,
+    pub ty: BufferType,
+    pub location: MemoryLocation,
+    allocation: Allocation,
+    pub(crate) state: Mutex<ResourceState>,
+    device: Device,
 }
 
 impl Buffer {
     pub fn with_size(device: &Device, size: u64, ty: BufferType) -> Result<Self> {
+        Self::with_size_and_location(device, size, ty, ty.default_location())
+    }
+
+    pub fn with_size_and_location(
+        device: &Device,
+        size: u64,
+        ty: BufferType,
+        location: MemoryLocation,
+    ) -> Result<Self> {
+        if size == 0 {
+            return Err(BufferCreationError::ZeroSize.into());
+        }
+        // Buffers the host can't write to are filled with transfers
+        let usage = if location.is_host_visible() {
+            ty.usage()
+        } else {
+            ty.usage() | vk::BufferUsageFlags::TRANSFER_DST
+        };
         let buffer_info = vk::BufferCreateInfo::builder()
             .size(size)
-            .usage(ty.usage())
+            .usage(usage)
             .sharing_mode(vk::SharingMode::EXCLUSIVE);
 
         let buffer = unsafe { device.device.create_buffer(&buffer_info, None)? };
         let buffer_memory_req = unsafe { device.device.get_buffer_memory_requirements(buffer) };
-        // TODO: Configurable somehow, abstracted away
-        let flags = vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT;
-        let buffer_memory_index =
-            mem::find_memory_type_index(&buffer_memory_req, &device.memory_properties, flags)
-                .ok_or(BufferCreationError::CouldNotFindMemoryIndex)?;
-
-        let allocate_info = vk::MemoryAllocateInfo {
-            allocation_size: buffer_memory_req.size,
-            memory_type_index: buffer_memory_index,
-            ..Default::default()
-        };
-        let memory = unsafe { device.device.allocate_memory(&allocate_info, None)? };
-        // TODO: offset at some point. Should I also bind this unconditionally?
-        unsafe { device.device.bind_buffer_memory(buffer, memory, 0)? };
-        let ptr = if flags.contains(vk::MemoryPropertyFlags::HOST_VISIBLE) {
-            unsafe {
-                let ptr = device.device.map_memory(
-                    memory,
-                    0,
-                    buffer_memory_req.size,
-                    vk::MemoryMapFlags::empty(),
-                )?;
-                Some(MemoryMappablePointer::from_raw_ptr(ptr))
-            }
-        } else {
-            None
+        let buffer_memory_index = location
+            .find_memory_type_index(&buffer_memory_req, &device.memory_properties)
+            .ok_or(BufferCreationError::CouldNotFindMemoryIndex)?;
+
+        let allocation = device.allocate(
+            &buffer_memory_req,
+            buffer_memory_index,
+            AllocationKind::Linear,
+        )?;
+        unsafe {
+            device
+                .device
+                .bind_buffer_memory(buffer, allocation.memory, allocation.offset)?
         };
 
         Ok(Self {
             buffer,
             size,
-            memory,
-            ptr,
+            ty,
+            location,
+            allocation,
+            state: Default::default(),
+            device: device.clone(),
         })
     }
 
+    // For accesses that happen outside of `ResourceTransitions`
+    pub fn assume_usage(&self, usage: ResourceUsage) {
+        self.state.lock().unwrap().assume(usage);
+    }
+
     pub fn from_data<T: Copy>(device: &Device, ty: BufferType, data: &[T]) -> Result<Self> {
         let size = std::mem::size_of_val(data) as u64;
         Self::from_data_with_size(device, ty, data, size)
@@ -106,8 +163,48 @@ impl Buffer {
         Ok(buffer)
     }
 
+    fn upload_commands(&self, device: &Device, context: &Context, staging_buffer: &Buffer) {
+        ResourceTransitions::default()
+            .with_buffer(staging_buffer, ResourceUsage::TransferSrc)
+            .with_buffer(self, ResourceUsage::TransferDst)
+            .record(device, context);
+        device.copy_buffer(context, staging_buffer, self);
+        self.ty
+            .consumers()
+            .iter()
+            .fold(ResourceTransitions::default(), |transitions, usage| {
+                transitions.with_buffer(self, *usage)
+            })
+            .record(device, context);
+    }
+
+    // Creates a `GpuOnly` buffer and fills it through a staging buffer. The returned staging
+    // buffer must be kept alive until the copy has finished executing.
+    pub fn from_data_with_staging<T: Copy>(
+        device: &Device,
+        context: &Context,
+        fence: &Fence,
+        ty: BufferType,
+        data: &[T],
+        record: bool,
+    ) -> Result<(Self, Buffer)> {
+        let size = std::mem::size_of_val(data) as u64;
+        let staging_buffer = Buffer::from_data(device, BufferType::Staging, data)?;
+        let buffer = Buffer::with_size_and_location(device, size, ty, MemoryLocation::GpuOnly)?;
+
+        if record {
+            context.record(device, &[], &[], fence, &[], |device, context| {
+                buffer.upload_commands(device, context, &staging_buffer);
+            })?;
+        } else {
+            buffer.upload_commands(device, context, &staging_buffer);
+        }
+
+        Ok((buffer, staging_buffer))
+    }
+
     pub fn copy_data<T: Copy>(&self, data: &[T]) -> Result<(), MemoryCopyError> {
-        if let Some(ptr) = &self.ptr {
+        if let Some(ptr) = self.allocation.ptr() {
             ptr.mem_copy(data);
             Ok(())
         } else {
@@ -115,8 +212,35 @@ impl Buffer {
         }
     }
 
-    pub unsafe fn clean(&self, device: &Device) {
-        device.device.destroy_buffer(self.buffer, None);
-        device.device.free_memory(self.memory, None);
+    pub fn read_bytes(&self) -> Result<Vec<u8>, MemoryCopyError> {
+        if let Some(ptr) = self.allocation.ptr() {
+            Ok(unsafe { ptr.read_bytes(self.size as usize) })
+        } else {
+            Err(MemoryCopyError::CannotTransferToCPU)
+        }
+    }
+}
+
+impl Retire for Buffer {
+    fn into_retired(self) -> RetiredResource {
+        take_fields!(let Buffer {
+            buffer,
+            size,
+            ty,
+            location,
+            allocation,
+            state,
+            device,
+        } = self);
+        RetiredResource::Buffer { buffer, allocation }
+    }
+}
+
+impl Drop for Buffer {
+    fn drop(&mut self) {
+        unsafe {
+            self.device.device.destroy_buffer(self.buffer, None);
+            self.device.free(&self.allocation);
+        }
     }
 }
@@ -1,9 +1,11 @@
 pub(crate) mod ash_image;
 pub(crate) mod buffer;
+pub(crate) mod readback;
 pub(crate) mod sampler;
 
 pub use {
-    ash_image::{Image, ImageLayout, ImageResolution, ImageType},
-    buffer::{Buffer, BufferType},
+    ash_image::{DepthFormat, Image, ImageCreationError, ImageLayout, ImageResolution, ImageType},
+    buffer::{Buffer, BufferCreationError, BufferType, MemoryCopyError},
+    readback::ImageReadbackError,
     sampler::{Sampler, SamplerFilter, SamplerWrapMode},
 };
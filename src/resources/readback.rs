@@ -0,0 +1,241 @@
+use crate::{
+    context::Context,
+    device::Device,
+    error::Result,
+    resources::{
+        ash_image::{Image, ImageLayout},
+        buffer::{Buffer, BufferType},
+    },
+    sync::{Fence, ResourceTransitions, ResourceUsage},
+};
+use ash::vk;
+use image::RgbaImage;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum ImageReadbackError {
+    #[error("cannot read back image with format {0:?}")]
+    UnsupportedFormat(vk::Format),
+    #[error("read back data does not match image dimensions")]
+    InvalidDimensions,
+    #[error("surface does not support reading back present images")]
+    TransferSrcUnsupported,
+    #[error("present image index {index} is out of range, the swapchain has {count} images")]
+    InvalidPresentIndex { index: u32, count: usize },
+    #[error("multisampled images have to be resolved before they are read back")]
+    Multisampled,
+}
+
+enum ChannelOrder {
+    Rgba,
+    Bgra,
+}
+
+impl ChannelOrder {
+    fn from_format(format: vk::Format) -> Result<Self, ImageReadbackError> {
+        match format {
+            vk::Format::R8G8B8A8_UNORM | vk::Format::R8G8B8A8_SRGB => Ok(ChannelOrder::Rgba),
+            vk::Format::B8G8R8A8_UNORM | vk::Format::B8G8R8A8_SRGB => Ok(ChannelOrder::Bgra),
+            _ => Err(ImageReadbackError::UnsupportedFormat(format)),
+        }
+    }
+}
+
+// Copies `image`, in `TRANSFER_SRC_OPTIMAL`, into `buffer` and makes it visible to the host
+fn record_copy(
+    device: &Device,
+    context: &Context,
+    image: vk::Image,
+    extent: vk::Extent2D,
+    buffer: &Buffer,
+) {
+    let buffer_copy_region = vk::BufferImageCopy::builder()
+        .image_subresource(
+            vk::ImageSubresourceLayers::builder()
+                .aspect_mask(vk::ImageAspectFlags::COLOR)
+                .layer_count(1)
+                .build(),
+        )
+        .image_extent(extent.into())
+        .build();
+    unsafe {
+        device.device.cmd_copy_image_to_buffer(
+            context.command_buffer,
+            image,
+            vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            buffer.buffer,
+            &[buffer_copy_region],
+        );
+    }
+
+    let to_host = vk::BufferMemoryBarrier {
+        src_access_mask: vk::AccessFlags::TRANSFER_WRITE,
+        dst_access_mask: vk::AccessFlags::HOST_READ,
+        src_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+        dst_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+        buffer: buffer.buffer,
+        size: vk::WHOLE_SIZE,
+        ..Default::default()
+    };
+    unsafe {
+        device.device.cmd_pipeline_barrier(
+            context.command_buffer,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::PipelineStageFlags::HOST,
+            vk::DependencyFlags::empty(),
+            &[],
+            &[to_host],
+            &[],
+        );
+    }
+}
+
+// Present images aren't tracked, they are moved out of `layout` for the copy and back afterwards
+fn record_present_copy(
+    device: &Device,
+    context: &Context,
+    image: vk::Image,
+    extent: vk::Extent2D,
+    layout: vk::ImageLayout,
+    buffer: &Buffer,
+) {
+    let subresource_range = vk::ImageSubresourceRange {
+        aspect_mask: vk::ImageAspectFlags::COLOR,
+        level_count: 1,
+        layer_count: 1,
+        ..Default::default()
+    };
+    let to_transfer_src = vk::ImageMemoryBarrier {
+        src_access_mask: vk::AccessFlags::MEMORY_WRITE,
+        dst_access_mask: vk::AccessFlags::TRANSFER_READ,
+        old_layout: layout,
+        new_layout: vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+        image,
+        subresource_range,
+        ..Default::default()
+    };
+    unsafe {
+        device.device.cmd_pipeline_barrier(
+            context.command_buffer,
+            vk::PipelineStageFlags::ALL_COMMANDS,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::DependencyFlags::empty(),
+            &[],
+            &[],
+            &[to_transfer_src],
+        );
+    }
+
+    record_copy(device, context, image, extent, buffer);
+
+    // Undefined contents can't be restored, leave the image in a usable layout instead
+    let restored_layout = if layout == vk::ImageLayout::UNDEFINED {
+        vk::ImageLayout::TRANSFER_SRC_OPTIMAL
+    } else {
+        layout
+    };
+    let to_original_layout = vk::ImageMemoryBarrier {
+        src_access_mask: vk::AccessFlags::TRANSFER_READ,
+        dst_access_mask: vk::AccessFlags::MEMORY_READ,
+        old_layout: vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+        new_layout: restored_layout,
+        image,
+        subresource_range,
+        ..Default::default()
+    };
+    unsafe {
+        device.device.cmd_pipeline_barrier(
+            context.command_buffer,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::PipelineStageFlags::ALL_COMMANDS,
+            vk::DependencyFlags::empty(),
+            &[],
+            &[],
+            &[to_original_layout],
+        );
+    }
+}
+
+// Copies a color image into host memory with the commands recorded by `record_copy`, and blocks
+// until the copy is done
+fn read_rgba(
+    device: &Device,
+    context: &Context,
+    fence: &Fence,
+    format: vk::Format,
+    extent: vk::Extent2D,
+    record_copy: impl FnOnce(&Device, &Context, &Buffer),
+) -> Result<RgbaImage> {
+    let channel_order = ChannelOrder::from_format(format)?;
+    let size = extent.width as u64 * extent.height as u64 * 4;
+    let buffer = Buffer::with_size(device, size, BufferType::Readback)?;
+
+    context.record(device, &[], &[], fence, &[], |device, context| {
+        record_copy(device, context, &buffer);
+    })?;
+    fence.wait(device)?;
+
+    let mut pixels = buffer.read_bytes()?;
+    drop(buffer);
+    if let ChannelOrder::Bgra = channel_order {
+        for pixel in pixels.chunks_exact_mut(4) {
+            pixel.swap(0, 2);
+        }
+    }
+
+    let image = RgbaImage::from_raw(extent.width, extent.height, pixels)
+        .ok_or(ImageReadbackError::InvalidDimensions)?;
+    Ok(image)
+}
+
+// The image is moved out of its tracked layout, and left tracked as a transfer source
+pub(crate) fn read_image(
+    device: &Device,
+    context: &Context,
+    fence: &Fence,
+    image: &Image,
+) -> Result<RgbaImage> {
+    if image.samples != vk::SampleCountFlags::TYPE_1 {
+        return Err(ImageReadbackError::Multisampled.into());
+    }
+    let extent = vk::Extent2D {
+        width: image.resolution.width,
+        height: image.resolution.height,
+    };
+    read_rgba(
+        device,
+        context,
+        fence,
+        image.format,
+        extent,
+        |device, context, buffer| {
+            ResourceTransitions::default()
+                .with_image(image, ResourceUsage::TransferSrc)
+                .record(device, context);
+            record_copy(device, context, image.image, extent, buffer);
+        },
+    )
+}
+
+// `layout` is the layout the present image is in when the copy is recorded, it is restored
+// afterwards
+pub(crate) fn read_present_image(
+    device: &Device,
+    context: &Context,
+    fence: &Fence,
+    image: vk::Image,
+    format: vk::Format,
+    extent: vk::Extent2D,
+    layout: ImageLayout,
+) -> Result<RgbaImage> {
+    read_rgba(
+        device,
+        context,
+        fence,
+        format,
+        extent,
+        |device, context, buffer| {
+            record_present_copy(device, context, image, extent, layout.into(), buffer);
+        },
+    )
+}
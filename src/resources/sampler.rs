@@ -1,5 +1,7 @@
-use crate::device::Device;
-use anyhow::Result;
+use crate::{
+    device::{take_fields, Device, Retire, RetiredResource},
+    error::Result,
+};
 use ash::vk;
 
 #[derive(Debug, Copy, Clone)]
@@ -38,6 +40,7 @@ impl From<SamplerWrapMode> for vk::SamplerAddressMode {
 
 pub struct Sampler {
     pub sampler: vk::Sampler,
+    device: Device,
 }
 
 impl Sampler {
@@ -55,10 +58,22 @@ impl Sampler {
 
         let sampler = unsafe { device.device.create_sampler(&sampler_info, None)? };
 
-        Ok(Self { sampler })
+        Ok(Self {
+            sampler,
+            device: device.clone(),
+        })
     }
+}
+
+impl Retire for Sampler {
+    fn into_retired(self) -> RetiredResource {
+        take_fields!(let Sampler { sampler, device } = self);
+        RetiredResource::Sampler(sampler)
+    }
+}
 
-    pub unsafe fn clean(&self, device: &Device) {
-        device.device.destroy_sampler(self.sampler, None);
+impl Drop for Sampler {
+    fn drop(&mut self) {
+        unsafe { self.device.device.destroy_sampler(self.sampler, None) };
     }
 }
@@ -1,4 +1,4 @@
-use crate::device::Device;
+use crate::shader::reflection::{ReflectionError, ShaderReflection};
 use crate::Shader;
 
 pub struct GraphicsProgram {
@@ -14,8 +14,10 @@ impl GraphicsProgram {
         }
     }
 
-    pub unsafe fn clean(&self, device: &Device) {
-        self.vertex_shader.clean(device);
-        self.fragment_shader.clean(device);
+    pub fn reflection(&self) -> Result<ShaderReflection, ReflectionError> {
+        ShaderReflection::merge(&[
+            self.vertex_shader.reflection()?,
+            self.fragment_shader.reflection()?,
+        ])
     }
 }
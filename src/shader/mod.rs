@@ -1,14 +1,17 @@
 pub mod graphics_program;
+pub mod reflection;
 
-use crate::device::Device;
-use anyhow::Result;
+use crate::{device::Device, error::Result};
 use ash::vk;
+use reflection::{ReflectionError, ShaderReflection};
 use std::{fs::File, path::Path};
 
-// TODO: We should get a lot of reflection data at compile-time
 // TODO: Type safery for shader kind (Vertex, Fragment, Compute)?
 pub struct Shader {
     pub module: vk::ShaderModule,
+    // Shaders using features reflection doesn't support still load, only reflecting them fails
+    reflection: Result<ShaderReflection, ReflectionError>,
+    device: Device,
 }
 
 impl Shader {
@@ -16,12 +19,23 @@ impl Shader {
         let path = path.as_ref();
         let mut spv_file = File::open(path)?;
         let code = ash::util::read_spv(&mut spv_file)?;
+        let reflection = ShaderReflection::new(&code);
         let shader_info = vk::ShaderModuleCreateInfo::builder().code(&code);
         let module = unsafe { device.device.create_shader_module(&shader_info, None)? };
-        Ok(Self { module })
+        Ok(Self {
+            module,
+            reflection,
+            device: device.clone(),
+        })
     }
 
-    pub unsafe fn clean(&self, device: &Device) {
-        device.device.destroy_shader_module(self.module, None);
+    pub fn reflection(&self) -> Result<&ShaderReflection, ReflectionError> {
+        self.reflection.as_ref().map_err(Clone::clone)
+    }
+}
+
+impl Drop for Shader {
+    fn drop(&mut self) {
+        unsafe { self.device.device.destroy_shader_module(self.module, None) };
     }
 }
@@ -0,0 +1,970 @@
+use crate::{
+    descriptors::{DescriptorSet, ShaderStage},
+    pipeline::graphics_pipeline::VertexInputData,
+    push_constant::PushConstant,
+};
+use ash::vk;
+use std::collections::HashMap;
+use thiserror::Error;
+
+const SPIRV_MAGIC: u32 = 0x0723_0203;
+const HEADER_WORDS: usize = 5;
+
+// Opcodes
+const OP_ENTRY_POINT: u32 = 15;
+const OP_TYPE_INT: u32 = 21;
+const OP_TYPE_FLOAT: u32 = 22;
+const OP_TYPE_VECTOR: u32 = 23;
+const OP_TYPE_MATRIX: u32 = 24;
+const OP_TYPE_IMAGE: u32 = 25;
+const OP_TYPE_SAMPLER: u32 = 26;
+const OP_TYPE_SAMPLED_IMAGE: u32 = 27;
+const OP_TYPE_ARRAY: u32 = 28;
+const OP_TYPE_RUNTIME_ARRAY: u32 = 29;
+const OP_TYPE_STRUCT: u32 = 30;
+const OP_TYPE_POINTER: u32 = 32;
+const OP_CONSTANT: u32 = 43;
+const OP_SPEC_CONSTANT: u32 = 50;
+const OP_VARIABLE: u32 = 59;
+const OP_DECORATE: u32 = 71;
+const OP_MEMBER_DECORATE: u32 = 72;
+const OP_TYPE_ACCELERATION_STRUCTURE: u32 = 5341;
+
+// Decorations
+const DECORATION_BUFFER_BLOCK: u32 = 3;
+const DECORATION_ARRAY_STRIDE: u32 = 6;
+const DECORATION_MATRIX_STRIDE: u32 = 7;
+const DECORATION_BUILT_IN: u32 = 11;
+const DECORATION_LOCATION: u32 = 30;
+const DECORATION_BINDING: u32 = 33;
+const DECORATION_DESCRIPTOR_SET: u32 = 34;
+const DECORATION_OFFSET: u32 = 35;
+
+// Storage classes
+const STORAGE_UNIFORM_CONSTANT: u32 = 0;
+const STORAGE_INPUT: u32 = 1;
+const STORAGE_UNIFORM: u32 = 2;
+const STORAGE_PUSH_CONSTANT: u32 = 9;
+const STORAGE_STORAGE_BUFFER: u32 = 12;
+
+// Image dimensions
+const DIM_BUFFER: u32 = 5;
+const DIM_SUBPASS_DATA: u32 = 6;
+
+#[derive(Debug, Clone, Error)]
+pub enum ReflectionError {
+    #[error("invalid SPIR-V: {0}")]
+    InvalidSpirv(&'static str),
+    #[error("binding {binding} of set {set} is declared with different types across stages")]
+    ConflictingBinding { set: u32, binding: u32 },
+    #[error("shader uses descriptor set {0}, but it was not provided")]
+    MissingDescriptorSet(u32),
+    #[error("shader uses binding {binding} of set {set}, but the set does not declare it")]
+    MissingBinding { set: u32, binding: u32 },
+    #[error("binding {binding} of set {set} is {found:?} but the shader expects {expected:?}")]
+    DescriptorTypeMismatch {
+        set: u32,
+        binding: u32,
+        expected: vk::DescriptorType,
+        found: vk::DescriptorType,
+    },
+    #[error(
+        "binding {binding} of set {set} has {found} descriptors but the shader expects {expected}"
+    )]
+    DescriptorCountMismatch {
+        set: u32,
+        binding: u32,
+        expected: u32,
+        found: u32,
+    },
+    #[error("binding {binding} of set {set} is not visible to all the stages that use it")]
+    DescriptorStageMismatch { set: u32, binding: u32 },
+    #[error("push constants cover {found} bytes but the shader expects {expected}")]
+    PushConstantMismatch { expected: u32, found: u32 },
+    #[error("shader reads vertex input location {0}, but no attribute provides it")]
+    MissingVertexInput(u32),
+    #[error("vertex inputs of 8 and 16-bit types can't be reflected")]
+    UnsupportedVertexInputType,
+    #[error("array length {0} is not a constant or a specialization constant")]
+    UnsupportedArrayLength(u32),
+    #[error("stages {0:?} can't be described by a single `ShaderStage`")]
+    UnsupportedShaderStages(vk::ShaderStageFlags),
+    #[error("vertex input location {location} is {found:?} but the shader expects {expected:?}")]
+    VertexFormatMismatch {
+        location: u32,
+        expected: vk::Format,
+        found: vk::Format,
+    },
+}
+
+#[derive(Debug, Clone)]
+pub struct EntryPoint {
+    pub name: String,
+    pub stage: vk::ShaderStageFlags,
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct DescriptorBinding {
+    pub set: u32,
+    pub binding: u32,
+    pub ty: vk::DescriptorType,
+    // 0 for runtime-sized arrays
+    pub count: u32,
+    pub stage: vk::ShaderStageFlags,
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct VertexInput {
+    pub location: u32,
+    pub format: vk::Format,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ShaderReflection {
+    pub entry_points: Vec<EntryPoint>,
+    pub descriptor_bindings: Vec<DescriptorBinding>,
+    pub push_constant_size: Option<u32>,
+    pub push_constant_stage: vk::ShaderStageFlags,
+    pub vertex_inputs: Vec<VertexInput>,
+}
+
+#[derive(Debug, Copy, Clone)]
+enum Type {
+    Int { width: u32, signed: bool },
+    Float { width: u32 },
+    Vector { component: u32, count: u32 },
+    Matrix { column: u32, count: u32 },
+    Image { dim: u32, sampled: u32 },
+    Sampler,
+    SampledImage { image: u32 },
+    Array { element: u32, length: u32 },
+    RuntimeArray { element: u32 },
+    Struct,
+    Pointer { pointee: u32 },
+    AccelerationStructure,
+}
+
+#[derive(Debug, Default)]
+struct Decorations {
+    set: Option<u32>,
+    binding: Option<u32>,
+    location: Option<u32>,
+    array_stride: Option<u32>,
+    buffer_block: bool,
+    built_in: bool,
+}
+
+#[derive(Debug, Default)]
+struct MemberDecorations {
+    offset: Option<u32>,
+    matrix_stride: Option<u32>,
+}
+
+#[derive(Default)]
+struct Module {
+    entry_points: Vec<(EntryPoint, Vec<u32>)>,
+    types: HashMap<u32, Type>,
+    struct_members: HashMap<u32, Vec<u32>>,
+    constants: HashMap<u32, u32>,
+    variables: Vec<(u32, u32, u32)>,
+    decorations: HashMap<u32, Decorations>,
+    member_decorations: HashMap<(u32, u32), MemberDecorations>,
+}
+
+fn execution_model_stage(model: u32) -> vk::ShaderStageFlags {
+    match model {
+        0 => vk::ShaderStageFlags::VERTEX,
+        1 => vk::ShaderStageFlags::TESSELLATION_CONTROL,
+        2 => vk::ShaderStageFlags::TESSELLATION_EVALUATION,
+        3 => vk::ShaderStageFlags::GEOMETRY,
+        4 => vk::ShaderStageFlags::FRAGMENT,
+        5 => vk::ShaderStageFlags::COMPUTE,
+        _ => vk::ShaderStageFlags::empty(),
+    }
+}
+
+// Literal strings are nul-terminated and packed four bytes per word
+fn parse_string(words: &[u32]) -> (String, usize) {
+    let mut bytes = Vec::new();
+    for (index, word) in words.iter().enumerate() {
+        for byte in word.to_le_bytes() {
+            if byte == 0 {
+                return (String::from_utf8_lossy(&bytes).into_owned(), index + 1);
+            }
+            bytes.push(byte);
+        }
+    }
+    (String::from_utf8_lossy(&bytes).into_owned(), words.len())
+}
+
+impl Module {
+    fn parse(code: &[u32]) -> Result<Self, ReflectionError> {
+        if code.len() < HEADER_WORDS || code[0] != SPIRV_MAGIC {
+            return Err(ReflectionError::InvalidSpirv("missing SPIR-V header"));
+        }
+
+        let mut module = Module::default();
+        let mut cursor = HEADER_WORDS;
+        while cursor < code.len() {
+            let opcode = code[cursor] & 0xffff;
+            let word_count = (code[cursor] >> 16) as usize;
+            if word_count == 0 || cursor + word_count > code.len() {
+                return Err(ReflectionError::InvalidSpirv("truncated instruction"));
+            }
+            let operands = &code[cursor + 1..cursor + word_count];
+            module.parse_instruction(opcode, operands)?;
+            cursor += word_count;
+        }
+        Ok(module)
+    }
+
+    fn parse_instruction(&mut self, opcode: u32, operands: &[u32]) -> Result<(), ReflectionError> {
+        let operand = |index: usize| {
+            operands
+                .get(index)
+                .copied()
+                .ok_or(ReflectionError::InvalidSpirv("missing operand"))
+        };
+        match opcode {
+            OP_ENTRY_POINT => {
+                let (name, name_words) = parse_string(&operands[2.min(operands.len())..]);
+                let interface = operands[(2 + name_words).min(operands.len())..].to_vec();
+                let entry_point = EntryPoint {
+                    name,
+                    stage: execution_model_stage(operand(0)?),
+                };
+                self.entry_points.push((entry_point, interface));
+            }
+            OP_TYPE_INT => {
+                let ty = Type::Int {
+                    width: operand(1)?,
+                    signed: operand(2)? != 0,
+                };
+                self.types.insert(operand(0)?, ty);
+            }
+            OP_TYPE_FLOAT => {
+                let ty = Type::Float { width: operand(1)? };
+                self.types.insert(operand(0)?, ty);
+            }
+            OP_TYPE_VECTOR => {
+                let ty = Type::Vector {
+                    component: operand(1)?,
+                    count: operand(2)?,
+                };
+                self.types.insert(operand(0)?, ty);
+            }
+            OP_TYPE_MATRIX => {
+                let ty = Type::Matrix {
+                    column: operand(1)?,
+                    count: operand(2)?,
+                };
+                self.types.insert(operand(0)?, ty);
+            }
+            OP_TYPE_IMAGE => {
+                let ty = Type::Image {
+                    dim: operand(2)?,
+                    sampled: operand(6)?,
+                };
+                self.types.insert(operand(0)?, ty);
+            }
+            OP_TYPE_SAMPLER => {
+                self.types.insert(operand(0)?, Type::Sampler);
+            }
+            OP_TYPE_SAMPLED_IMAGE => {
+                let ty = Type::SampledImage { image: operand(1)? };
+                self.types.insert(operand(0)?, ty);
+            }
+            OP_TYPE_ARRAY => {
+                let ty = Type::Array {
+                    element: operand(1)?,
+                    length: operand(2)?,
+                };
+                self.types.insert(operand(0)?, ty);
+            }
+            OP_TYPE_RUNTIME_ARRAY => {
+                let ty = Type::RuntimeArray {
+                    element: operand(1)?,
+                };
+                self.types.insert(operand(0)?, ty);
+            }
+            OP_TYPE_STRUCT => {
+                let id = operand(0)?;
+                self.types.insert(id, Type::Struct);
+                self.struct_members.insert(id, operands[1..].to_vec());
+            }
+            OP_TYPE_POINTER => {
+                let ty = Type::Pointer {
+                    pointee: operand(2)?,
+                };
+                self.types.insert(operand(0)?, ty);
+            }
+            OP_TYPE_ACCELERATION_STRUCTURE => {
+                self.types.insert(operand(0)?, Type::AccelerationStructure);
+            }
+            // Only the low word matters, constants are only used for array lengths. Pipelines are
+            // created without specialization, so spec constants keep their default value.
+            OP_CONSTANT | OP_SPEC_CONSTANT => {
+                self.constants.insert(operand(1)?, operand(2)?);
+            }
+            OP_VARIABLE => {
+                self.variables.push((operand(1)?, operand(0)?, operand(2)?));
+            }
+            OP_DECORATE => {
+                let decorations = self.decorations.entry(operand(0)?).or_default();
+                match operand(1)? {
+                    DECORATION_BUFFER_BLOCK => decorations.buffer_block = true,
+                    DECORATION_BUILT_IN => decorations.built_in = true,
+                    DECORATION_ARRAY_STRIDE => decorations.array_stride = Some(operand(2)?),
+                    DECORATION_LOCATION => decorations.location = Some(operand(2)?),
+                    DECORATION_BINDING => decorations.binding = Some(operand(2)?),
+                    DECORATION_DESCRIPTOR_SET => decorations.set = Some(operand(2)?),
+                    _ => {}
+                }
+            }
+            OP_MEMBER_DECORATE => {
+                let decorations = self
+                    .member_decorations
+                    .entry((operand(0)?, operand(1)?))
+                    .or_default();
+                match operand(2)? {
+                    DECORATION_OFFSET => decorations.offset = Some(operand(3)?),
+                    DECORATION_MATRIX_STRIDE => decorations.matrix_stride = Some(operand(3)?),
+                    _ => {}
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn ty(&self, id: u32) -> Result<Type, ReflectionError> {
+        self.types
+            .get(&id)
+            .copied()
+            .ok_or(ReflectionError::InvalidSpirv("reference to unknown type"))
+    }
+
+    // Lengths computed with `OpSpecConstantOp` aren't evaluated
+    fn array_length(&self, id: u32) -> Result<u32, ReflectionError> {
+        self.constants
+            .get(&id)
+            .copied()
+            .ok_or(ReflectionError::UnsupportedArrayLength(id))
+    }
+
+    fn decorations(&self, id: u32) -> Option<&Decorations> {
+        self.decorations.get(&id)
+    }
+
+    fn size_of(&self, id: u32) -> Result<u32, ReflectionError> {
+        let size = match self.ty(id)? {
+            Type::Int { width, .. } | Type::Float { width } => width / 8,
+            Type::Vector { component, count } => self.size_of(component)? * count,
+            Type::Matrix { column, count } => self.size_of(column)? * count,
+            Type::Array { element, length } => {
+                let length = self.array_length(length)?;
+                let stride = match self.decorations(id).and_then(|d| d.array_stride) {
+                    Some(stride) => stride,
+                    None => self.size_of(element)?,
+                };
+                stride * length
+            }
+            Type::Struct => {
+                let members = self.struct_members.get(&id).cloned().unwrap_or_default();
+                let mut size = 0;
+                for (index, member) in members.iter().enumerate() {
+                    let decorations = self.member_decorations.get(&(id, index as u32));
+                    let offset = decorations.and_then(|d| d.offset).unwrap_or(size);
+                    let member_size = match (self.ty(*member)?, decorations) {
+                        (
+                            Type::Matrix { count, .. },
+                            Some(MemberDecorations {
+                                matrix_stride: Some(stride),
+                                ..
+                            }),
+                        ) => stride * count,
+                        _ => self.size_of(*member)?,
+                    };
+                    size = size.max(offset + member_size);
+                }
+                size
+            }
+            _ => 0,
+        };
+        Ok(size)
+    }
+
+    fn descriptor_type(
+        &self,
+        type_id: u32,
+        storage: u32,
+    ) -> Result<Option<vk::DescriptorType>, ReflectionError> {
+        let ty = match self.ty(type_id)? {
+            Type::Struct => {
+                let buffer_block = self
+                    .decorations(type_id)
+                    .is_some_and(|decorations| decorations.buffer_block);
+                if storage == STORAGE_STORAGE_BUFFER || buffer_block {
+                    vk::DescriptorType::STORAGE_BUFFER
+                } else {
+                    vk::DescriptorType::UNIFORM_BUFFER
+                }
+            }
+            Type::SampledImage { image } => match self.ty(image)? {
+                Type::Image {
+                    dim: DIM_BUFFER, ..
+                } => vk::DescriptorType::UNIFORM_TEXEL_BUFFER,
+                _ => vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            },
+            Type::Image { dim, sampled } => match (dim, sampled) {
+                (DIM_SUBPASS_DATA, _) => vk::DescriptorType::INPUT_ATTACHMENT,
+                (DIM_BUFFER, 2) => vk::DescriptorType::STORAGE_TEXEL_BUFFER,
+                (DIM_BUFFER, _) => vk::DescriptorType::UNIFORM_TEXEL_BUFFER,
+                (_, 2) => vk::DescriptorType::STORAGE_IMAGE,
+                _ => vk::DescriptorType::SAMPLED_IMAGE,
+            },
+            Type::Sampler => vk::DescriptorType::SAMPLER,
+            Type::AccelerationStructure => vk::DescriptorType::ACCELERATION_STRUCTURE_KHR,
+            _ => return Ok(None),
+        };
+        Ok(Some(ty))
+    }
+
+    // Formats of each location the type occupies, matrices take one location per column
+    fn vertex_formats(&self, type_id: u32) -> Result<Vec<vk::Format>, ReflectionError> {
+        let formats = match self.ty(type_id)? {
+            Type::Matrix { column, count } => {
+                let column_format = self.vertex_formats(column)?;
+                (0..count).flat_map(|_| column_format.clone()).collect()
+            }
+            Type::Array { element, length } => {
+                let length = self.array_length(length)?;
+                let element_formats = self.vertex_formats(element)?;
+                (0..length).flat_map(|_| element_formats.clone()).collect()
+            }
+            Type::Vector { component, count } => {
+                vec![self.scalar_format(component, count)?]
+            }
+            _ => vec![self.scalar_format(type_id, 1)?],
+        };
+        Ok(formats)
+    }
+
+    fn scalar_format(&self, type_id: u32, count: u32) -> Result<vk::Format, ReflectionError> {
+        let formats = match self.ty(type_id)? {
+            Type::Float { width: 32 } => [
+                vk::Format::R32_SFLOAT,
+                vk::Format::R32G32_SFLOAT,
+                vk::Format::R32G32B32_SFLOAT,
+                vk::Format::R32G32B32A32_SFLOAT,
+            ],
+            Type::Float { width: 64 } => [
+                vk::Format::R64_SFLOAT,
+                vk::Format::R64G64_SFLOAT,
+                vk::Format::R64G64B64_SFLOAT,
+                vk::Format::R64G64B64A64_SFLOAT,
+            ],
+            Type::Int {
+                width: 32,
+                signed: true,
+            } => [
+                vk::Format::R32_SINT,
+                vk::Format::R32G32_SINT,
+                vk::Format::R32G32B32_SINT,
+                vk::Format::R32G32B32A32_SINT,
+            ],
+            Type::Int {
+                width: 32,
+                signed: false,
+            } => [
+                vk::Format::R32_UINT,
+                vk::Format::R32G32_UINT,
+                vk::Format::R32G32B32_UINT,
+                vk::Format::R32G32B32A32_UINT,
+            ],
+            _ => return Err(ReflectionError::UnsupportedVertexInputType),
+        };
+        count
+            .checked_sub(1)
+            .and_then(|index| formats.get(index as usize))
+            .copied()
+            .ok_or(ReflectionError::InvalidSpirv("invalid vector size"))
+    }
+}
+
+pub(crate) fn vertex_format_size(format: vk::Format) -> u32 {
+    match format {
+        vk::Format::R32_SFLOAT | vk::Format::R32_SINT | vk::Format::R32_UINT => 4,
+        vk::Format::R32G32_SFLOAT | vk::Format::R32G32_SINT | vk::Format::R32G32_UINT => 8,
+        vk::Format::R32G32B32_SFLOAT | vk::Format::R32G32B32_SINT | vk::Format::R32G32B32_UINT => {
+            12
+        }
+        vk::Format::R32G32B32A32_SFLOAT
+        | vk::Format::R32G32B32A32_SINT
+        | vk::Format::R32G32B32A32_UINT => 16,
+        vk::Format::R64_SFLOAT => 8,
+        vk::Format::R64G64_SFLOAT => 16,
+        vk::Format::R64G64B64_SFLOAT => 24,
+        vk::Format::R64G64B64A64_SFLOAT => 32,
+        _ => 0,
+    }
+}
+
+impl ShaderReflection {
+    pub fn new(code: &[u32]) -> Result<Self, ReflectionError> {
+        let module = Module::parse(code)?;
+
+        let stage = module
+            .entry_points
+            .iter()
+            .fold(vk::ShaderStageFlags::empty(), |stage, (entry_point, _)| {
+                stage | entry_point.stage
+            });
+
+        let mut reflection = ShaderReflection {
+            entry_points: module
+                .entry_points
+                .iter()
+                .map(|(entry_point, _)| entry_point.clone())
+                .collect(),
+            ..Default::default()
+        };
+
+        let vertex_interface = module
+            .entry_points
+            .iter()
+            .filter(|(entry_point, _)| entry_point.stage == vk::ShaderStageFlags::VERTEX)
+            .flat_map(|(_, interface)| interface.iter().copied())
+            .collect::<Vec<_>>();
+
+        for &(id, pointer_type, storage) in &module.variables {
+            let pointee = match module.ty(pointer_type)? {
+                Type::Pointer { pointee } => pointee,
+                _ => return Err(ReflectionError::InvalidSpirv("variable is not a pointer")),
+            };
+            let decorations = module.decorations(id);
+
+            match storage {
+                STORAGE_UNIFORM_CONSTANT | STORAGE_UNIFORM | STORAGE_STORAGE_BUFFER => {
+                    let (set, binding) = match decorations {
+                        Some(Decorations {
+                            set: Some(set),
+                            binding: Some(binding),
+                            ..
+                        }) => (*set, *binding),
+                        _ => continue,
+                    };
+                    let (type_id, count) = match module.ty(pointee)? {
+                        Type::Array { element, length } => (element, module.array_length(length)?),
+                        Type::RuntimeArray { element } => (element, 0),
+                        _ => (pointee, 1),
+                    };
+                    if let Some(ty) = module.descriptor_type(type_id, storage)? {
+                        reflection.descriptor_bindings.push(DescriptorBinding {
+                            set,
+                            binding,
+                            ty,
+                            count,
+                            stage,
+                        });
+                    }
+                }
+                STORAGE_PUSH_CONSTANT => {
+                    reflection.push_constant_size = Some(module.size_of(pointee)?);
+                    reflection.push_constant_stage = stage;
+                }
+                STORAGE_INPUT if vertex_interface.contains(&id) => {
+                    let location = match decorations {
+                        Some(Decorations {
+                            location: Some(location),
+                            built_in: false,
+                            ..
+                        }) => *location,
+                        _ => continue,
+                    };
+                    for (index, format) in module.vertex_formats(pointee)?.into_iter().enumerate() {
+                        reflection.vertex_inputs.push(VertexInput {
+                            location: location + index as u32,
+                            format,
+                        });
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        reflection
+            .descriptor_bindings
+            .sort_by_key(|binding| (binding.set, binding.binding));
+        reflection.vertex_inputs.sort_by_key(|input| input.location);
+        Ok(reflection)
+    }
+
+    // Combines the reflection data of all the stages of a program
+    pub fn merge(reflections: &[&ShaderReflection]) -> Result<Self, ReflectionError> {
+        let mut merged = ShaderReflection::default();
+        for reflection in reflections {
+            merged
+                .entry_points
+                .extend(reflection.entry_points.iter().cloned());
+            merged
+                .vertex_inputs
+                .extend(reflection.vertex_inputs.iter().copied());
+
+            if let Some(size) = reflection.push_constant_size {
+                merged.push_constant_size = Some(merged.push_constant_size.unwrap_or(0).max(size));
+                merged.push_constant_stage |= reflection.push_constant_stage;
+            }
+
+            for binding in &reflection.descriptor_bindings {
+                match merged
+                    .descriptor_bindings
+                    .iter_mut()
+                    .find(|b| b.set == binding.set && b.binding == binding.binding)
+                {
+                    Some(existing) => {
+                        if existing.ty != binding.ty || existing.count != binding.count {
+                            return Err(ReflectionError::ConflictingBinding {
+                                set: binding.set,
+                                binding: binding.binding,
+                            });
+                        }
+                        existing.stage |= binding.stage;
+                    }
+                    None => merged.descriptor_bindings.push(*binding),
+                }
+            }
+        }
+        merged
+            .descriptor_bindings
+            .sort_by_key(|binding| (binding.set, binding.binding));
+        merged.vertex_inputs.sort_by_key(|input| input.location);
+        Ok(merged)
+    }
+
+    pub fn descriptor_set_count(&self) -> u32 {
+        self.descriptor_bindings
+            .iter()
+            .map(|binding| binding.set + 1)
+            .max()
+            .unwrap_or(0)
+    }
+
+    pub fn bindings_for_set(&self, set: u32) -> impl Iterator<Item = &DescriptorBinding> {
+        self.descriptor_bindings
+            .iter()
+            .filter(move |binding| binding.set == set)
+    }
+
+    pub fn push_constants(&self) -> Result<Vec<PushConstant>, ReflectionError> {
+        self.push_constant_size
+            .map(|size| {
+                Ok(PushConstant {
+                    stage: ShaderStage::try_from(self.push_constant_stage)?,
+                    offset: 0,
+                    size,
+                })
+            })
+            .into_iter()
+            .collect()
+    }
+
+    // Assumes a single vertex buffer with tightly packed attributes, in location order
+    pub fn vertex_input_data(&self) -> VertexInputData {
+        let mut offset = 0;
+        let attributes = self
+            .vertex_inputs
+            .iter()
+            .map(|input| {
+                let attribute = vk::VertexInputAttributeDescription {
+                    location: input.location,
+                    binding: 0,
+                    format: input.format,
+                    offset,
+                };
+                offset += vertex_format_size(input.format);
+                attribute
+            })
+            .collect::<Vec<_>>();
+        let bindings = if attributes.is_empty() {
+            vec![]
+        } else {
+            vec![vk::VertexInputBindingDescription {
+                binding: 0,
+                stride: offset,
+                input_rate: vk::VertexInputRate::VERTEX,
+            }]
+        };
+        VertexInputData {
+            bindings,
+            attributes,
+        }
+    }
+
+    pub fn validate_descriptor_sets(
+        &self,
+        descriptor_sets: &[&DescriptorSet],
+    ) -> Result<(), ReflectionError> {
+        for expected in &self.descriptor_bindings {
+            let set = descriptor_sets
+                .get(expected.set as usize)
+                .ok_or(ReflectionError::MissingDescriptorSet(expected.set))?;
+            let found =
+                set.layout_binding(expected.binding)
+                    .ok_or(ReflectionError::MissingBinding {
+                        set: expected.set,
+                        binding: expected.binding,
+                    })?;
+            if found.descriptor_type != expected.ty {
+                return Err(ReflectionError::DescriptorTypeMismatch {
+                    set: expected.set,
+                    binding: expected.binding,
+                    expected: expected.ty,
+                    found: found.descriptor_type,
+                });
+            }
+            // Runtime-sized arrays accept any count
+            if expected.count != 0 && found.descriptor_count < expected.count {
+                return Err(ReflectionError::DescriptorCountMismatch {
+                    set: expected.set,
+                    binding: expected.binding,
+                    expected: expected.count,
+                    found: found.descriptor_count,
+                });
+            }
+            if !found.stage_flags.contains(expected.stage) {
+                return Err(ReflectionError::DescriptorStageMismatch {
+                    set: expected.set,
+                    binding: expected.binding,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    pub fn validate_push_constants(
+        &self,
+        push_constants: &[&PushConstant],
+    ) -> Result<(), ReflectionError> {
+        let expected = self.push_constant_size.unwrap_or(0);
+        let found = push_constants
+            .iter()
+            .map(|pc| pc.offset + pc.size)
+            .max()
+            .unwrap_or(0);
+        if found < expected {
+            return Err(ReflectionError::PushConstantMismatch { expected, found });
+        }
+        Ok(())
+    }
+
+    pub fn validate_vertex_input(
+        &self,
+        vertex_input: &VertexInputData,
+    ) -> Result<(), ReflectionError> {
+        for input in &self.vertex_inputs {
+            let attribute = vertex_input
+                .attributes
+                .iter()
+                .find(|attribute| attribute.location == input.location)
+                .ok_or(ReflectionError::MissingVertexInput(input.location))?;
+            if attribute.format != input.format {
+                return Err(ReflectionError::VertexFormatMismatch {
+                    location: input.location,
+                    expected: input.format,
+                    found: attribute.format,
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{fs::File, path::Path};
+
+    fn load(name: &str) -> Vec<u32> {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/golden/shaders")
+            .join(name);
+        ash::util::read_spv(&mut File::open(path).unwrap()).unwrap()
+    }
+
+    fn reflect(name: &str) -> ShaderReflection {
+        ShaderReflection::new(&load(name)).unwrap()
+    }
+
+    #[test]
+    fn storage_buffer_binding() {
+        let reflection = reflect("double.comp.spv");
+        assert_eq!(reflection.entry_points.len(), 1);
+        assert_eq!(reflection.entry_points[0].name, "main");
+        assert_eq!(
+            reflection.entry_points[0].stage,
+            vk::ShaderStageFlags::COMPUTE
+        );
+        assert_eq!(reflection.descriptor_bindings.len(), 1);
+        let binding = reflection.descriptor_bindings[0];
+        assert_eq!((binding.set, binding.binding), (0, 0));
+        assert_eq!(binding.ty, vk::DescriptorType::STORAGE_BUFFER);
+        assert_eq!(binding.count, 1);
+        assert_eq!(binding.stage, vk::ShaderStageFlags::COMPUTE);
+        assert!(reflection.push_constant_size.is_none());
+        assert!(reflection.vertex_inputs.is_empty());
+    }
+
+    #[test]
+    fn vertex_inputs_uniforms_and_push_constants() {
+        let reflection = reflect("mesh.vert.spv");
+        let inputs = reflection
+            .vertex_inputs
+            .iter()
+            .map(|input| (input.location, input.format))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            inputs,
+            [
+                (0, vk::Format::R32G32B32_SFLOAT),
+                (1, vk::Format::R32G32_SFLOAT)
+            ]
+        );
+        let vertex_input = reflection.vertex_input_data();
+        assert_eq!(vertex_input.bindings[0].stride, 20);
+        assert_eq!(vertex_input.attributes[1].offset, 12);
+
+        assert_eq!(reflection.descriptor_bindings.len(), 1);
+        let binding = reflection.descriptor_bindings[0];
+        assert_eq!((binding.set, binding.binding), (0, 0));
+        assert_eq!(binding.ty, vk::DescriptorType::UNIFORM_BUFFER);
+        assert_eq!(binding.stage, vk::ShaderStageFlags::VERTEX);
+
+        // mat4 + vec4
+        assert_eq!(reflection.push_constant_size, Some(80));
+        let push_constants = reflection.push_constants().unwrap();
+        assert_eq!(push_constants.len(), 1);
+        assert_eq!(push_constants[0].stage, ShaderStage::Vertex);
+        assert!(reflection.validate_push_constants(&[]).is_err());
+        assert!(reflection
+            .validate_push_constants(&[&push_constants[0]])
+            .is_ok());
+    }
+
+    #[test]
+    fn input_attachment_binding() {
+        let reflection = reflect("subpass_copy.frag.spv");
+        assert_eq!(
+            reflection.entry_points[0].stage,
+            vk::ShaderStageFlags::FRAGMENT
+        );
+        assert_eq!(reflection.descriptor_bindings.len(), 1);
+        let binding = reflection.descriptor_bindings[0];
+        assert_eq!((binding.set, binding.binding), (0, 0));
+        assert_eq!(binding.ty, vk::DescriptorType::INPUT_ATTACHMENT);
+    }
+
+    #[test]
+    fn merge_program_stages() {
+        let vertex = reflect("triangle.vert.spv");
+        let fragment = reflect("solid.frag.spv");
+        // Built-ins aren't vertex inputs
+        assert!(vertex.vertex_inputs.is_empty());
+        let merged = ShaderReflection::merge(&[&vertex, &fragment]).unwrap();
+        let stages = merged
+            .entry_points
+            .iter()
+            .map(|entry_point| entry_point.stage)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            stages,
+            [vk::ShaderStageFlags::VERTEX, vk::ShaderStageFlags::FRAGMENT]
+        );
+        assert!(merged.descriptor_bindings.is_empty());
+        assert_eq!(merged.descriptor_set_count(), 0);
+    }
+
+    #[test]
+    fn malformed_spirv() {
+        assert!(matches!(
+            ShaderReflection::new(&[]),
+            Err(ReflectionError::InvalidSpirv(_))
+        ));
+
+        let mut code = load("double.comp.spv");
+        code[0] = 0;
+        assert!(matches!(
+            ShaderReflection::new(&code),
+            Err(ReflectionError::InvalidSpirv(_))
+        ));
+
+        // The last instruction claims more words than there are left
+        let mut code = load("double.comp.spv");
+        code.push(5 << 16 | OP_DECORATE);
+        assert!(matches!(
+            ShaderReflection::new(&code),
+            Err(ReflectionError::InvalidSpirv(_))
+        ));
+    }
+
+    #[test]
+    fn zero_sized_vector_is_rejected() {
+        let mut module = Module::default();
+        module.types.insert(1, Type::Float { width: 32 });
+        assert!(module.scalar_format(1, 0).is_err());
+        assert_eq!(
+            module.scalar_format(1, 4).unwrap(),
+            vk::Format::R32G32B32A32_SFLOAT
+        );
+    }
+
+    #[test]
+    fn spec_constant_array_uses_default_length() {
+        let mut module = Module::default();
+        let instructions: [(u32, &[u32]); 5] = [
+            (OP_TYPE_INT, &[1, 32, 0]),
+            (OP_SPEC_CONSTANT, &[1, 2, 8]),
+            (OP_TYPE_FLOAT, &[3, 32]),
+            (OP_TYPE_ARRAY, &[4, 3, 2]),
+            // Length computed by an `OpSpecConstantOp`, which isn't parsed
+            (OP_TYPE_ARRAY, &[5, 3, 9]),
+        ];
+        for (opcode, operands) in instructions {
+            module.parse_instruction(opcode, operands).unwrap();
+        }
+        assert_eq!(module.size_of(4).unwrap(), 32);
+        assert_eq!(module.vertex_formats(4).unwrap().len(), 8);
+        assert!(matches!(
+            module.size_of(5),
+            Err(ReflectionError::UnsupportedArrayLength(9))
+        ));
+    }
+
+    #[test]
+    fn half_float_vertex_input_is_unsupported() {
+        let mut module = Module::default();
+        module.types.insert(1, Type::Float { width: 16 });
+        assert!(matches!(
+            module.vertex_formats(1),
+            Err(ReflectionError::UnsupportedVertexInputType)
+        ));
+    }
+
+    #[test]
+    fn shader_stage_masks() {
+        let graphics = vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT;
+        assert_eq!(
+            ShaderStage::try_from(graphics).unwrap(),
+            ShaderStage::AllGraphics
+        );
+        assert_eq!(
+            ShaderStage::try_from(vk::ShaderStageFlags::COMPUTE).unwrap(),
+            ShaderStage::Compute
+        );
+        assert!(ShaderStage::try_from(
+            vk::ShaderStageFlags::COMPUTE | vk::ShaderStageFlags::VERTEX
+        )
+        .is_err());
+        assert!(ShaderStage::try_from(vk::ShaderStageFlags::empty()).is_err());
+    }
+}
@@ -1,11 +1,11 @@
-use crate::{device::Device, entry::Entry};
-use anyhow::Result;
+use crate::{device::Device, entry::Entry, error::Result};
 use ash::vk;
 use raw_window_handle::HasRawWindowHandle;
 
 pub struct Surface {
     pub loader: ash::extensions::khr::Surface,
     pub raw: vk::SurfaceKHR,
+    _entry: Entry,
 }
 
 impl Surface {
@@ -15,11 +15,17 @@ impl Surface {
             ash_window::create_surface(&entry.entry, &entry.instance, window_handle, None)?
         };
 
-        Ok(Self { loader, raw })
+        Ok(Self {
+            loader,
+            raw,
+            _entry: entry.clone(),
+        })
     }
+}
 
-    pub unsafe fn clean(&self) {
-        self.loader.destroy_surface(self.raw, None);
+impl Drop for Surface {
+    fn drop(&mut self) {
+        unsafe { self.loader.destroy_surface(self.raw, None) };
     }
 }
 
@@ -1,12 +1,54 @@
 use crate::{
     device::Device,
     entry::Entry,
+    error::Result,
+    resources::{readback, ImageReadbackError},
     surface::{Surface, SurfaceData},
-    sync::Semaphore,
-    Context, Fence, Image, ImageType,
+    sync::{ResourceTransitions, ResourceUsage, Semaphore},
+    Context, Fence, Image, ImageLayout, ImageType,
 };
-use anyhow::Result;
 use ash::vk;
+use image::RgbaImage;
+
+// TODO: Let user choose flipped viewport
+pub(crate) fn flipped_viewport(resolution: vk::Extent2D) -> vk::Viewport {
+    vk::Viewport {
+        x: 0.0,
+        y: resolution.height as f32,
+        width: resolution.width as f32,
+        height: -(resolution.height as f32),
+        min_depth: 0.0,
+        max_depth: 1.0,
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SwapchainStatus {
+    Ok,
+    // The image can still be presented, but the swapchain should be recreated
+    Suboptimal,
+    OutOfDate,
+    SurfaceLost,
+}
+
+impl SwapchainStatus {
+    fn from_result(result: Result<bool, vk::Result>) -> Result<Self, vk::Result> {
+        match result {
+            Ok(false) => Ok(SwapchainStatus::Ok),
+            Ok(true) => Ok(SwapchainStatus::Suboptimal),
+            Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => Ok(SwapchainStatus::OutOfDate),
+            Err(vk::Result::ERROR_SURFACE_LOST_KHR) => Ok(SwapchainStatus::SurfaceLost),
+            Err(err) => Err(err),
+        }
+    }
+
+    pub fn needs_recreate(&self) -> bool {
+        matches!(
+            self,
+            SwapchainStatus::Suboptimal | SwapchainStatus::OutOfDate
+        )
+    }
+}
 
 pub struct Swapchain {
     pub surface: Surface,
@@ -17,11 +59,18 @@ pub struct Swapchain {
     pub present_image_views: Vec<vk::ImageView>,
     pub depth_image: Image,
     use_vsync: bool,
+    auto_recreate: bool,
+    // Bumped every time the swapchain is recreated, so dependent framebuffers can tell they are
+    // stale
+    generation: u64,
+    device: Device,
 }
 
 impl Swapchain {
+    // `old_swapchain` is retired but not destroyed, that is left to the caller once the new
+    // structures are in place. On failure, everything created so far is destroyed.
     fn create_swapchain_structures(
-        entry: &Entry,
+        loader: &ash::extensions::khr::Swapchain,
         device: &Device,
         surface: &Surface,
         old_swapchain: Option<vk::SwapchainKHR>,
@@ -30,7 +79,6 @@ impl Swapchain {
         use_vsync: bool,
     ) -> Result<(
         SurfaceData,
-        ash::extensions::khr::Swapchain,
         vk::SwapchainKHR,
         Vec<vk::Image>,
         Vec<vk::ImageView>,
@@ -64,15 +112,17 @@ impl Swapchain {
                 }
             })
             .unwrap_or(vk::PresentModeKHR::FIFO);
-        let loader = ash::extensions::khr::Swapchain::new(&entry.instance, &device.device);
 
+        // Allows present images to be read back for screenshots
+        let image_usage = vk::ImageUsageFlags::COLOR_ATTACHMENT
+            | (surface_data.capabilities.supported_usage_flags & vk::ImageUsageFlags::TRANSFER_SRC);
         let swapchain_create_info = vk::SwapchainCreateInfoKHR::builder()
             .surface(surface.raw)
             .min_image_count(surface_data.desired_image_count)
             .image_color_space(surface_data.format.color_space)
             .image_format(surface_data.format.format)
             .image_extent(surface_data.resolution)
-            .image_usage(vk::ImageUsageFlags::COLOR_ATTACHMENT)
+            .image_usage(image_usage)
             .image_sharing_mode(vk::SharingMode::EXCLUSIVE)
             .pre_transform(pre_transform)
             .composite_alpha(vk::CompositeAlphaFlagsKHR::OPAQUE)
@@ -86,43 +136,59 @@ impl Swapchain {
             });
 
         let swapchain = unsafe { loader.create_swapchain(&swapchain_create_info, None)? };
+        let destroy = |present_image_views: &[vk::ImageView]| unsafe {
+            for &image_view in present_image_views {
+                device.device.destroy_image_view(image_view, None);
+            }
+            loader.destroy_swapchain(swapchain, None);
+        };
 
-        let present_images = unsafe { loader.get_swapchain_images(swapchain)? };
-        let present_image_views: Vec<vk::ImageView> = present_images
-            .iter()
-            .map(|&image| {
-                let create_view_info = vk::ImageViewCreateInfo::builder()
-                    .view_type(vk::ImageViewType::TYPE_2D)
-                    .format(surface_data.format.format)
-                    .components(vk::ComponentMapping {
-                        r: vk::ComponentSwizzle::R,
-                        g: vk::ComponentSwizzle::G,
-                        b: vk::ComponentSwizzle::B,
-                        a: vk::ComponentSwizzle::A,
-                    })
-                    .subresource_range(vk::ImageSubresourceRange {
-                        aspect_mask: vk::ImageAspectFlags::COLOR,
-                        base_mip_level: 0,
-                        level_count: 1,
-                        base_array_layer: 0,
-                        layer_count: 1,
-                    })
-                    .image(image);
-                unsafe { device.device.create_image_view(&create_view_info, None) }
-            })
-            .collect::<Result<_, _>>()?;
-
-        if let Some(old_swapchain) = old_swapchain {
-            unsafe {
-                loader.destroy_swapchain(old_swapchain, None);
+        let present_images = match unsafe { loader.get_swapchain_images(swapchain) } {
+            Ok(present_images) => present_images,
+            Err(error) => {
+                destroy(&[]);
+                return Err(error.into());
+            }
+        };
+        let mut present_image_views = Vec::with_capacity(present_images.len());
+        for &image in &present_images {
+            let create_view_info = vk::ImageViewCreateInfo::builder()
+                .view_type(vk::ImageViewType::TYPE_2D)
+                .format(surface_data.format.format)
+                .components(vk::ComponentMapping {
+                    r: vk::ComponentSwizzle::R,
+                    g: vk::ComponentSwizzle::G,
+                    b: vk::ComponentSwizzle::B,
+                    a: vk::ComponentSwizzle::A,
+                })
+                .subresource_range(vk::ImageSubresourceRange {
+                    aspect_mask: vk::ImageAspectFlags::COLOR,
+                    base_mip_level: 0,
+                    level_count: 1,
+                    base_array_layer: 0,
+                    layer_count: 1,
+                })
+                .image(image);
+            match unsafe { device.device.create_image_view(&create_view_info, None) } {
+                Ok(image_view) => present_image_views.push(image_view),
+                Err(error) => {
+                    destroy(&present_image_views);
+                    return Err(error.into());
+                }
             }
         }
 
-        let depth_image = Image::new(&device, surface_data.resolution.into(), ImageType::Depth)?;
+        let depth_image =
+            match Image::new(&device, surface_data.resolution.into(), ImageType::Depth) {
+                Ok(depth_image) => depth_image,
+                Err(error) => {
+                    destroy(&present_image_views);
+                    return Err(error);
+                }
+            };
 
         Ok((
             surface_data,
-            loader,
             swapchain,
             present_images,
             present_image_views,
@@ -138,9 +204,10 @@ impl Swapchain {
         height: u32,
         use_vsync: bool,
     ) -> Result<Self> {
-        let (surface_data, loader, swapchain, present_images, present_image_views, depth_image) =
+        let loader = ash::extensions::khr::Swapchain::new(&entry.instance, &device.device);
+        let (surface_data, swapchain, present_images, present_image_views, depth_image) =
             Self::create_swapchain_structures(
-                entry, device, &surface, None, width, height, use_vsync,
+                &loader, device, &surface, None, width, height, use_vsync,
             )?;
         Ok(Self {
             surface,
@@ -151,9 +218,27 @@ impl Swapchain {
             present_image_views,
             depth_image,
             use_vsync,
+            auto_recreate: false,
+            generation: 0,
+            device: device.clone(),
         })
     }
 
+    // When enabled, `FrameManager` recreates the swapchain whenever it is out of date or
+    // suboptimal, and skips frames while the window is minimized. Render passes pick up the new
+    // images in `RenderPass::begin_with_swapchain`.
+    pub fn set_auto_recreate(&mut self, auto_recreate: bool) {
+        self.auto_recreate = auto_recreate;
+    }
+
+    pub fn auto_recreate(&self) -> bool {
+        self.auto_recreate
+    }
+
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
     pub fn transition_depth_image(
         &self,
         device: &Device,
@@ -161,53 +246,24 @@ impl Swapchain {
         fence: &Fence,
     ) -> Result<()> {
         context.record(&device, &[], &[], &fence, &[], |device, context| {
-            let layout_transition_barriers = vk::ImageMemoryBarrier::builder()
-                .image(self.depth_image.image)
-                .dst_access_mask(
-                    vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ
-                        | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
-                )
-                .new_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
-                .old_layout(vk::ImageLayout::UNDEFINED)
-                .subresource_range(
-                    vk::ImageSubresourceRange::builder()
-                        .aspect_mask(vk::ImageAspectFlags::DEPTH)
-                        .layer_count(1)
-                        .level_count(1)
-                        .build(),
-                )
-                .build();
-
-            unsafe {
-                device.device.cmd_pipeline_barrier(
-                    context.command_buffer,
-                    vk::PipelineStageFlags::BOTTOM_OF_PIPE,
-                    vk::PipelineStageFlags::LATE_FRAGMENT_TESTS,
-                    vk::DependencyFlags::empty(),
-                    &[],
-                    &[],
-                    &[layout_transition_barriers],
-                )
-            };
+            ResourceTransitions::default()
+                .with_image(&self.depth_image, ResourceUsage::DepthStencilAttachment)
+                .record(device, context);
         })?;
         Ok(())
     }
 
-    pub fn resize(
+    fn rebuild(
         &mut self,
-        entry: &Entry,
         device: &Device,
         context: &Context,
         fence: &Fence,
         width: u32,
         height: u32,
     ) -> Result<()> {
-        unsafe {
-            self.clean_images(device);
-        }
-        let (surface_data, loader, swapchain, present_images, present_image_views, depth_image) =
+        let (surface_data, swapchain, present_images, present_image_views, depth_image) =
             Self::create_swapchain_structures(
-                entry,
+                &self.loader,
                 device,
                 &self.surface,
                 Some(self.swapchain),
@@ -215,16 +271,55 @@ impl Swapchain {
                 height,
                 self.use_vsync,
             )?;
+        // The old depth image is dropped when replaced
+        unsafe {
+            self.destroy_image_views();
+            self.loader.destroy_swapchain(self.swapchain, None);
+        }
         self.surface_data = surface_data;
-        self.loader = loader;
         self.swapchain = swapchain;
         self.present_images = present_images;
         self.present_image_views = present_image_views;
         self.depth_image = depth_image;
+        self.generation += 1;
         self.transition_depth_image(&device, &context, &fence)?;
         Ok(())
     }
 
+    pub fn resize(
+        &mut self,
+        entry: &Entry,
+        device: &Device,
+        context: &Context,
+        fence: &Fence,
+        width: u32,
+        height: u32,
+    ) -> Result<()> {
+        self.loader = ash::extensions::khr::Swapchain::new(&entry.instance, &device.device);
+        self.rebuild(device, context, fence, width, height)
+    }
+
+    // Recreates the swapchain at the surface's current size. Returns `false` without doing
+    // anything while the surface has a zero extent, e.g. when the window is minimized.
+    pub fn recreate(&mut self, device: &Device, context: &Context, fence: &Fence) -> Result<bool> {
+        let capabilities = unsafe {
+            self.surface
+                .loader
+                .get_physical_device_surface_capabilities(device.p_device, self.surface.raw)?
+        };
+        let extent = match capabilities.current_extent.width {
+            // The surface size is set by the swapchain, keep the current one
+            std::u32::MAX => self.surface_data.resolution,
+            _ => capabilities.current_extent,
+        };
+        if extent.width == 0 || extent.height == 0 {
+            return Ok(false);
+        }
+        device.wait_idle()?;
+        self.rebuild(device, context, fence, extent.width, extent.height)?;
+        Ok(true)
+    }
+
     pub fn resolution(&self) -> vk::Extent2D {
         self.surface_data.resolution
     }
@@ -239,15 +334,7 @@ impl Swapchain {
 
     // TODO: Wrapper type
     pub fn viewport(&self) -> vk::Viewport {
-        // TODO: Let user choose flipped viewport
-        vk::Viewport {
-            x: 0.0,
-            y: self.surface_data.resolution.height as f32,
-            width: self.surface_data.resolution.width as f32,
-            height: -(self.surface_data.resolution.height as f32),
-            min_depth: 0.0,
-            max_depth: 1.0,
-        }
+        flipped_viewport(self.surface_data.resolution)
     }
 
     // TODO: Wrapper type
@@ -255,17 +342,57 @@ impl Swapchain {
         self.surface_data.resolution.into()
     }
 
-    pub fn acquire_next_image_index(&self, semaphore: &Semaphore) -> Result<u32> {
-        // TODO: Hanbdle the bool return
-        let (index, _) = unsafe {
+    pub fn read_present_image(
+        &self,
+        device: &Device,
+        context: &Context,
+        fence: &Fence,
+        present_index: u32,
+        layout: ImageLayout,
+    ) -> Result<RgbaImage> {
+        // Present images only have transfer source usage if the surface supports it
+        if !self
+            .surface_data
+            .capabilities
+            .supported_usage_flags
+            .contains(vk::ImageUsageFlags::TRANSFER_SRC)
+        {
+            return Err(ImageReadbackError::TransferSrcUnsupported.into());
+        }
+        let image = *self.present_images.get(present_index as usize).ok_or(
+            ImageReadbackError::InvalidPresentIndex {
+                index: present_index,
+                count: self.present_images.len(),
+            },
+        )?;
+        readback::read_present_image(
+            device,
+            context,
+            fence,
+            image,
+            self.surface_data.format.format,
+            self.surface_data.resolution,
+            layout,
+        )
+    }
+
+    // The index is `None` when no image could be acquired, i.e. the swapchain is out of date
+    // or the surface was lost
+    pub fn acquire_next_image_index(
+        &self,
+        semaphore: &Semaphore,
+    ) -> Result<(Option<u32>, SwapchainStatus)> {
+        let result = unsafe {
             self.loader.acquire_next_image(
                 self.swapchain,
                 std::u64::MAX,
                 semaphore.semaphore,
                 vk::Fence::null(),
-            )?
+            )
         };
-        Ok(index)
+        let index = result.ok().map(|(index, _)| index);
+        let status = SwapchainStatus::from_result(result.map(|(_, suboptimal)| suboptimal))?;
+        Ok((index, status))
     }
 
     pub fn present(
@@ -273,7 +400,7 @@ impl Swapchain {
         device: &Device,
         wait_semaphores: &[&Semaphore],
         image_indices: &[u32],
-    ) -> Result<()> {
+    ) -> Result<SwapchainStatus> {
         let wait_semaphores = wait_semaphores
             .iter()
             .map(|semaphore| semaphore.semaphore)
@@ -283,23 +410,26 @@ impl Swapchain {
             .swapchains(std::slice::from_ref(&self.swapchain))
             .image_indices(&image_indices);
 
-        unsafe {
+        let result = unsafe {
             self.loader
-                .queue_present(device.present_queue, &present_info)?;
-        }
-        Ok(())
+                .queue_present(device.graphics_queue.queue, &present_info)
+        };
+        Ok(SwapchainStatus::from_result(result)?)
     }
 
-    unsafe fn clean_images(&self, device: &Device) {
+    unsafe fn destroy_image_views(&self) {
         for image_view in &self.present_image_views {
-            device.device.destroy_image_view(*image_view, None);
+            self.device.device.destroy_image_view(*image_view, None);
         }
-        self.depth_image.clean(device);
     }
+}
 
-    pub unsafe fn clean(&self, device: &Device) {
-        self.clean_images(device);
-        self.loader.destroy_swapchain(self.swapchain, None);
-        self.surface.clean();
+// The surface is a field, so it is only destroyed after the swapchain
+impl Drop for Swapchain {
+    fn drop(&mut self) {
+        unsafe {
+            self.destroy_image_views();
+            self.loader.destroy_swapchain(self.swapchain, None);
+        }
     }
 }
@@ -1,9 +1,9 @@
-use crate::device::Device;
-use anyhow::Result;
+use crate::{device::Device, error::Result};
 use ash::vk;
 
 pub struct Fence {
     pub fence: vk::Fence,
+    device: Device,
 }
 
 impl Fence {
@@ -13,11 +13,17 @@ impl Fence {
 
         let fence = unsafe { device.device.create_fence(&fence_create_info, None)? };
 
-        Ok(Self { fence })
+        Ok(Self {
+            fence,
+            device: device.clone(),
+        })
     }
+}
 
-    pub unsafe fn clean(&self, device: &Device) {
-        device.device.destroy_fence(self.fence, None);
+impl Drop for Fence {
+    fn drop(&mut self) {
+        self.device.release_fence(self.fence);
+        unsafe { self.device.device.destroy_fence(self.fence, None) };
     }
 }
 
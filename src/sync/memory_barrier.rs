@@ -1,49 +1,292 @@
-use crate::resources::ash_image::{Image, ImageLayout};
+use crate::resources::{
+    ash_image::{Image, ImageLayout},
+    buffer::Buffer,
+};
 use ash::vk;
 
+// Access masks convert into `vk::AccessFlags`, barriers accept anything that does, so several
+// accesses can be combined by or-ing `vk::AccessFlags`
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum AccessMask {
+    None,
+    IndirectCommandRead,
+    IndexRead,
+    VertexAttributeRead,
+    UniformRead,
+    InputAttachmentRead,
+    ShaderRead,
+    ShaderWrite,
+    ColorAttachmentRead,
+    ColorAttachmentWrite,
+    DepthStencilAttachmentRead,
+    DepthStencilAttachmentWrite,
+    // Read and write
     DepthStencil,
+    TransferRead,
+    // Same as `TransferWrite`
     Transfer,
+    TransferWrite,
+    HostRead,
+    HostWrite,
+    MemoryRead,
+    MemoryWrite,
 }
 
 impl From<AccessMask> for vk::AccessFlags {
     fn from(mask: AccessMask) -> Self {
         match mask {
+            AccessMask::None => vk::AccessFlags::empty(),
+            AccessMask::IndirectCommandRead => vk::AccessFlags::INDIRECT_COMMAND_READ,
+            AccessMask::IndexRead => vk::AccessFlags::INDEX_READ,
+            AccessMask::VertexAttributeRead => vk::AccessFlags::VERTEX_ATTRIBUTE_READ,
+            AccessMask::UniformRead => vk::AccessFlags::UNIFORM_READ,
+            AccessMask::InputAttachmentRead => vk::AccessFlags::INPUT_ATTACHMENT_READ,
+            AccessMask::ShaderRead => vk::AccessFlags::SHADER_READ,
+            AccessMask::ShaderWrite => vk::AccessFlags::SHADER_WRITE,
+            AccessMask::ColorAttachmentRead => vk::AccessFlags::COLOR_ATTACHMENT_READ,
+            AccessMask::ColorAttachmentWrite => vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+            AccessMask::DepthStencilAttachmentRead => {
+                vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ
+            }
+            AccessMask::DepthStencilAttachmentWrite => {
+                vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE
+            }
             AccessMask::DepthStencil => {
                 vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ
                     | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE
             }
-            AccessMask::Transfer => vk::AccessFlags::TRANSFER_WRITE,
+            AccessMask::TransferRead => vk::AccessFlags::TRANSFER_READ,
+            AccessMask::Transfer | AccessMask::TransferWrite => vk::AccessFlags::TRANSFER_WRITE,
+            AccessMask::HostRead => vk::AccessFlags::HOST_READ,
+            AccessMask::HostWrite => vk::AccessFlags::HOST_WRITE,
+            AccessMask::MemoryRead => vk::AccessFlags::MEMORY_READ,
+            AccessMask::MemoryWrite => vk::AccessFlags::MEMORY_WRITE,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DependencyFlags {
+    None,
+    ByRegion,
+    DeviceGroup,
+    ViewLocal,
+}
+
+impl From<DependencyFlags> for vk::DependencyFlags {
+    fn from(flags: DependencyFlags) -> Self {
+        match flags {
+            DependencyFlags::None => vk::DependencyFlags::empty(),
+            DependencyFlags::ByRegion => vk::DependencyFlags::BY_REGION,
+            DependencyFlags::DeviceGroup => vk::DependencyFlags::DEVICE_GROUP,
+            DependencyFlags::ViewLocal => vk::DependencyFlags::VIEW_LOCAL,
         }
     }
 }
 
+// Covers every resource
+#[derive(Copy, Clone)]
+pub struct MemoryBarrier {
+    pub raw: vk::MemoryBarrier,
+}
+
+impl MemoryBarrier {
+    pub fn new(
+        src_access_mask: impl Into<vk::AccessFlags>,
+        dst_access_mask: impl Into<vk::AccessFlags>,
+    ) -> Self {
+        let raw = vk::MemoryBarrier::builder()
+            .src_access_mask(src_access_mask.into())
+            .dst_access_mask(dst_access_mask.into())
+            .build();
+
+        Self { raw }
+    }
+}
+
+#[derive(Copy, Clone)]
+pub struct BufferMemoryBarrier {
+    pub raw: vk::BufferMemoryBarrier,
+}
+
+impl BufferMemoryBarrier {
+    // Covers the whole buffer, see `with_range`
+    pub fn new(
+        buffer: &Buffer,
+        src_access_mask: impl Into<vk::AccessFlags>,
+        dst_access_mask: impl Into<vk::AccessFlags>,
+    ) -> Self {
+        Self::from_raw(buffer.buffer, src_access_mask, dst_access_mask)
+    }
+
+    pub(crate) fn from_raw(
+        buffer: vk::Buffer,
+        src_access_mask: impl Into<vk::AccessFlags>,
+        dst_access_mask: impl Into<vk::AccessFlags>,
+    ) -> Self {
+        let raw = vk::BufferMemoryBarrier::builder()
+            .buffer(buffer)
+            .src_access_mask(src_access_mask.into())
+            .dst_access_mask(dst_access_mask.into())
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .offset(0)
+            .size(vk::WHOLE_SIZE)
+            .build();
+
+        Self { raw }
+    }
+
+    pub fn with_range(mut self, offset: u64, size: u64) -> Self {
+        self.raw.offset = offset;
+        self.raw.size = size;
+        self
+    }
+
+    // Releases or acquires the buffer for a queue family ownership transfer
+    pub fn with_queue_families(mut self, src_family: u32, dst_family: u32) -> Self {
+        self.raw.src_queue_family_index = src_family;
+        self.raw.dst_queue_family_index = dst_family;
+        self
+    }
+}
+
 #[derive(Copy, Clone)]
 pub struct ImageMemoryBarrier {
     pub raw: vk::ImageMemoryBarrier,
 }
 
 impl ImageMemoryBarrier {
+    // Covers every mip level and array layer of the image, with no source access. See the `with_`
+    // methods to narrow it down.
     pub fn new(
         image: &Image,
-        dst_access_mask: AccessMask,
+        dst_access_mask: impl Into<vk::AccessFlags>,
+        old_layout: ImageLayout,
+        new_layout: ImageLayout,
+    ) -> Self {
+        Self::from_raw(
+            image.image,
+            image.aspect_mask(),
+            dst_access_mask,
+            old_layout,
+            new_layout,
+        )
+    }
+
+    pub(crate) fn from_raw(
+        image: vk::Image,
+        aspect_mask: vk::ImageAspectFlags,
+        dst_access_mask: impl Into<vk::AccessFlags>,
         old_layout: ImageLayout,
         new_layout: ImageLayout,
     ) -> Self {
         let raw = vk::ImageMemoryBarrier::builder()
-            .image(image.image)
+            .image(image)
             .dst_access_mask(dst_access_mask.into())
             .old_layout(old_layout.into())
             .new_layout(new_layout.into())
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
             .subresource_range(
                 vk::ImageSubresourceRange::builder()
-                    .aspect_mask(image.ty.aspect_mask())
-                    .layer_count(1)
-                    .level_count(1)
+                    .aspect_mask(aspect_mask)
+                    .layer_count(vk::REMAINING_ARRAY_LAYERS)
+                    .level_count(vk::REMAINING_MIP_LEVELS)
                     .build(),
             )
             .build();
 
         Self { raw }
     }
+
+    pub fn with_src_access(mut self, src_access_mask: impl Into<vk::AccessFlags>) -> Self {
+        self.raw.src_access_mask = src_access_mask.into();
+        self
+    }
+
+    pub fn with_aspect_mask(mut self, aspect_mask: vk::ImageAspectFlags) -> Self {
+        self.raw.subresource_range.aspect_mask = aspect_mask;
+        self
+    }
+
+    // `count` can be `vk::REMAINING_MIP_LEVELS`
+    pub fn with_mip_levels(mut self, base: u32, count: u32) -> Self {
+        self.raw.subresource_range.base_mip_level = base;
+        self.raw.subresource_range.level_count = count;
+        self
+    }
+
+    // `count` can be `vk::REMAINING_ARRAY_LAYERS`
+    pub fn with_array_layers(mut self, base: u32, count: u32) -> Self {
+        self.raw.subresource_range.base_array_layer = base;
+        self.raw.subresource_range.layer_count = count;
+        self
+    }
+
+    // Releases or acquires the image for a queue family ownership transfer
+    pub fn with_queue_families(mut self, src_family: u32, dst_family: u32) -> Self {
+        self.raw.src_queue_family_index = src_family;
+        self.raw.dst_queue_family_index = dst_family;
+        self
+    }
+}
+
+// Any number of barriers between two sets of stages, recorded with a single
+// `cmd_pipeline_barrier` by `Device::pipeline_barrier`
+pub struct PipelineBarrier {
+    pub(crate) src_stage: vk::PipelineStageFlags,
+    pub(crate) dst_stage: vk::PipelineStageFlags,
+    pub(crate) dependency_flags: vk::DependencyFlags,
+    pub(crate) memory_barriers: Vec<vk::MemoryBarrier>,
+    pub(crate) buffer_barriers: Vec<vk::BufferMemoryBarrier>,
+    pub(crate) image_barriers: Vec<vk::ImageMemoryBarrier>,
+}
+
+impl PipelineBarrier {
+    pub fn new(
+        src_stage: impl Into<vk::PipelineStageFlags>,
+        dst_stage: impl Into<vk::PipelineStageFlags>,
+    ) -> Self {
+        Self {
+            src_stage: src_stage.into(),
+            dst_stage: dst_stage.into(),
+            dependency_flags: vk::DependencyFlags::empty(),
+            memory_barriers: Default::default(),
+            buffer_barriers: Default::default(),
+            image_barriers: Default::default(),
+        }
+    }
+
+    pub fn with_dependency_flags(mut self, flags: impl Into<vk::DependencyFlags>) -> Self {
+        self.dependency_flags |= flags.into();
+        self
+    }
+
+    pub fn with_memory_barrier(mut self, barrier: MemoryBarrier) -> Self {
+        self.memory_barriers.push(barrier.raw);
+        self
+    }
+
+    pub fn with_buffer_barrier(mut self, barrier: BufferMemoryBarrier) -> Self {
+        self.buffer_barriers.push(barrier.raw);
+        self
+    }
+
+    pub fn with_image_barrier(mut self, barrier: ImageMemoryBarrier) -> Self {
+        self.image_barriers.push(barrier.raw);
+        self
+    }
+
+    pub fn with_image_barriers(mut self, barriers: &[ImageMemoryBarrier]) -> Self {
+        self.image_barriers
+            .extend(barriers.iter().map(|barrier| barrier.raw));
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.memory_barriers.is_empty()
+            && self.buffer_barriers.is_empty()
+            && self.image_barriers.is_empty()
+    }
 }
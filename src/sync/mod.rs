@@ -1,9 +1,20 @@
 mod fence;
 mod memory_barrier;
+mod queue_transfer;
 mod semaphore;
+mod state;
+mod timeline_semaphore;
+
+pub(crate) use self::state::{ImageState, ResourceState, TrackedImage};
 
 pub use self::{
     fence::Fence,
-    memory_barrier::{AccessMask, ImageMemoryBarrier},
+    memory_barrier::{
+        AccessMask, BufferMemoryBarrier, DependencyFlags, ImageMemoryBarrier, MemoryBarrier,
+        PipelineBarrier,
+    },
+    queue_transfer::QueueOwnershipTransfer,
     semaphore::Semaphore,
+    state::{ResourceTransitions, ResourceUsage},
+    timeline_semaphore::{TimelineSemaphore, TimelineSemaphoreError, TimelineValue},
 };
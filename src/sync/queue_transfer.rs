@@ -0,0 +1,185 @@
+use crate::{
+    context::Context,
+    device::{Device, QueueType},
+    pipeline::PipelineStages,
+    resources::{
+        ash_image::{Image, ImageLayout},
+        buffer::Buffer,
+    },
+    sync::{
+        AccessMask, BufferMemoryBarrier, ImageMemoryBarrier, PipelineBarrier, ResourceUsage,
+        TrackedImage,
+    },
+};
+use ash::vk;
+
+#[derive(Clone)]
+enum TransferredResource {
+    Buffer(vk::Buffer),
+    Image {
+        image: TrackedImage,
+        usage: ResourceUsage,
+        old_layout: ImageLayout,
+        new_layout: ImageLayout,
+    },
+}
+
+// Moves a resource between queues of different families. `release` has to be recorded on a
+// context of `src_queue` and `acquire` on one of `dst_queue`, with the acquiring submission
+// waiting on a semaphore signaled by the releasing one. When both queues share a family,
+// `release` records a regular barrier and `acquire` does nothing.
+#[derive(Clone)]
+pub struct QueueOwnershipTransfer {
+    resource: TransferredResource,
+    src_queue: QueueType,
+    dst_queue: QueueType,
+    src_stage: vk::PipelineStageFlags,
+    src_access: vk::AccessFlags,
+    dst_stage: vk::PipelineStageFlags,
+    dst_access: vk::AccessFlags,
+}
+
+impl QueueOwnershipTransfer {
+    fn new(resource: TransferredResource, src_queue: QueueType, dst_queue: QueueType) -> Self {
+        Self {
+            resource,
+            src_queue,
+            dst_queue,
+            src_stage: PipelineStages::AllCommands.into(),
+            src_access: AccessMask::MemoryWrite.into(),
+            dst_stage: PipelineStages::AllCommands.into(),
+            dst_access: vk::AccessFlags::from(AccessMask::MemoryRead)
+                | vk::AccessFlags::from(AccessMask::MemoryWrite),
+        }
+    }
+
+    pub fn buffer(buffer: &Buffer, src_queue: QueueType, dst_queue: QueueType) -> Self {
+        Self::new(
+            TransferredResource::Buffer(buffer.buffer),
+            src_queue,
+            dst_queue,
+        )
+    }
+
+    // The image moves from the layout it is tracked in now to the layout of `usage` as part of
+    // the transfer, and is tracked as used by `usage` once `release` is recorded
+    pub fn image(
+        image: &Image,
+        src_queue: QueueType,
+        dst_queue: QueueType,
+        usage: ResourceUsage,
+    ) -> Self {
+        let old_layout = image.state.lock().unwrap().subresource(0, 0).layout();
+        let mut transfer = Self::new(
+            TransferredResource::Image {
+                image: TrackedImage::new(image),
+                usage,
+                old_layout,
+                new_layout: usage.layout().unwrap_or(old_layout),
+            },
+            src_queue,
+            dst_queue,
+        );
+        transfer.dst_stage = usage.stages();
+        transfer.dst_access = usage.access();
+        transfer
+    }
+
+    // How the source queue last used the resource
+    pub fn with_src(mut self, stage: PipelineStages, access: impl Into<vk::AccessFlags>) -> Self {
+        self.src_stage = stage.into();
+        self.src_access = access.into();
+        self
+    }
+
+    // How the destination queue is going to use the resource
+    pub fn with_dst(mut self, stage: PipelineStages, access: impl Into<vk::AccessFlags>) -> Self {
+        self.dst_stage = stage.into();
+        self.dst_access = access.into();
+        self
+    }
+
+    fn families(&self, device: &Device) -> (u32, u32) {
+        (
+            device.queue(self.src_queue).family_index,
+            device.queue(self.dst_queue).family_index,
+        )
+    }
+
+    pub fn release(&self, device: &Device, context: &Context) {
+        let (src_family, dst_family) = self.families(device);
+        if src_family == dst_family {
+            self.record(
+                device,
+                context,
+                (self.src_stage, self.dst_stage),
+                (self.src_access, self.dst_access),
+                (vk::QUEUE_FAMILY_IGNORED, vk::QUEUE_FAMILY_IGNORED),
+            );
+        } else {
+            self.record(
+                device,
+                context,
+                (self.src_stage, vk::PipelineStageFlags::BOTTOM_OF_PIPE),
+                (self.src_access, AccessMask::None.into()),
+                (src_family, dst_family),
+            );
+        }
+        if let TransferredResource::Image { image, usage, .. } = &self.resource {
+            let mut state = image.state.lock().unwrap();
+            for mip_level in 0..state.mip_levels() {
+                for array_layer in 0..state.array_layers() {
+                    state.subresource(mip_level, array_layer).assume(*usage);
+                }
+            }
+        }
+    }
+
+    pub fn acquire(&self, device: &Device, context: &Context) {
+        let (src_family, dst_family) = self.families(device);
+        if src_family != dst_family {
+            self.record(
+                device,
+                context,
+                (vk::PipelineStageFlags::TOP_OF_PIPE, self.dst_stage),
+                (AccessMask::None.into(), self.dst_access),
+                (src_family, dst_family),
+            );
+        }
+    }
+
+    fn record(
+        &self,
+        device: &Device,
+        context: &Context,
+        (src_stage, dst_stage): (vk::PipelineStageFlags, vk::PipelineStageFlags),
+        (src_access, dst_access): (vk::AccessFlags, vk::AccessFlags),
+        (src_family, dst_family): (u32, u32),
+    ) {
+        let barrier = PipelineBarrier::new(src_stage, dst_stage);
+        // Covers the whole resource, every mip level and array layer included
+        let barrier = match &self.resource {
+            TransferredResource::Buffer(buffer) => barrier.with_buffer_barrier(
+                BufferMemoryBarrier::from_raw(*buffer, src_access, dst_access)
+                    .with_queue_families(src_family, dst_family),
+            ),
+            TransferredResource::Image {
+                image,
+                old_layout,
+                new_layout,
+                ..
+            } => barrier.with_image_barrier(
+                ImageMemoryBarrier::from_raw(
+                    image.image,
+                    image.aspect_mask,
+                    dst_access,
+                    *old_layout,
+                    *new_layout,
+                )
+                .with_src_access(src_access)
+                .with_queue_families(src_family, dst_family),
+            ),
+        };
+        device.pipeline_barrier(context, &barrier);
+    }
+}
@@ -1,10 +1,9 @@
-use crate::device::Device;
-use anyhow::Result;
+use crate::{device::Device, error::Result};
 use ash::vk;
 
-#[derive(Copy, Clone)]
 pub struct Semaphore {
     pub semaphore: vk::Semaphore,
+    device: Device,
 }
 
 impl Semaphore {
@@ -13,10 +12,15 @@ impl Semaphore {
 
         let semaphore = unsafe { device.device.create_semaphore(&create_info, None)? };
 
-        Ok(Self { semaphore })
+        Ok(Self {
+            semaphore,
+            device: device.clone(),
+        })
     }
+}
 
-    pub unsafe fn clean(&self, device: &Device) {
-        device.device.destroy_semaphore(self.semaphore, None);
+impl Drop for Semaphore {
+    fn drop(&mut self) {
+        unsafe { self.device.device.destroy_semaphore(self.semaphore, None) };
     }
 }
@@ -0,0 +1,549 @@
+use crate::{
+    context::Context,
+    descriptors::ShaderStage,
+    device::Device,
+    resources::{
+        ash_image::{Image, ImageLayout},
+        buffer::Buffer,
+    },
+    sync::{BufferMemoryBarrier, ImageMemoryBarrier, PipelineBarrier},
+};
+use ash::vk;
+use std::{
+    ops::Range,
+    sync::{Arc, Mutex},
+};
+
+// How a command is going to use a resource. Layouts only apply to images.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ResourceUsage {
+    TransferSrc,
+    TransferDst,
+    Sampled(ShaderStage),
+    StorageRead(ShaderStage),
+    // Read and write
+    StorageWrite(ShaderStage),
+    UniformBuffer(ShaderStage),
+    VertexBuffer,
+    IndexBuffer,
+    IndirectBuffer,
+    ColorAttachment,
+    DepthStencilAttachment,
+    // Depth testing without writes, possibly also sampled from `ShaderStage`
+    DepthStencilReadOnly(ShaderStage),
+    InputAttachment,
+    Present,
+    HostRead,
+    HostWrite,
+}
+
+fn shader_stages(stage: ShaderStage) -> vk::PipelineStageFlags {
+    match stage {
+        ShaderStage::Vertex => vk::PipelineStageFlags::VERTEX_SHADER,
+        ShaderStage::Fragment => vk::PipelineStageFlags::FRAGMENT_SHADER,
+        ShaderStage::Compute => vk::PipelineStageFlags::COMPUTE_SHADER,
+        ShaderStage::AllGraphics => {
+            vk::PipelineStageFlags::VERTEX_SHADER
+                | vk::PipelineStageFlags::TESSELLATION_CONTROL_SHADER
+                | vk::PipelineStageFlags::TESSELLATION_EVALUATION_SHADER
+                | vk::PipelineStageFlags::GEOMETRY_SHADER
+                | vk::PipelineStageFlags::FRAGMENT_SHADER
+        }
+    }
+}
+
+impl ResourceUsage {
+    pub fn stages(&self) -> vk::PipelineStageFlags {
+        match self {
+            ResourceUsage::TransferSrc | ResourceUsage::TransferDst => {
+                vk::PipelineStageFlags::TRANSFER
+            }
+            ResourceUsage::Sampled(stage)
+            | ResourceUsage::StorageRead(stage)
+            | ResourceUsage::StorageWrite(stage)
+            | ResourceUsage::UniformBuffer(stage) => shader_stages(*stage),
+            ResourceUsage::VertexBuffer | ResourceUsage::IndexBuffer => {
+                vk::PipelineStageFlags::VERTEX_INPUT
+            }
+            ResourceUsage::IndirectBuffer => vk::PipelineStageFlags::DRAW_INDIRECT,
+            ResourceUsage::ColorAttachment => vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+            ResourceUsage::DepthStencilAttachment => {
+                vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS
+                    | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS
+            }
+            ResourceUsage::DepthStencilReadOnly(stage) => {
+                vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS
+                    | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS
+                    | shader_stages(*stage)
+            }
+            ResourceUsage::InputAttachment => vk::PipelineStageFlags::FRAGMENT_SHADER,
+            ResourceUsage::Present => vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+            ResourceUsage::HostRead | ResourceUsage::HostWrite => vk::PipelineStageFlags::HOST,
+        }
+    }
+
+    pub fn access(&self) -> vk::AccessFlags {
+        match self {
+            ResourceUsage::TransferSrc => vk::AccessFlags::TRANSFER_READ,
+            ResourceUsage::TransferDst => vk::AccessFlags::TRANSFER_WRITE,
+            ResourceUsage::Sampled(_) | ResourceUsage::StorageRead(_) => {
+                vk::AccessFlags::SHADER_READ
+            }
+            ResourceUsage::StorageWrite(_) => {
+                vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE
+            }
+            ResourceUsage::UniformBuffer(_) => vk::AccessFlags::UNIFORM_READ,
+            ResourceUsage::VertexBuffer => vk::AccessFlags::VERTEX_ATTRIBUTE_READ,
+            ResourceUsage::IndexBuffer => vk::AccessFlags::INDEX_READ,
+            ResourceUsage::IndirectBuffer => vk::AccessFlags::INDIRECT_COMMAND_READ,
+            ResourceUsage::ColorAttachment => {
+                vk::AccessFlags::COLOR_ATTACHMENT_READ | vk::AccessFlags::COLOR_ATTACHMENT_WRITE
+            }
+            ResourceUsage::DepthStencilAttachment => {
+                vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ
+                    | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE
+            }
+            ResourceUsage::DepthStencilReadOnly(_) => {
+                vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ | vk::AccessFlags::SHADER_READ
+            }
+            ResourceUsage::InputAttachment => vk::AccessFlags::INPUT_ATTACHMENT_READ,
+            ResourceUsage::Present => vk::AccessFlags::empty(),
+            ResourceUsage::HostRead => vk::AccessFlags::HOST_READ,
+            ResourceUsage::HostWrite => vk::AccessFlags::HOST_WRITE,
+        }
+    }
+
+    // `None` for usages that don't care about the layout
+    pub fn layout(&self) -> Option<ImageLayout> {
+        match self {
+            ResourceUsage::TransferSrc => Some(ImageLayout::TransferSrc),
+            ResourceUsage::TransferDst => Some(ImageLayout::TransferDest),
+            ResourceUsage::Sampled(_) => Some(ImageLayout::ShaderReadOnly),
+            ResourceUsage::StorageRead(_) | ResourceUsage::StorageWrite(_) => {
+                Some(ImageLayout::General)
+            }
+            ResourceUsage::ColorAttachment => Some(ImageLayout::ColorAttachment),
+            ResourceUsage::DepthStencilAttachment => Some(ImageLayout::DepthStencil),
+            ResourceUsage::DepthStencilReadOnly(_) => Some(ImageLayout::DepthStencilReadOnly),
+            ResourceUsage::InputAttachment => Some(ImageLayout::ShaderReadOnly),
+            ResourceUsage::Present => Some(ImageLayout::PresentSrc),
+            ResourceUsage::UniformBuffer(_)
+            | ResourceUsage::VertexBuffer
+            | ResourceUsage::IndexBuffer
+            | ResourceUsage::IndirectBuffer
+            | ResourceUsage::HostRead
+            | ResourceUsage::HostWrite => None,
+        }
+    }
+
+    pub fn is_write(&self) -> bool {
+        matches!(
+            self,
+            ResourceUsage::TransferDst
+                | ResourceUsage::StorageWrite(_)
+                | ResourceUsage::ColorAttachment
+                | ResourceUsage::DepthStencilAttachment
+                | ResourceUsage::HostWrite
+        )
+    }
+}
+
+// What has happened to a buffer or image subresource since its last write
+#[derive(Debug, Copy, Clone)]
+pub(crate) struct ResourceState {
+    layout: ImageLayout,
+    write_stages: vk::PipelineStageFlags,
+    write_access: vk::AccessFlags,
+    read_stages: vk::PipelineStageFlags,
+    read_access: vk::AccessFlags,
+}
+
+impl Default for ResourceState {
+    fn default() -> Self {
+        Self {
+            layout: ImageLayout::Undefined,
+            write_stages: vk::PipelineStageFlags::empty(),
+            write_access: vk::AccessFlags::empty(),
+            read_stages: vk::PipelineStageFlags::empty(),
+            read_access: vk::AccessFlags::empty(),
+        }
+    }
+}
+
+struct Transition {
+    src_stages: vk::PipelineStageFlags,
+    src_access: vk::AccessFlags,
+    old_layout: ImageLayout,
+    new_layout: ImageLayout,
+}
+
+impl ResourceState {
+    pub(crate) fn layout(&self) -> ImageLayout {
+        self.layout
+    }
+
+    // Sets the state as if `usage` just happened, without recording anything
+    pub(crate) fn assume(&mut self, usage: ResourceUsage) {
+        self.transition(usage, true);
+    }
+
+    // Like `assume`, but the resource ends up in `layout`, for transitions that happen outside
+    // of barriers like the final layouts of render pass attachments
+    pub(crate) fn assume_in_layout(&mut self, usage: ResourceUsage, layout: ImageLayout) {
+        self.assume(usage);
+        self.layout = layout;
+    }
+
+    // Moves to `usage`, returning what has to be waited on first, if anything
+    fn transition(&mut self, usage: ResourceUsage, track_layout: bool) -> Option<Transition> {
+        let (stages, access) = (usage.stages(), usage.access());
+        let new_layout = match usage.layout() {
+            Some(layout) if track_layout => layout,
+            _ => self.layout,
+        };
+        let old_layout = self.layout;
+        let layout_change = new_layout != old_layout;
+
+        if usage.is_write() || layout_change {
+            // Writes and layout transitions wait on every access since the last write, but only
+            // need to make the last write available
+            let src_stages = self.write_stages | self.read_stages;
+            let src_access = self.write_access;
+            let is_write = usage.is_write();
+            *self = Self {
+                layout: new_layout,
+                write_stages: stages,
+                write_access: if is_write {
+                    access
+                } else {
+                    vk::AccessFlags::empty()
+                },
+                read_stages: if is_write {
+                    vk::PipelineStageFlags::empty()
+                } else {
+                    stages
+                },
+                read_access: if is_write {
+                    vk::AccessFlags::empty()
+                } else {
+                    access
+                },
+            };
+            if src_stages.is_empty() && !layout_change {
+                None
+            } else {
+                Some(Transition {
+                    src_stages,
+                    src_access,
+                    old_layout,
+                    new_layout,
+                })
+            }
+        } else {
+            // Reads only wait on the last write, and only once per stage and access
+            let covered = self.read_stages.contains(stages) && self.read_access.contains(access);
+            self.read_stages |= stages;
+            self.read_access |= access;
+            if covered || self.write_stages.is_empty() {
+                None
+            } else {
+                Some(Transition {
+                    src_stages: self.write_stages,
+                    src_access: self.write_access,
+                    old_layout,
+                    new_layout,
+                })
+            }
+        }
+    }
+}
+
+// Tracked state of each mip level and array layer of an image
+#[derive(Debug, Clone)]
+pub(crate) struct ImageState {
+    array_layers: u32,
+    subresources: Vec<ResourceState>,
+}
+
+impl ImageState {
+    pub(crate) fn new(mip_levels: u32, array_layers: u32) -> Self {
+        Self {
+            array_layers,
+            subresources: vec![Default::default(); (mip_levels * array_layers) as usize],
+        }
+    }
+
+    pub(crate) fn mip_levels(&self) -> u32 {
+        self.subresources.len() as u32 / self.array_layers
+    }
+
+    pub(crate) fn array_layers(&self) -> u32 {
+        self.array_layers
+    }
+
+    pub(crate) fn subresource(&mut self, mip_level: u32, array_layer: u32) -> &mut ResourceState {
+        &mut self.subresources[(mip_level * self.array_layers + array_layer) as usize]
+    }
+
+    // Discards the contents of the image, which now reuses the memory of `previous`. Accesses
+    // through `previous` still have to finish before the first use of this image.
+    pub(crate) fn alias(&mut self, previous: &ImageState) {
+        let mut state = ResourceState::default();
+        for subresource in &previous.subresources {
+            state.write_stages |= subresource.write_stages;
+            state.write_access |= subresource.write_access;
+            state.read_stages |= subresource.read_stages;
+            state.read_access |= subresource.read_access;
+        }
+        self.subresources.fill(state);
+    }
+}
+
+// An image and its tracked state, kept by objects like render passes that record transitions for
+// images they don't borrow
+#[derive(Debug, Clone)]
+pub(crate) struct TrackedImage {
+    pub(crate) image: vk::Image,
+    pub(crate) aspect_mask: vk::ImageAspectFlags,
+    pub(crate) state: Arc<Mutex<ImageState>>,
+}
+
+impl TrackedImage {
+    pub(crate) fn new(image: &Image) -> Self {
+        Self {
+            image: image.image,
+            aspect_mask: image.aspect_mask(),
+            state: image.state.clone(),
+        }
+    }
+}
+
+enum TrackedResource<'a> {
+    Image {
+        image: TrackedImage,
+        mip_levels: Range<u32>,
+        array_layers: Range<u32>,
+    },
+    Buffer(&'a Buffer),
+}
+
+// Moves resources to their next usage, recording only the barriers that are needed in a single
+// `cmd_pipeline_barrier`. The tracked state is updated when recording, assuming contexts are
+// submitted in the order they were recorded in.
+#[derive(Default)]
+pub struct ResourceTransitions<'a> {
+    resources: Vec<(TrackedResource<'a>, ResourceUsage)>,
+}
+
+impl<'a> ResourceTransitions<'a> {
+    pub fn with_image(self, image: &'a Image, usage: ResourceUsage) -> Self {
+        let (mip_levels, array_layers) = image.subresource_counts();
+        self.with_image_subresources(image, usage, 0..mip_levels, 0..array_layers)
+    }
+
+    pub fn with_image_subresources(
+        mut self,
+        image: &'a Image,
+        usage: ResourceUsage,
+        mip_levels: Range<u32>,
+        array_layers: Range<u32>,
+    ) -> Self {
+        self.resources.push((
+            TrackedResource::Image {
+                image: TrackedImage::new(image),
+                mip_levels,
+                array_layers,
+            },
+            usage,
+        ));
+        self
+    }
+
+    pub(crate) fn with_tracked_image(mut self, image: &TrackedImage, usage: ResourceUsage) -> Self {
+        let (mip_levels, array_layers) = {
+            let state = image.state.lock().unwrap();
+            (state.mip_levels(), state.array_layers())
+        };
+        self.resources.push((
+            TrackedResource::Image {
+                image: image.clone(),
+                mip_levels: 0..mip_levels,
+                array_layers: 0..array_layers,
+            },
+            usage,
+        ));
+        self
+    }
+
+    pub fn with_buffer(mut self, buffer: &'a Buffer, usage: ResourceUsage) -> Self {
+        self.resources
+            .push((TrackedResource::Buffer(buffer), usage));
+        self
+    }
+
+    pub fn record(self, device: &Device, context: &Context) {
+        let mut src_stages = vk::PipelineStageFlags::empty();
+        let mut dst_stages = vk::PipelineStageFlags::empty();
+        let mut image_barriers = Vec::new();
+        let mut buffer_barriers = Vec::new();
+
+        for (resource, usage) in self.resources {
+            match resource {
+                TrackedResource::Image {
+                    image,
+                    mip_levels,
+                    array_layers,
+                } => {
+                    let mut state = image.state.lock().unwrap();
+                    for mip_level in mip_levels {
+                        for array_layer in array_layers.clone() {
+                            let subresource = state.subresource(mip_level, array_layer);
+                            if let Some(transition) = subresource.transition(usage, true) {
+                                src_stages |= transition.src_stages;
+                                dst_stages |= usage.stages();
+                                image_barriers.push(
+                                    ImageMemoryBarrier::from_raw(
+                                        image.image,
+                                        image.aspect_mask,
+                                        usage.access(),
+                                        transition.old_layout,
+                                        transition.new_layout,
+                                    )
+                                    .with_src_access(transition.src_access)
+                                    .with_mip_levels(mip_level, 1)
+                                    .with_array_layers(array_layer, 1),
+                                );
+                            }
+                        }
+                    }
+                }
+                TrackedResource::Buffer(buffer) => {
+                    let mut state = buffer.state.lock().unwrap();
+                    if let Some(transition) = state.transition(usage, false) {
+                        src_stages |= transition.src_stages;
+                        dst_stages |= usage.stages();
+                        buffer_barriers.push(BufferMemoryBarrier::new(
+                            buffer,
+                            transition.src_access,
+                            usage.access(),
+                        ));
+                    }
+                }
+            }
+        }
+
+        if image_barriers.is_empty() && buffer_barriers.is_empty() {
+            return;
+        }
+        if src_stages.is_empty() {
+            src_stages = vk::PipelineStageFlags::TOP_OF_PIPE;
+        }
+        let mut barrier = PipelineBarrier::new(src_stages, dst_stages);
+        for image_barrier in image_barriers {
+            barrier = barrier.with_image_barrier(image_barrier);
+        }
+        for buffer_barrier in buffer_barriers {
+            barrier = barrier.with_buffer_barrier(buffer_barrier);
+        }
+        device.pipeline_barrier(context, &barrier);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn written(usage: ResourceUsage) -> ResourceState {
+        let mut state = ResourceState::default();
+        state.assume(usage);
+        state
+    }
+
+    #[test]
+    fn read_waits_on_write() {
+        let mut state = written(ResourceUsage::TransferDst);
+        let transition = state
+            .transition(ResourceUsage::Sampled(ShaderStage::Fragment), false)
+            .unwrap();
+        assert_eq!(transition.src_stages, vk::PipelineStageFlags::TRANSFER);
+        assert_eq!(transition.src_access, vk::AccessFlags::TRANSFER_WRITE);
+        assert_eq!(transition.old_layout, transition.new_layout);
+    }
+
+    #[test]
+    fn read_in_same_stage_needs_no_barrier() {
+        let mut state = written(ResourceUsage::TransferDst);
+        let usage = ResourceUsage::Sampled(ShaderStage::Fragment);
+        assert!(state.transition(usage, true).is_some());
+        assert!(state.transition(usage, true).is_none());
+    }
+
+    #[test]
+    fn read_in_new_stage_waits_on_write_again() {
+        let mut state = written(ResourceUsage::TransferDst);
+        assert!(state
+            .transition(ResourceUsage::StorageRead(ShaderStage::Fragment), false)
+            .is_some());
+        let transition = state
+            .transition(ResourceUsage::StorageRead(ShaderStage::Compute), false)
+            .unwrap();
+        assert_eq!(transition.src_stages, vk::PipelineStageFlags::TRANSFER);
+        assert_eq!(transition.src_access, vk::AccessFlags::TRANSFER_WRITE);
+    }
+
+    #[test]
+    fn read_changing_layout_waits_on_earlier_reads() {
+        let mut state = written(ResourceUsage::TransferDst);
+        assert!(state
+            .transition(ResourceUsage::Sampled(ShaderStage::Fragment), true)
+            .is_some());
+        let transition = state.transition(ResourceUsage::TransferSrc, true).unwrap();
+        // The transfer write was already waited on by the move to `ShaderReadOnly`, only the
+        // sampling has to finish before the layout changes again
+        assert_eq!(
+            transition.src_stages,
+            vk::PipelineStageFlags::FRAGMENT_SHADER
+        );
+        assert_eq!(transition.src_access, vk::AccessFlags::empty());
+        assert_eq!(transition.old_layout, ImageLayout::ShaderReadOnly);
+        assert_eq!(transition.new_layout, ImageLayout::TransferSrc);
+        assert_eq!(state.layout(), ImageLayout::TransferSrc);
+
+        // Reading again in the new layout is covered by the transition
+        assert!(state.transition(ResourceUsage::TransferSrc, true).is_none());
+    }
+
+    #[test]
+    fn write_waits_on_reads_since_last_write() {
+        let mut state = written(ResourceUsage::ColorAttachment);
+        state.transition(ResourceUsage::StorageRead(ShaderStage::Compute), false);
+        state.transition(ResourceUsage::VertexBuffer, false);
+        let transition = state.transition(ResourceUsage::TransferDst, false).unwrap();
+        assert_eq!(
+            transition.src_stages,
+            vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+                | vk::PipelineStageFlags::COMPUTE_SHADER
+                | vk::PipelineStageFlags::VERTEX_INPUT
+        );
+        // Reads only need to have finished, only the last write has to be made available
+        assert_eq!(
+            transition.src_access,
+            ResourceUsage::ColorAttachment.access()
+        );
+    }
+
+    #[test]
+    fn assume_in_layout_keeps_the_write() {
+        let mut state = ResourceState::default();
+        state.assume_in_layout(ResourceUsage::ColorAttachment, ImageLayout::ShaderReadOnly);
+        assert_eq!(state.layout(), ImageLayout::ShaderReadOnly);
+        let transition = state
+            .transition(ResourceUsage::Sampled(ShaderStage::Fragment), true)
+            .unwrap();
+        assert_eq!(
+            transition.src_stages,
+            vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+        );
+        assert_eq!(transition.old_layout, transition.new_layout);
+    }
+}
@@ -0,0 +1,86 @@
+use crate::{device::Device, error::Result};
+use ash::vk;
+use std::time::Duration;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum TimelineSemaphoreError {
+    #[error("timeline semaphores are not enabled, see `DeviceFeatures::with_timeline_semaphores`")]
+    NotEnabled,
+}
+
+// A semaphore with a monotonically increasing 64-bit counter, that can be waited on and signaled
+// from both the host and queue submissions. Requires Vulkan 1.2, or `VK_KHR_timeline_semaphore`
+// on Vulkan 1.1.
+pub struct TimelineSemaphore {
+    pub semaphore: vk::Semaphore,
+    device: Device,
+}
+
+impl TimelineSemaphore {
+    pub fn new(device: &Device, initial_value: u64) -> Result<Self> {
+        if !device.enabled_features().timeline_semaphores() {
+            return Err(TimelineSemaphoreError::NotEnabled.into());
+        }
+        let mut type_create_info = vk::SemaphoreTypeCreateInfo::builder()
+            .semaphore_type(vk::SemaphoreType::TIMELINE)
+            .initial_value(initial_value);
+        let create_info = vk::SemaphoreCreateInfo::builder().push_next(&mut type_create_info);
+
+        let semaphore = unsafe { device.device.create_semaphore(&create_info, None)? };
+
+        Ok(Self {
+            semaphore,
+            device: device.clone(),
+        })
+    }
+
+    // `value` has to be greater than the current one
+    pub fn signal(&self, device: &Device, value: u64) -> Result<()> {
+        let signal_info = vk::SemaphoreSignalInfo::builder()
+            .semaphore(self.semaphore)
+            .value(value);
+        device.signal_semaphore(&signal_info)
+    }
+
+    // Waits until the counter reaches `value`. Returns false if `timeout` ran out first, `None`
+    // waits forever.
+    pub fn wait(&self, device: &Device, value: u64, timeout: Option<Duration>) -> Result<bool> {
+        let timeout = timeout.map_or(u64::MAX, |timeout| {
+            timeout.as_nanos().min(u64::MAX as u128) as u64
+        });
+        let wait_info = vk::SemaphoreWaitInfo::builder()
+            .semaphores(std::slice::from_ref(&self.semaphore))
+            .values(std::slice::from_ref(&value));
+        match device.wait_semaphores(&wait_info, timeout) {
+            Ok(()) => Ok(true),
+            Err(vk::Result::TIMEOUT) => Ok(false),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    pub fn value(&self, device: &Device) -> Result<u64> {
+        device.semaphore_counter_value(self.semaphore)
+    }
+
+    pub fn at(&self, value: u64) -> TimelineValue<'_> {
+        TimelineValue {
+            semaphore: self,
+            value,
+        }
+    }
+}
+
+impl Drop for TimelineSemaphore {
+    fn drop(&mut self) {
+        self.device.release_timeline(self.semaphore);
+        unsafe { self.device.device.destroy_semaphore(self.semaphore, None) };
+    }
+}
+
+// A point on a timeline, for submissions to wait on or signal
+#[derive(Copy, Clone)]
+pub struct TimelineValue<'a> {
+    pub semaphore: &'a TimelineSemaphore,
+    pub value: u64,
+}
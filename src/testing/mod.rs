@@ -0,0 +1,222 @@
+// Golden-image test harness. Renders offscreen on a headless device, so it also works on
+// software rasterizers like lavapipe (`VK_ICD_FILENAMES=<path to lvp_icd.json>`).
+// Set `EASY_ASH_BLESS=1` to write the rendered images as the new references.
+use crate::{
+    context::Context,
+    device::Device,
+    entry::{Entry, InstanceInfo},
+    error::Result,
+    render_pass::{ClearValue, RenderPass, RenderPassAttachment},
+    resources::{Image, ImageResolution, ImageType},
+    sync::Fence,
+    ApplicationInfo,
+};
+use image::{Rgba, RgbaImage};
+use math::vec::Vec4;
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+const BLESS_ENV_VAR: &str = "EASY_ASH_BLESS";
+
+#[derive(Debug, Error)]
+pub enum GoldenImageError {
+    #[error("reference image {0} does not exist, run with EASY_ASH_BLESS=1 to create it")]
+    MissingReference(PathBuf),
+    #[error("image is {actual:?} but reference is {expected:?}")]
+    DimensionMismatch {
+        expected: (u32, u32),
+        actual: (u32, u32),
+    },
+    #[error("{mismatched} pixels differ by more than {tolerance}, diff written to {diff_path}")]
+    Mismatch {
+        mismatched: usize,
+        tolerance: u8,
+        diff_path: PathBuf,
+    },
+}
+
+pub struct HeadlessRenderer {
+    pub entry: Entry,
+    pub device: Device,
+    pub context: Context,
+    pub fence: Fence,
+    pub color_image: Image,
+    pub depth_image: Image,
+    pub render_pass: RenderPass,
+}
+
+impl HeadlessRenderer {
+    pub fn new(width: u32, height: u32, clear_color: Vec4) -> Result<Self> {
+        // Validation layers are usually not installed on CI machines
+        let entry = Entry::new_headless(
+            ApplicationInfo::default(),
+            InstanceInfo::default().with_debug_layers(false),
+        )?;
+        let device = Device::new_headless(&entry)?;
+        let context = Context::new(&device)?;
+        let fence = Fence::new(&device)?;
+
+        let resolution = ImageResolution::from_width_height(width, height);
+        let color_image = Image::new(&device, resolution, ImageType::ColorTarget)?;
+        let depth_image = Image::new(&device, resolution, ImageType::DepthTarget)?;
+        let render_pass = RenderPass::new_offscreen(
+            &device,
+            &color_image,
+            &depth_image,
+            RenderPassAttachment::ColorClear,
+            RenderPassAttachment::DepthClear,
+            &[
+                ClearValue::Color(clear_color),
+                ClearValue::Depth {
+                    depth: 1.0,
+                    stencil: 0,
+                },
+            ],
+        )?;
+
+        Ok(Self {
+            entry,
+            device,
+            context,
+            fence,
+            color_image,
+            depth_image,
+            render_pass,
+        })
+    }
+
+    pub fn render<F>(&self, f: F) -> Result<RgbaImage>
+    where
+        F: FnOnce(&Device, &Context),
+    {
+        self.context.record(
+            &self.device,
+            &[],
+            &[],
+            &self.fence,
+            &[],
+            |device, context| {
+                self.render_pass.begin(device, context, 0);
+                device.set_render_pass_viewport_and_scissor(context, &self.render_pass);
+                f(device, context);
+                self.render_pass.end(device, context);
+            },
+        )?;
+        self.color_image
+            .read_rgba(&self.device, &self.context, &self.fence)
+    }
+}
+
+impl Drop for HeadlessRenderer {
+    fn drop(&mut self) {
+        self.device.wait_idle().ok();
+    }
+}
+
+pub struct GoldenImage {
+    path: PathBuf,
+    tolerance: u8,
+    max_mismatched_pixels: usize,
+    bless: bool,
+}
+
+impl GoldenImage {
+    pub fn new(path: impl AsRef<Path>) -> Self {
+        Self {
+            path: path.as_ref().to_path_buf(),
+            tolerance: 0,
+            max_mismatched_pixels: 0,
+            bless: std::env::var_os(BLESS_ENV_VAR).is_some(),
+        }
+    }
+
+    // Overrides `EASY_ASH_BLESS`, for tests of the harness itself
+    pub fn with_bless(mut self, bless: bool) -> Self {
+        self.bless = bless;
+        self
+    }
+
+    // Maximum per-channel difference for two pixels to be considered equal
+    pub fn with_tolerance(mut self, tolerance: u8) -> Self {
+        self.tolerance = tolerance;
+        self
+    }
+
+    pub fn with_max_mismatched_pixels(mut self, max_mismatched_pixels: usize) -> Self {
+        self.max_mismatched_pixels = max_mismatched_pixels;
+        self
+    }
+
+    fn sibling_path(&self, suffix: &str) -> PathBuf {
+        let stem = self
+            .path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        self.path.with_file_name(format!("{}.{}.png", stem, suffix))
+    }
+
+    pub fn diff_path(&self) -> PathBuf {
+        self.sibling_path("diff")
+    }
+
+    pub fn actual_path(&self) -> PathBuf {
+        self.sibling_path("actual")
+    }
+
+    pub fn compare(&self, actual: &RgbaImage) -> Result<()> {
+        if self.bless {
+            actual.save_with_format(&self.path, image::ImageFormat::Png)?;
+            return Ok(());
+        }
+        if !self.path.exists() {
+            return Err(GoldenImageError::MissingReference(self.path.clone()).into());
+        }
+        let expected = image::open(&self.path)?.into_rgba8();
+        if expected.dimensions() != actual.dimensions() {
+            return Err(GoldenImageError::DimensionMismatch {
+                expected: expected.dimensions(),
+                actual: actual.dimensions(),
+            }
+            .into());
+        }
+
+        let (mismatched, diff) = diff_images(&expected, actual, self.tolerance);
+        if mismatched > self.max_mismatched_pixels {
+            let diff_path = self.diff_path();
+            diff.save_with_format(&diff_path, image::ImageFormat::Png)?;
+            actual.save_with_format(self.actual_path(), image::ImageFormat::Png)?;
+            return Err(GoldenImageError::Mismatch {
+                mismatched,
+                tolerance: self.tolerance,
+                diff_path,
+            }
+            .into());
+        }
+        Ok(())
+    }
+}
+
+// Mismatched pixels are drawn in red over a faded grayscale version of the reference
+fn diff_images(expected: &RgbaImage, actual: &RgbaImage, tolerance: u8) -> (usize, RgbaImage) {
+    let mut mismatched = 0;
+    let diff = RgbaImage::from_fn(expected.width(), expected.height(), |x, y| {
+        let expected = expected.get_pixel(x, y);
+        let actual = actual.get_pixel(x, y);
+        let matches = expected
+            .0
+            .iter()
+            .zip(actual.0.iter())
+            .all(|(e, a)| e.abs_diff(*a) <= tolerance);
+        if matches {
+            let [r, g, b, _] = expected.0;
+            let luma = ((r as u32 + g as u32 + b as u32) / 3) as u8;
+            let faded = luma / 4 + 192;
+            Rgba([faded, faded, faded, 255])
+        } else {
+            mismatched += 1;
+            Rgba([255, 0, 0, 255])
+        }
+    });
+    (mismatched, diff)
+}
@@ -0,0 +1,377 @@
+use easy_ash::{
+    math::vec::Vec4,
+    new_descriptor_input_attachment_info,
+    testing::{GoldenImage, HeadlessRenderer},
+    ApplicationInfo, AttachmentDesc, AttachmentStoreOp, BindingDesc, Buffer, BufferType,
+    ClearValue, ComputePipeline, Context, CullMode, DescriptorBufferInfo, DescriptorInfo,
+    DescriptorPool, DescriptorSet, DescriptorType, Device, DeviceSelector, Entry, Fence, FrontFace,
+    GraphicsPipeline, GraphicsPipelineDesc, GraphicsProgram, Image, ImageResolution, ImageType,
+    InstanceInfo, MemoryLocation, RenderPass, RenderPassDesc, RenderingAttachment, RenderingDesc,
+    ResourceTransitions, ResourceUsage, Shader, ShaderStage, SubpassDesc, TimelineSemaphore,
+};
+use image::{Rgba, RgbaImage};
+use std::{
+    path::PathBuf,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Duration,
+};
+
+const WIDTH: u32 = 64;
+const HEIGHT: u32 = 64;
+
+fn golden_path(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("golden")
+        .join(name)
+}
+
+// Per process and call, so concurrent test runs don't share files
+fn unique_temp_dir(name: &str) -> PathBuf {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    let dir = std::env::temp_dir().join(format!(
+        "easy-ash-{}-{}-{}",
+        name,
+        std::process::id(),
+        COUNTER.fetch_add(1, Ordering::Relaxed)
+    ));
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn clear_color() -> Vec4 {
+    Vec4::new(0.0, 0.0, 1.0, 1.0)
+}
+
+#[test]
+fn clear_only() {
+    let renderer = HeadlessRenderer::new(WIDTH, HEIGHT, clear_color()).unwrap();
+    let image = renderer.render(|_device, _context| {}).unwrap();
+    GoldenImage::new(golden_path("clear.png"))
+        .compare(&image)
+        .unwrap();
+}
+
+// Only drawn with the right winding and viewport orientation, in the lower left corner
+#[test]
+fn triangle() {
+    let renderer = HeadlessRenderer::new(WIDTH, HEIGHT, clear_color()).unwrap();
+    let device = &renderer.device;
+    let program = GraphicsProgram::new(
+        Shader::new(device, golden_path("shaders/triangle.vert.spv")).unwrap(),
+        Shader::new(device, golden_path("shaders/solid.frag.spv")).unwrap(),
+    );
+    let pipeline = GraphicsPipeline::new(
+        device,
+        &renderer.render_pass,
+        &program,
+        None,
+        &[],
+        &[],
+        &GraphicsPipelineDesc::default()
+            .with_cull_mode(CullMode::Back)
+            .with_front_face(FrontFace::CounterClockwise),
+        None,
+    )
+    .unwrap();
+
+    let image = renderer
+        .render(|device, context| {
+            pipeline.bind(device, context);
+            device.draw(context, 0, 3);
+        })
+        .unwrap();
+    GoldenImage::new(golden_path("triangle.png"))
+        .with_tolerance(1)
+        .compare(&image)
+        .unwrap();
+}
+
+// Same triangle as `triangle`, drawn without a render pass
+#[test]
+fn dynamic_rendering_triangle() {
+    let entry = Entry::new_headless(
+        ApplicationInfo::default(),
+        InstanceInfo::default().with_debug_layers(false),
+    )
+    .unwrap();
+    let device = Device::from_selector(
+        &entry,
+        &DeviceSelector::default()
+            .with_required_features(Device::default_features().with_dynamic_rendering()),
+    )
+    .unwrap();
+    let context = Context::new(&device).unwrap();
+    let fence = Fence::new(&device).unwrap();
+    let resolution = ImageResolution::from_width_height(WIDTH, HEIGHT);
+    let color_image = Image::new(&device, resolution, ImageType::ColorTarget).unwrap();
+    let depth_image = Image::new(&device, resolution, ImageType::DepthTarget).unwrap();
+
+    let desc = RenderingDesc::default()
+        .with_color_attachment(
+            RenderingAttachment::new(&color_image).with_clear(ClearValue::Color(clear_color())),
+        )
+        .with_depth_attachment(RenderingAttachment::new(&depth_image).with_clear(
+            ClearValue::Depth {
+                depth: 1.0,
+                stencil: 0,
+            },
+        ));
+    let program = GraphicsProgram::new(
+        Shader::new(&device, golden_path("shaders/triangle.vert.spv")).unwrap(),
+        Shader::new(&device, golden_path("shaders/solid.frag.spv")).unwrap(),
+    );
+    let pipeline = GraphicsPipeline::with_rendering(
+        &device,
+        &desc.formats(),
+        &program,
+        None,
+        &[],
+        &[],
+        &GraphicsPipelineDesc::default()
+            .with_cull_mode(CullMode::Back)
+            .with_front_face(FrontFace::CounterClockwise),
+        None,
+    )
+    .unwrap();
+
+    context
+        .record(&device, &[], &[], &fence, &[], |device, context| {
+            context.begin_rendering(device, &desc).unwrap();
+            device.set_rendering_viewport_and_scissor(context, &desc);
+            pipeline.bind(device, context);
+            device.draw(context, 0, 3);
+            context.end_rendering(device, &desc);
+        })
+        .unwrap();
+    let image = color_image.read_rgba(&device, &context, &fence).unwrap();
+    device.wait_idle().unwrap();
+    GoldenImage::new(golden_path("triangle.png"))
+        .with_tolerance(1)
+        .compare(&image)
+        .unwrap();
+}
+
+// The triangle is drawn in the first subpass, and copied to the output through an input
+// attachment in the second one
+#[test]
+fn subpass_input_attachment_copy() {
+    let renderer = HeadlessRenderer::new(WIDTH, HEIGHT, clear_color()).unwrap();
+    let device = &renderer.device;
+    let resolution = ImageResolution::from_width_height(WIDTH, HEIGHT);
+    let gbuffer = Image::new(device, resolution, ImageType::ColorTarget).unwrap();
+    let output = Image::new(device, resolution, ImageType::ColorTarget).unwrap();
+
+    let desc = RenderPassDesc::default()
+        .with_color_attachment(
+            AttachmentDesc::new(gbuffer.format)
+                .with_clear(ClearValue::Color(clear_color()))
+                .with_store_op(AttachmentStoreOp::DontCare),
+        )
+        // Left red if the copy doesn't happen
+        .with_color_attachment(
+            AttachmentDesc::new(output.format)
+                .with_clear(ClearValue::Color(Vec4::new(1.0, 0.0, 0.0, 1.0))),
+        )
+        .with_subpass(SubpassDesc::default().with_color_attachment(0))
+        .with_subpass(
+            SubpassDesc::default()
+                .with_input_attachment(0)
+                .with_color_attachment(1),
+        );
+    let render_pass = RenderPass::from_desc(device, &desc, &[&gbuffer, &output], None).unwrap();
+
+    let pool = DescriptorPool::new(device).unwrap();
+    let mut set = DescriptorSet::new(
+        device,
+        &pool,
+        &[BindingDesc::new(
+            DescriptorType::InputAttachment,
+            1,
+            ShaderStage::Fragment,
+        )],
+    )
+    .unwrap();
+    set.bind(&[DescriptorInfo::InputAttachment(vec![
+        new_descriptor_input_attachment_info(&gbuffer),
+    ])])
+    .unwrap();
+    set.update(device);
+
+    let triangle = GraphicsPipeline::new(
+        device,
+        &render_pass,
+        &GraphicsProgram::new(
+            Shader::new(device, golden_path("shaders/triangle.vert.spv")).unwrap(),
+            Shader::new(device, golden_path("shaders/solid.frag.spv")).unwrap(),
+        ),
+        None,
+        &[],
+        &[],
+        &GraphicsPipelineDesc::default()
+            .with_cull_mode(CullMode::Back)
+            .with_front_face(FrontFace::CounterClockwise)
+            .with_subpass(0),
+        None,
+    )
+    .unwrap();
+    let copy = GraphicsPipeline::new(
+        device,
+        &render_pass,
+        &GraphicsProgram::new(
+            Shader::new(device, golden_path("shaders/fullscreen.vert.spv")).unwrap(),
+            Shader::new(device, golden_path("shaders/subpass_copy.frag.spv")).unwrap(),
+        ),
+        None,
+        &[&set],
+        &[],
+        &GraphicsPipelineDesc::default()
+            .with_cull_mode(CullMode::None)
+            .with_subpass(1),
+        None,
+    )
+    .unwrap();
+
+    renderer
+        .context
+        .record(device, &[], &[], &renderer.fence, &[], |device, context| {
+            render_pass.begin(device, context, 0);
+            device.set_render_pass_viewport_and_scissor(context, &render_pass);
+            triangle.bind(device, context);
+            device.draw(context, 0, 3);
+            context.next_subpass(device);
+            copy.bind(device, context);
+            copy.bind_descriptor_set(device, context, &set);
+            device.draw(context, 0, 3);
+            render_pass.end(device, context);
+        })
+        .unwrap();
+    let image = output
+        .read_rgba(device, &renderer.context, &renderer.fence)
+        .unwrap();
+    GoldenImage::new(golden_path("triangle.png"))
+        .with_tolerance(1)
+        .compare(&image)
+        .unwrap();
+}
+
+// Headless compute, no render pass involved
+#[test]
+fn compute_doubles_buffer() {
+    let renderer = HeadlessRenderer::new(WIDTH, HEIGHT, clear_color()).unwrap();
+    let device = &renderer.device;
+    let values = (0..64).collect::<Vec<u32>>();
+    // Read back on the host once the dispatch is done
+    let buffer = Buffer::with_size_and_location(
+        device,
+        std::mem::size_of_val(values.as_slice()) as u64,
+        BufferType::Storage,
+        MemoryLocation::GpuToCpu,
+    )
+    .unwrap();
+    buffer.copy_data(&values).unwrap();
+    buffer.assume_usage(ResourceUsage::HostWrite);
+
+    let pool = DescriptorPool::new(device).unwrap();
+    let mut set = DescriptorSet::new(
+        device,
+        &pool,
+        &[BindingDesc::new(
+            DescriptorType::StorageBuffer,
+            1,
+            ShaderStage::Compute,
+        )],
+    )
+    .unwrap();
+    set.bind(&[DescriptorInfo::StorageBuffer(DescriptorBufferInfo::new(
+        &buffer, None, None,
+    ))])
+    .unwrap();
+    set.update(device);
+
+    let shader = Shader::new(device, golden_path("shaders/double.comp.spv")).unwrap();
+    let pipeline = ComputePipeline::new(device, &shader, &[&set], &[], None).unwrap();
+    renderer
+        .context
+        .record(device, &[], &[], &renderer.fence, &[], |device, context| {
+            ResourceTransitions::default()
+                .with_buffer(&buffer, ResourceUsage::StorageWrite(ShaderStage::Compute))
+                .record(device, context);
+            pipeline.bind(device, context);
+            pipeline.bind_descriptor_set(device, context, &set);
+            device.dispatch(context, 1, 1, 1);
+            ResourceTransitions::default()
+                .with_buffer(&buffer, ResourceUsage::HostRead)
+                .record(device, context);
+        })
+        .unwrap();
+    renderer.fence.wait(device).unwrap();
+
+    let doubled = buffer
+        .read_bytes()
+        .unwrap()
+        .chunks_exact(4)
+        .map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        doubled,
+        values.iter().map(|value| value * 2).collect::<Vec<_>>()
+    );
+}
+
+// Host only, nothing is submitted
+#[test]
+fn timeline_semaphore_host_signal_and_wait() {
+    let entry = Entry::new_headless(
+        ApplicationInfo::default(),
+        InstanceInfo::default().with_debug_layers(false),
+    )
+    .unwrap();
+    let device = Device::from_selector(
+        &entry,
+        &DeviceSelector::default()
+            .with_required_features(Device::default_features().with_timeline_semaphores()),
+    )
+    .unwrap();
+
+    let timeline = TimelineSemaphore::new(&device, 1).unwrap();
+    assert_eq!(timeline.value(&device).unwrap(), 1);
+    assert!(timeline
+        .wait(&device, 1, Some(Duration::from_millis(0)))
+        .unwrap());
+
+    timeline.signal(&device, 3).unwrap();
+    assert_eq!(timeline.value(&device).unwrap(), 3);
+    assert!(timeline
+        .wait(&device, 2, Some(Duration::from_millis(0)))
+        .unwrap());
+    // Nothing is going to signal 4
+    assert!(!timeline
+        .wait(&device, 4, Some(Duration::from_millis(1)))
+        .unwrap());
+}
+
+#[test]
+fn mismatch_writes_diff_image() {
+    let dir = unique_temp_dir("mismatch");
+    let reference = RgbaImage::from_pixel(4, 4, Rgba([0, 0, 0, 255]));
+    reference.save(dir.join("mismatch.png")).unwrap();
+
+    let mut actual = reference.clone();
+    actual.put_pixel(1, 1, Rgba([10, 0, 0, 255]));
+    actual.put_pixel(2, 2, Rgba([2, 0, 0, 255]));
+
+    let golden = GoldenImage::new(dir.join("mismatch.png"))
+        .with_tolerance(2)
+        .with_bless(false);
+    assert!(golden.compare(&actual).is_err());
+    let diff = image::open(golden.diff_path()).unwrap().into_rgba8();
+    assert_eq!(*diff.get_pixel(1, 1), Rgba([255, 0, 0, 255]));
+    assert_ne!(*diff.get_pixel(2, 2), Rgba([255, 0, 0, 255]));
+
+    assert!(golden
+        .with_max_mismatched_pixels(1)
+        .compare(&actual)
+        .is_ok());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,20 @@
+#version 450
+
+layout(location = 0) in vec3 position;
+layout(location = 1) in vec2 uv;
+
+layout(location = 0) out vec2 out_uv;
+
+layout(set = 0, binding = 0) uniform Camera {
+    mat4 view_projection;
+};
+
+layout(push_constant) uniform Constants {
+    mat4 model;
+    vec4 tint;
+};
+
+void main() {
+    out_uv = uv;
+    gl_Position = view_projection * model * vec4(position, 1.0) * tint.w;
+}
@@ -0,0 +1,10 @@
+#version 450
+
+// Assembled by hand, naga can't compile subpass inputs
+layout(input_attachment_index = 0, set = 0, binding = 0) uniform subpassInput previous;
+
+layout(location = 0) out vec4 color;
+
+void main() {
+    color = subpassLoad(previous);
+}
@@ -0,0 +1,12 @@
+#version 450
+
+// Right triangle in the lower left corner, counter-clockwise with y up
+const vec2 positions[3] = vec2[3](
+    vec2(-0.75, -0.75),
+    vec2(0.5, -0.75),
+    vec2(-0.75, 0.25)
+);
+
+void main() {
+    gl_Position = vec4(positions[gl_VertexIndex], 0.0, 1.0);
+}